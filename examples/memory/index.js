@@ -17,14 +17,7 @@ function buttonPress(well) {
       count: 0,
     }
 
-    let memory;
-    
-    try {
-      memory = well.getData()
-    } catch (e) {
-      console.log('memory read error:', e)
-      memory = memoryTemplate
-    }
+    let memory = well.getData() ?? memoryTemplate
 
     if (!memory.buttonPresses) {
       console.log('did not detect button presses data, erasing memory and starting fresh')
@@ -1,8 +1,400 @@
 import test from 'ava'
 
-//import { sum } from '../index.js'
+import { Plinth, CardNotPresentError, Framebuffer, imageUtilities, imageHash, defaultLut } from '../index.js'
 
 test('sum from native', (t) => {
   t.pass()
   //t.is(sum(1, 2), 3)
 })
+
+test('simulated wells start out with a card in each', (t) => {
+  let plinth = new Plinth('simulator')
+
+  plinth.wells.forEach((well) => t.true(well.isOccupied()))
+})
+
+test('simulated card keeps its memory when moved to another well', (t) => {
+  let plinth = new Plinth('simulator')
+  plinth.wells[0].storeData({ hello: 'chukwudi' })
+
+  let memory = plinth.plinth.removeCard(0)
+  t.false(plinth.wells[0].isOccupied())
+  t.throws(() => plinth.wells[0].getData(), { instanceOf: CardNotPresentError, code: 'CardNotPresent' })
+
+  plinth.plinth.insertCard(2, memory)
+  t.deepEqual(plinth.wells[2].getData(), { hello: 'chukwudi' })
+})
+
+test('pressing a simulated button calls the callback for that well', async (t) => {
+  let plinth = new Plinth('simulator')
+  let pressed = new Promise((resolve) => plinth.wells[1].onBButtonPress(async () => resolve()))
+
+  plinth.plinth.pressButton(1, 'b')
+  plinth.plinth.releaseButton(1, 'b')
+
+  await pressed
+  t.pass()
+})
+
+test('image shown on a simulated well can be read back as a png', async (t) => {
+  let plinth = new Plinth('simulator')
+  await plinth.wells[3].displayImage(Buffer.alloc(128*296*2/8, 0))
+
+  let png = plinth.plinth.currentImagePng(3)
+  t.is(png.subarray(1, 4).toString(), 'PNG')
+})
+
+test('errors from the drivers say what went wrong in their code', async (t) => {
+  let plinth = new Plinth('simulator')
+
+  await t.throwsAsync(plinth.plinth.displayImage(0, Buffer.alloc(10)), { code: 'InvalidImage' })
+  t.throws(() => plinth.plinth.readMemory(7, 1), { code: 'InvalidWell' })
+  t.throws(() => plinth.plinth.readMemory(0, 5000), { code: 'MemoryOutOfRange' })
+
+  plinth.plinth.removeCard(0)
+  t.throws(() => plinth.plinth.readMemory(0, 1), { code: 'CardNotPresent' })
+})
+
+test('an empty well has the same error code whether the sdk or the drivers notice', async (t) => {
+  let plinth = new Plinth('simulator')
+  plinth.plinth.removeCard(1)
+
+  let fromSdk = t.throws(() => plinth.wells[1].getData())
+  let fromDrivers = t.throws(() => plinth.plinth.readMemory(1, 1))
+  t.is(fromSdk.code, 'CardNotPresent')
+  t.is(fromSdk.code, fromDrivers.code)
+  t.true(fromSdk instanceof Error)
+  t.is(fromSdk.well, 1)
+})
+
+test('displaying an image returns a promise which resolves once the card has updated', async (t) => {
+  let plinth = new Plinth('simulator')
+
+  let refresh = plinth.plinth.displayImage(2, Buffer.alloc(128*296*2/8, 0xff))
+  t.true(refresh instanceof Promise)
+  await refresh
+
+  let png = plinth.plinth.currentImagePng(2)
+  t.is(png.subarray(1, 4).toString(), 'PNG')
+})
+
+test('several wells can be given images at once', async (t) => {
+  let plinth = new Plinth('simulator')
+  let black = Buffer.alloc(128*296*2/8, 0)
+
+  await plinth.displayImages([{ well: 0, image: black }, { well: 2, image: black }])
+
+  t.deepEqual(plinth.plinth.currentImagePng(0), plinth.plinth.currentImagePng(2))
+  t.notDeepEqual(plinth.plinth.currentImagePng(0), plinth.plinth.currentImagePng(1))
+})
+
+test('a png read back from a card loads as the same image', async (t) => {
+  let plinth = new Plinth('simulator')
+  let stripes = Buffer.alloc(128*296*2/8, 0b00_01_10_11)
+  await plinth.wells[0].displayImage(stripes)
+
+  let image = await imageUtilities.loadImage(plinth.plinth.currentImagePng(0), { dither: 'none', gamma: 1, contrast: 1 })
+  t.deepEqual(image, stripes)
+})
+
+test('images which can\'t be decoded are rejected', async (t) => {
+  await t.throwsAsync(imageUtilities.loadImage(Buffer.from('not an image')), { code: 'InvalidImage' })
+})
+
+test('dithering keeps a mid gray looking mid gray', async (t) => {
+  let plinth = new Plinth('simulator')
+  // a flat gray halfway between two of the card's grays
+  await plinth.wells[0].displayImage(Buffer.alloc(128*296*2/8, 0b01_10_01_10))
+  let png = plinth.plinth.currentImagePng(0)
+
+  for (let dither of ['floyd-steinberg', 'atkinson', 'bayer', 'blue-noise']) {
+    let image = await imageUtilities.loadImage(png, { fit: 'stretch', width: 64, height: 64, dither, gamma: 1, contrast: 1 })
+    let levels = [...image].flatMap((byte) => [byte >> 6, (byte >> 4) & 0b11, (byte >> 2) & 0b11, byte & 0b11])
+    let mean = levels.reduce((sum, level) => sum + level, 0) / levels.length
+    t.true(Math.abs(mean - 1.5) < 0.1, `${dither} averaged ${mean}`)
+  }
+})
+
+test('a framebuffer can be drawn on and shown on a card', async (t) => {
+  let plinth = new Plinth('simulator')
+  let framebuffer = new Framebuffer()
+  framebuffer.drawRect(10, 10, 20, 20, { fill: 'black' })
+  framebuffer.drawLine(0, 0, 127, 0, 'dark-gray')
+
+  t.is(framebuffer.getPixel(15, 15), 'black')
+  t.is(framebuffer.getPixel(50, 0), 'dark-gray')
+  t.is(framebuffer.getPixel(50, 50), 'white')
+
+  await plinth.wells[0].displayImage(framebuffer)
+  let shown = await imageUtilities.loadImage(plinth.plinth.currentImagePng(0), { dither: 'none', gamma: 1, contrast: 1 })
+  t.deepEqual(shown, framebuffer.toBuffer())
+})
+
+test('text boxes wrap, report overflow and can be turned for landscape cards', (t) => {
+  let framebuffer = new Framebuffer()
+  t.true(framebuffer.drawTextBox('Peacock', 0, 0, 128, 20))
+  t.false(framebuffer.drawTextBox('far too many words for such a small box', 0, 100, 40, 13))
+
+  // at 90 degrees the top left of the landscape card is the top right of the screen
+  let landscape = new Framebuffer()
+  t.true(landscape.drawTextBox('W', 0, 0, 296, 128, { font: 'x-large', rotation: 90 }))
+  let ink = (x0, y0, x1, y1) => {
+    let count = 0
+    for (let y = y0; y < y1; y++) {
+      for (let x = x0; x < x1; x++) {
+        if (landscape.getPixel(x, y) === 'black') count++
+      }
+    }
+    return count
+  }
+  t.true(ink(108, 0, 128, 10) > 0)
+  t.is(ink(0, 0, 108, 296) + ink(108, 10, 128, 296), 0)
+})
+
+test('a well turns its images to match its orientation', async (t) => {
+  let plinth = new Plinth('simulator')
+  plinth.wells[0].setOrientation(90)
+  t.deepEqual(plinth.wells[0].getOrientation(), { rotation: 90, mirrored: false })
+
+  // a landscape image, 296 wide and 128 tall, with its top left pixel black
+  let landscape = Buffer.alloc(296 * 128 / 4, 0xFF)
+  landscape[0] = 0b00111111
+  await plinth.wells[0].displayImage(landscape)
+
+  // the landscape image's top left ends up at the screen's top right
+  let png = plinth.plinth.currentImagePng(0)
+  let shown = new Framebuffer(await imageUtilities.loadImage(png, { dither: 'none', gamma: 1, contrast: 1 }))
+  t.is(shown.getPixel(127, 0), 'black')
+  t.is(shown.getPixel(0, 0), 'white')
+
+  t.throws(() => plinth.wells[0].setOrientation(45), { code: 'InvalidArgument' })
+})
+
+test('partial updates redraw whole bytes, and only inside the screen', async (t) => {
+  let plinth = new Plinth('simulator')
+  let black = Buffer.alloc(128*296*2/8, 0)
+
+  await t.throwsAsync(plinth.wells[0].displayImagePartial(black, { x: 120, y: 0, width: 16, height: 8 }), { code: 'InvalidArgument' })
+  await t.throwsAsync(plinth.wells[0].displayImagePartial(black, { x: 0, y: 0, width: 0, height: 8 }), { code: 'InvalidArgument' })
+
+  await plinth.wells[0].displayImagePartial(black, { x: 3, y: 10, width: 2, height: 1 })
+  let shown = new Framebuffer(plinth.wells[0].getCurrentImage())
+  t.is(shown.getPixel(0, 10), 'black')
+  t.is(shown.getPixel(7, 10), 'black')
+  t.is(shown.getPixel(8, 10), 'white')
+})
+
+test('wells remember their image, and can leave its hash on the card', async (t) => {
+  let plinth = new Plinth('simulator')
+  t.is(plinth.wells[1].getStoredImageHash(), null)
+
+  let framebuffer = new Framebuffer()
+  framebuffer.drawRect(0, 0, 64, 64, { fill: 'dark-gray' })
+  plinth.setPersistImageHashes(true)
+  await plinth.wells[1].displayImage(framebuffer)
+
+  t.deepEqual(plinth.wells[1].getCurrentImage(), framebuffer.toBuffer())
+  t.is(plinth.wells[1].getStoredImageHash(), imageHash(framebuffer))
+  t.regex(imageHash(framebuffer), /^[0-9a-f]{16}$/)
+
+  // the hash goes with the card
+  let card = plinth.plinth.removeCard(1)
+  plinth.plinth.insertCard(3, card)
+  t.is(plinth.wells[3].getStoredImageHash(), imageHash(framebuffer))
+})
+
+test('refreshing a card with the image it already shows is skipped unless its stored hash disagrees', async (t) => {
+  let plinth = new Plinth('simulator')
+  let framebuffer = new Framebuffer()
+  framebuffer.drawCircle(64, 64, 40, { fill: 'black' })
+
+  await plinth.wells[0].displayImage(framebuffer)
+  plinth.setPersistImageHashes(true)
+
+  // the card already shows it, but has no hash to prove it, so it's refreshed and the hash written
+  await plinth.wells[0].displayImage(framebuffer)
+  t.is(plinth.wells[0].getStoredImageHash(), imageHash(framebuffer))
+
+  // forcing always refreshes
+  plinth.plinth.writeMemory(0, Buffer.alloc(4096, 0xFF))
+  plinth.setPersistImageHashes(false)
+  await plinth.wells[0].displayImage(framebuffer)
+  t.is(plinth.wells[0].getStoredImageHash(), null)
+  plinth.setPersistImageHashes(true)
+  await plinth.wells[0].displayImage(framebuffer, { force: true })
+  t.is(plinth.wells[0].getStoredImageHash(), imageHash(framebuffer))
+})
+
+test('custom waveforms are checked against the panel in the well', async (t) => {
+  let plinth = new Plinth('simulator')
+  let lut = defaultLut('GDEY029T94')
+  t.is(lut.length, 153)
+  t.is(defaultLut('GDEW029T5D').length, 212)
+
+  plinth.wells[0].setLut(lut)
+  await plinth.wells[0].displayImage(new Framebuffer())
+
+  t.throws(() => plinth.wells[0].setLut(lut.subarray(0, 100)), { code: 'InvalidArgument' })
+  t.throws(() => plinth.wells[0].setLut(Buffer.alloc(153)), { code: 'InvalidArgument' })
+  await t.throwsAsync(plinth.wells[1].displayImage(new Framebuffer(), { lut: defaultLut('GDEW029T5D') }), { code: 'InvalidArgument' })
+
+  // the panel changed after its waveform was set
+  plinth.wells[0].setPanelType('GDEW029T5D')
+  await t.throwsAsync(plinth.wells[0].displayImage(new Framebuffer(), { force: true }), { code: 'InvalidArgument' })
+  plinth.wells[0].setLut(null)
+  await plinth.wells[0].displayImage(new Framebuffer(), { force: true })
+})
+
+test('simulated cards report their temperature, and temperature waveforms are checked', async (t) => {
+  let plinth = new Plinth('simulator')
+  t.is(plinth.wells[0].getPanelTemperature(), 20)
+
+  plinth.plinth.setCardTemperature(0, -4.5)
+  t.is(plinth.wells[0].getPanelTemperature(), -4.5)
+
+  let lut = defaultLut('GDEY029T94')
+  plinth.wells[0].setTemperatureLuts([{ from: -20, lut }, { from: 10, lut }])
+  await plinth.wells[0].displayImage(new Framebuffer(), { force: true })
+  t.throws(() => plinth.wells[0].setTemperatureLuts([{ from: 0, lut: defaultLut('GDEW029T5D') }]), { code: 'InvalidArgument' })
+  t.throws(() => plinth.wells[0].setTemperatureLuts([{ from: NaN, lut }]), { code: 'InvalidArgument' })
+
+  // prototype panels can't read their temperature
+  plinth.wells[1].setPanelType('GDEW029T5D')
+  t.is(plinth.wells[1].getPanelTemperature(), null)
+})
+
+test('card memory can be read and written from any address', (t) => {
+  let plinth = new Plinth('simulator')
+  let well = plinth.wells[2]
+
+  well._writeMemoryAt(100, Buffer.from([1, 2, 3]))
+  t.deepEqual(well._readMemoryAt(99, 5), Buffer.from([0xFF, 1, 2, 3, 0xFF]))
+  t.deepEqual(plinth.plinth.readMemory(2, 103).subarray(100), Buffer.from([1, 2, 3]))
+
+  well._writeMemoryAt(4095, Buffer.from([0xD5]))
+  t.is(well.getPanelType(), 'GDEW029T5D')
+  t.throws(() => well._writeMemoryAt(4095, Buffer.from([1, 2])), { code: 'MemoryOutOfRange' })
+  t.throws(() => well._readMemoryAt(4096, 1), { code: 'MemoryOutOfRange' })
+})
+
+test('stored data survives a card being pulled out partway through the next save', (t) => {
+  let plinth = new Plinth('simulator')
+  let well = plinth.wells[0]
+  t.is(well.getData(), null)
+
+  well.storeData({ score: 1 })
+  well.storeData({ score: 2 })
+  t.deepEqual(well.getData(), { score: 2 })
+
+  // the next save goes to the slot holding score 1. Half write it
+  let torn = Buffer.alloc(8, 0x00)
+  plinth.plinth.writeMemoryAt(0, 0, torn)
+  t.deepEqual(well.getData(), { score: 2 })
+
+  well.storeData({ score: 3 })
+  t.deepEqual(well.getData(), { score: 3 })
+  t.throws(() => well.storeData({ text: 'x'.repeat(well.maxDataSize) }))
+})
+
+test('the room on a card comes from the drivers', (t) => {
+  let well = new Plinth('simulator').wells[0]
+  t.is(well.maxMemory, 4087)
+  t.is(well.maxDataSize, 2033)
+  well.storeData({ text: 'x'.repeat(well.maxDataSize - 100) })
+})
+
+test('data stored by older versions can still be read', (t) => {
+  let plinth = new Plinth('simulator')
+  let memory = Buffer.alloc(4087, ' ')
+  memory.write(JSON.stringify({ id: 'abc' }))
+  plinth.plinth.writeMemory(1, memory)
+
+  t.deepEqual(plinth.wells[1].getData(), { id: 'abc' })
+})
+
+test('data stored by older versions is kept when the card is next saved to', (t) => {
+  let plinth = new Plinth('simulator')
+  let memory = Buffer.alloc(4087, ' ')
+  memory.write(JSON.stringify({ id: 'abc' }))
+  plinth.plinth.writeMemory(1, memory)
+
+  let well = plinth.wells[1]
+  well.setItem('score', 10)
+  well.setItem('score', 20)
+  t.deepEqual(well.getData(), { id: 'abc' })
+  t.deepEqual(well.getItemKeys(), ['data', 'score'])
+})
+
+test('data stored by older versions which is too long to keep has to be discarded before saving', (t) => {
+  let plinth = new Plinth('simulator')
+  let memory = Buffer.alloc(4087, ' ')
+  memory.write(JSON.stringify({ log: 'x'.repeat(3000) }))
+  plinth.plinth.writeMemory(1, memory)
+
+  let well = plinth.wells[1]
+  t.throws(() => well.setItem('score', 10), { code: 'LegacyDataTooLong' })
+  t.is(well.getData().log.length, 3000)
+
+  well.discardLegacyData()
+  t.is(well.getData(), null)
+  well.setItem('score', 10)
+  t.is(well.getItem('score'), 10)
+})
+
+test('cards keep named items of JSON or binary alongside stored data', (t) => {
+  let plinth = new Plinth('simulator')
+  let well = plinth.wells[3]
+  well.storeData({ level: 4 })
+
+  well.setItem('score', 250)
+  well.setItem('portrait', Buffer.from([1, 2, 3]))
+  well.setItem('score', 300)
+  t.is(well.getItem('score'), 300)
+  t.deepEqual(well.getItem('portrait'), Buffer.from([1, 2, 3]))
+  t.is(well.getItem('missing'), null)
+  t.deepEqual(well.getItemKeys(), ['data', 'score', 'portrait'])
+  t.deepEqual(well.getData(), { level: 4 })
+
+  t.true(well.deleteItem('portrait'))
+  t.false(well.deleteItem('portrait'))
+  t.deepEqual(well.getItemKeys(), ['data', 'score'])
+
+  t.throws(() => well.setItem('k'.repeat(33), 1), { code: 'InvalidArgument' })
+  t.throws(() => well.setItem('big', Buffer.alloc(4000)), { code: 'InvalidArgument' })
+})
+
+test('card data is stored as MessagePack, and compressed when asked', (t) => {
+  let plinth = new Plinth('simulator')
+  let well = plinth.wells[2]
+
+  let state = { hp: -12, speed: 1.5, alive: true, target: null, sprite: Buffer.from([0, 255]), moves: [[1, 2], [3, 4294967296]] }
+  well.storeData(state)
+  t.deepEqual(well.getData(), state)
+
+  // far more than fits as JSON, but it repeats itself
+  let map = { tiles: Array.from({ length: 1500 }, (_, i) => ({ kind: i % 7 == 0 ? 'water' : 'grass', seen: false })) }
+  t.true(JSON.stringify(map).length > 10 * well.maxDataSize)
+  t.throws(() => well.storeData(map), { code: 'InvalidArgument' })
+  well.storeData(map, { compress: true })
+  t.deepEqual(well.getData(), map)
+
+  let looped = {}
+  looped.self = looped
+  t.throws(() => well.setItem('looped', looped), { code: 'InvalidArgument' })
+  t.throws(() => well.setItem('callback', () => {}), { code: 'InvalidArgument' })
+})
+
+test('values which would come back as something else are refused rather than changed', (t) => {
+  let plinth = new Plinth('simulator')
+  let well = plinth.wells[2]
+
+  t.throws(() => well.setItem('when', new Date()), { code: 'InvalidArgument' })
+  t.throws(() => well.setItem('seen', { cards: new Set([1, 2]) }), { code: 'InvalidArgument' })
+  t.throws(() => well.setItem('owners', [new Map()]), { code: 'InvalidArgument' })
+
+  well.setItem('zero', -0)
+  t.true(Object.is(well.getItem('zero'), -0))
+  let bare = Object.assign(Object.create(null), { hp: 3 })
+  well.setItem('bare', bare)
+  t.deepEqual(well.getItem('bare'), { hp: 3 })
+})
@@ -8,7 +8,7 @@ async function main() {
 
   let erase = function(well) {
     console.log('erasing well', well.id)
-    let buf = Buffer.alloc(4096, ' '.charCodeAt(0))
+    let buf = Buffer.alloc(well.maxMemory, ' '.charCodeAt(0))
     buf[0] = '{'.charCodeAt(0)
     buf[1] = '}'.charCodeAt(0)
     well._writeMemory(buf)
@@ -50,7 +50,7 @@ main()
 
 //   let erase = function(well) {
 //     console.log('erasing well', well.id)
-//     let buf = Buffer.alloc(4096, ' '.charCodeAt(0))
+//     let buf = Buffer.alloc(well.maxMemory, ' '.charCodeAt(0))
 //     buf[0] = '{'.charCodeAt(0)
 //     buf[1] = '}'.charCodeAt(0)
 //     well._writeMemory(buf)
@@ -3,6 +3,8 @@ let path = require('path')
 let fs = require('fs/promises')
 let _ = require('lodash')
 
+let native = require('./nativeBinding')
+
 const DEFAULT_IMAGE_DIRECTORY = path.resolve('/', 'home', 'pi', 'Pictures', 'wyldcard')
 const DEFAULT_IMAGE_COLLECTION = 'collectionB'
 
@@ -11,9 +13,20 @@ let randomImage = async function(imageDirectory = DEFAULT_IMAGE_DIRECTORY, colle
   let imageNames = await fs.readdir(directory)
   let randomImageName = _.sample(imageNames)
   let randomImagePath = path.join(directory, randomImageName)
-  return loadPng(randomImagePath)
+  return loadImage(randomImagePath)
+}
+
+// load a PNG, JPEG, GIF or BMP, from a file path or a Buffer holding the file, ready to pass to `displayImage()`.
+// Images of any size are scaled to fit the card. Pass `{ fit: 'contain' }` to show the whole image with white bars
+// rather than cropping it, or `{ fit: 'stretch' }` to squash it to fit
+// shades between the card's four grays are dithered. `dither` can be 'floyd-steinberg' (the default), 'atkinson',
+// 'bayer', 'blue-noise' or 'none'. `gamma` and `contrast` default to a curve which suits the panels' grayish white,
+// pass `{ gamma: 1, contrast: 1 }` to leave the image's tones alone
+let loadImage = async function(source, { width, height, fit, dither, gamma, contrast } = {}) {
+  return native.loadImage(source, { width, height, fit, dither, gamma, contrast })
 }
 
+// only loads PNGs which are already exactly 128x296 with 4 grays. `loadImage()` takes anything
 let loadPng = async function(imagePath) {
   return new Promise((resolve, reject) => {
     pngparse.parseFile(imagePath, (err, data) => {
@@ -51,5 +64,6 @@ module.exports = {
   DEFAULT_IMAGE_DIRECTORY,
   DEFAULT_IMAGE_COLLECTION,
   randomImage,
+  loadImage,
   loadPng,
 }
\ No newline at end of file
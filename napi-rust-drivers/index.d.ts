@@ -3,19 +3,154 @@
 
 /* auto-generated by NAPI-RS */
 
+export interface WellImage {
+  well: number
+  image: Buffer
+}
+export interface TemperatureLut {
+  from: number
+  lut: Buffer
+}
+export interface WellOrientation {
+  rotation: number
+  mirrored: boolean
+}
+export interface LoadImageOptions {
+  width?: number
+  height?: number
+  fit?: string
+  dither?: string
+  gamma?: number
+  contrast?: number
+}
+export interface ShapeStyle {
+  fill?: string
+  stroke?: string
+  strokeWidth?: number
+}
+export interface TextBoxOptions {
+  color?: string
+  font?: string
+  bold?: boolean
+  align?: string
+  verticalAlign?: string
+  lineSpacing?: number
+  rotation?: number
+}
+export function loadImage(source: string | Buffer, options?: LoadImageOptions | undefined | null): Promise<Buffer>
+export function imageHash(image: Buffer | Framebuffer): string
+export function defaultLut(panelType: string): Buffer
+export const MAX_MEMORY: number
+export const MAX_DATA_SIZE: number
 export class JsPrototype {
   constructor()
-  displayImage(well: number, image: Buffer): void
+  displayImage(well: number, image: Buffer | Framebuffer, colorMode?: string | undefined | null, force?: boolean | undefined | null, lut?: Buffer | undefined | null): Promise<void>
+  displayImages(images: Array<WellImage>, colorMode?: string | undefined | null, force?: boolean | undefined | null, lut?: Buffer | undefined | null): Promise<void>
+  displayImagePartial(well: number, image: Buffer | Framebuffer, x: number, y: number, width: number, height: number): Promise<void>
   setSwitchCallback(well: number, switch: string, callback: (...args: any[]) => any): void
   readMemory(well: number, bytes: number): Buffer
   writeMemory(well: number, data: Buffer): void
+  readMemoryAt(well: number, address: number, bytes: number): Buffer
+  writeMemoryAt(well: number, address: number, data: Buffer): void
+  readRecord(well: number): Buffer | null
+  writeRecord(well: number, data: Buffer): void
+  discardLegacyData(well: number): void
+  getItem(well: number, key: string): any
+  setItem(well: number, key: string, value: any, compress?: boolean | undefined | null): void
+  deleteItem(well: number, key: string): boolean
+  itemKeys(well: number): Array<string>
   wellOccupied(well: number): boolean
+  setBusyTimeout(milliseconds: number): void
+  setPanelType(well: number, panelType?: string | undefined | null): void
+  panelType(well: number): string
+  storePanelType(well: number, panelType: string): void
+  setOrientation(well: number, rotation: number, mirrored?: boolean | undefined | null): void
+  orientation(well: number): WellOrientation
+  setLut(well: number, lut?: Buffer | undefined | null): void
+  setTemperatureLuts(well: number, luts: Array<TemperatureLut>): void
+  panelTemperature(well: number): number | null
+  currentImage(well: number): Buffer | null
+  setPersistImageHashes(persist: boolean): void
+  storedImageHash(well: number): string | null
 }
 export class JsDevkit {
   constructor()
-  displayImage(well: number, image: Buffer): void
+  displayImage(well: number, image: Buffer | Framebuffer, colorMode?: string | undefined | null, force?: boolean | undefined | null, lut?: Buffer | undefined | null): Promise<void>
+  displayImages(images: Array<WellImage>, colorMode?: string | undefined | null, force?: boolean | undefined | null, lut?: Buffer | undefined | null): Promise<void>
+  displayImagePartial(well: number, image: Buffer | Framebuffer, x: number, y: number, width: number, height: number): Promise<void>
+  setSwitchCallback(well: number, switch: string, callback: (...args: any[]) => any): void
+  readMemory(well: number, bytes: number): Buffer
+  writeMemory(well: number, data: Buffer): void
+  readMemoryAt(well: number, address: number, bytes: number): Buffer
+  writeMemoryAt(well: number, address: number, data: Buffer): void
+  readRecord(well: number): Buffer | null
+  writeRecord(well: number, data: Buffer): void
+  discardLegacyData(well: number): void
+  getItem(well: number, key: string): any
+  setItem(well: number, key: string, value: any, compress?: boolean | undefined | null): void
+  deleteItem(well: number, key: string): boolean
+  itemKeys(well: number): Array<string>
+  wellOccupied(well: number): boolean
+  setBusyTimeout(milliseconds: number): void
+  setPanelType(well: number, panelType?: string | undefined | null): void
+  panelType(well: number): string
+  storePanelType(well: number, panelType: string): void
+  setOrientation(well: number, rotation: number, mirrored?: boolean | undefined | null): void
+  orientation(well: number): WellOrientation
+  setLut(well: number, lut?: Buffer | undefined | null): void
+  setTemperatureLuts(well: number, luts: Array<TemperatureLut>): void
+  panelTemperature(well: number): number | null
+  currentImage(well: number): Buffer | null
+  setPersistImageHashes(persist: boolean): void
+  storedImageHash(well: number): string | null
+}
+export class Framebuffer {
+  constructor(image?: Buffer | undefined | null)
+  clear(color: string): void
+  getPixel(x: number, y: number): string
+  setPixel(x: number, y: number, color: string): void
+  drawLine(x0: number, y0: number, x1: number, y1: number, color: string, strokeWidth?: number | undefined | null): void
+  drawRect(x: number, y: number, width: number, height: number, style: ShapeStyle): void
+  drawCircle(centerX: number, centerY: number, diameter: number, style: ShapeStyle): void
+  drawText(text: string, x: number, y: number, color: string): void
+  drawTextBox(text: string, x: number, y: number, width: number, height: number, options?: TextBoxOptions | undefined | null): boolean
+  blit(image: Buffer, width: number, height: number, x: number, y: number, transparent?: string | undefined | null): void
+  toBuffer(): Buffer
+}
+export class JsSimulator {
+  constructor()
+  displayImage(well: number, image: Buffer | Framebuffer, colorMode?: string | undefined | null, force?: boolean | undefined | null, lut?: Buffer | undefined | null): Promise<void>
+  displayImages(images: Array<WellImage>, colorMode?: string | undefined | null, force?: boolean | undefined | null, lut?: Buffer | undefined | null): Promise<void>
+  displayImagePartial(well: number, image: Buffer | Framebuffer, x: number, y: number, width: number, height: number): Promise<void>
   setSwitchCallback(well: number, switch: string, callback: (...args: any[]) => any): void
   readMemory(well: number, bytes: number): Buffer
   writeMemory(well: number, data: Buffer): void
+  readMemoryAt(well: number, address: number, bytes: number): Buffer
+  writeMemoryAt(well: number, address: number, data: Buffer): void
+  readRecord(well: number): Buffer | null
+  writeRecord(well: number, data: Buffer): void
+  discardLegacyData(well: number): void
+  getItem(well: number, key: string): any
+  setItem(well: number, key: string, value: any, compress?: boolean | undefined | null): void
+  deleteItem(well: number, key: string): boolean
+  itemKeys(well: number): Array<string>
   wellOccupied(well: number): boolean
+  setBusyTimeout(milliseconds: number): void
+  setPanelType(well: number, panelType?: string | undefined | null): void
+  panelType(well: number): string
+  storePanelType(well: number, panelType: string): void
+  setOrientation(well: number, rotation: number, mirrored?: boolean | undefined | null): void
+  orientation(well: number): WellOrientation
+  setLut(well: number, lut?: Buffer | undefined | null): void
+  setTemperatureLuts(well: number, luts: Array<TemperatureLut>): void
+  panelTemperature(well: number): number | null
+  currentImage(well: number): Buffer | null
+  setPersistImageHashes(persist: boolean): void
+  storedImageHash(well: number): string | null
+  insertCard(well: number, memory?: Buffer | undefined | null): void
+  setCardTemperature(well: number, degrees: number): void
+  removeCard(well: number): Buffer | null
+  pressButton(well: number, switch: string): void
+  releaseButton(well: number, switch: string): void
+  currentImagePng(well: number): Buffer | null
 }
@@ -1,10 +1,15 @@
 const rustDriver = require('./nativeBinding')
 const imageUtilities = require('./images')
 const { Plinth, CardNotPresentError } = require('./plinth')
+// pulled out first so the names can be seen by `import { ... }` from ES modules, which can't follow `rustDriver.x`
+const { Framebuffer, imageHash, defaultLut } = rustDriver
 
 module.exports = {
   Plinth,
   imageUtilities,
+  Framebuffer,
+  imageHash,
+  defaultLut,
   _rustDriver: rustDriver,
   CardNotPresentError,
 }
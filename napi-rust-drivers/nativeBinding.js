@@ -246,7 +246,14 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { JsPrototype, JsDevkit } = nativeBinding
+const { JsPrototype, JsDevkit, Framebuffer, JsSimulator, loadImage, imageHash, defaultLut, MAX_MEMORY, MAX_DATA_SIZE } = nativeBinding
 
 module.exports.JsPrototype = JsPrototype
 module.exports.JsDevkit = JsDevkit
+module.exports.Framebuffer = Framebuffer
+module.exports.JsSimulator = JsSimulator
+module.exports.loadImage = loadImage
+module.exports.imageHash = imageHash
+module.exports.defaultLut = defaultLut
+module.exports.MAX_MEMORY = MAX_MEMORY
+module.exports.MAX_DATA_SIZE = MAX_DATA_SIZE
@@ -3,14 +3,19 @@ let util = require('util')
 
 let _ = require('lodash')
 
-let { JsPrototype, JsDevkit } = require('./nativeBinding')
+let { JsPrototype, JsDevkit, JsSimulator, Framebuffer, MAX_MEMORY, MAX_DATA_SIZE } = require('./nativeBinding')
 
-function CardNotPresentError(well) {
-  this.name = 'CardNotPresentError';
-  this.message = `No card present for well ${well}`;
-  this.stack = (new Error()).stack;
+// thrown before a call reaches the drivers when a well is empty. It has the
+// same code as the drivers' own error for an empty well, so checking
+// `e.code === 'CardNotPresent'` catches both
+class CardNotPresentError extends Error {
+  constructor(well) {
+    super(`No card present for well ${well}`)
+    this.name = 'CardNotPresentError'
+    this.code = 'CardNotPresent'
+    this.well = well
+  }
 }
-CardNotPresentError.prototype = new Error; 
 
 class Plinth extends EventEmitter {
   constructor(model = 'devkit') {
@@ -22,8 +27,11 @@ class Plinth extends EventEmitter {
     else if (model == 'devkit') {
       this.plinth = new JsDevkit()
     }
+    else if (model == 'simulator') {
+      this.plinth = new JsSimulator()
+    }
     else {
-      throw new Error('must supply argument to Plinth constructor, either "prototype" or "devkit"')
+      throw new Error('must supply argument to Plinth constructor, either "prototype", "devkit" or "simulator"')
     }
     
     this.wells = [
@@ -33,6 +41,27 @@ class Plinth extends EventEmitter {
       new Well(3, this.plinth),
     ]
   }
+
+  // how long, in milliseconds, to wait for a card to finish refreshing before giving up with a 'BusyTimeout' error.
+  // Defaults to 6 seconds
+  setBusyTimeout = function(milliseconds) {
+    this.plinth.setBusyTimeout(milliseconds)
+  }
+
+  // write a hash of each new image to the card it's shown on, so `well.getStoredImageHash()` can tell what a card
+  // shows even after a restart. Off by default, as it means writing to card memory after every refresh
+  setPersistImageHashes = function(persist) {
+    this.plinth.setPersistImageHashes(persist)
+  }
+
+  // show an image on several wells at once, eg. when dealing out a hand. The cards all refresh together, so this
+  // takes about as long as updating one of them. Pass an array of `{ well, image }`, where `well` is the well's number
+  // wells already showing their image are left alone, unless `force` is set. `lut` is a waveform for just this refresh
+  displayImages = async function(images, { colorMode, force, lut } = {}) {
+    images.forEach(({ well }) => this.wells[well]?._checkCardPresent())
+    images = images.map(({ well, image }) => ({ well, image: image instanceof Framebuffer ? image.toBuffer() : image }))
+    await this.plinth.displayImages(images, colorMode, force, lut)
+  }
 }
 
 class Well extends EventEmitter {
@@ -41,7 +70,8 @@ class Well extends EventEmitter {
 
     this.id = id
     this.plinth = plinth
-    this.maxMemory = 4096 // bytes. basically 4kb
+    this.maxMemory = MAX_MEMORY // bytes. basically 4kb, the last 9 bytes of the chip are reserved for a hash of the card's image and its panel type
+    this.maxDataSize = MAX_DATA_SIZE // bytes, shared by `storeData()` and the items. They're kept twice, so a card pulled out mid write still has the last save
     this.dimensions = {
       x: 128,
       y: 296,
@@ -58,10 +88,23 @@ class Well extends EventEmitter {
   }
 
   // display an image on the e-paper display of the wyldcard present in this well
-  // pass in a Buffer. You probably want to create this using the methods in `imageUtilities`
-  displayImage = async function(imageBuffer) {
+  // pass in a Buffer or a Framebuffer. You probably want to create this using the methods in `imageUtilities`
+  // pass `{ colorMode: 'monochrome' }` to send a black and white image with one bit per pixel, which refreshes faster
+  // the refresh happens in the background. Await the result to know when the card has finished updating
+  // if the card is already showing the image nothing happens, pass `{ force: true }` to refresh it anyway
+  // pass `{ lut }` to use a custom waveform for just this refresh, see `setLut()`
+  displayImage = async function(imageBuffer, { colorMode, force, lut } = {}) {
     this._checkCardPresent()
-    this.plinth.displayImage(this.id, imageBuffer)
+    await this.plinth.displayImage(this.id, imageBuffer, colorMode, force, lut)
+  }
+
+  // quickly redraw just one rectangle of the screen, without the whole card flashing. Handy for counters, health bars and cursors
+  // pass in a full screen image Buffer, along with the region to update as `{ x, y, width, height }` in pixels
+  // partial updates are black and white only: light grays show as white and dark grays as black
+  // older prototype cards can't do partial updates, so they redraw the whole image instead
+  displayImagePartial = async function(imageBuffer, { x, y, width, height }) {
+    this._checkCardPresent()
+    await this.plinth.displayImagePartial(this.id, imageBuffer, x, y, width, height)
   }
 
   // write raw bytes to the memory chip within the wyldcard present in this well
@@ -72,16 +115,11 @@ class Well extends EventEmitter {
     this.plinth.writeMemory(this.id, buffer)
   }
 
-  storeData = function(object) {
-    let text = JSON.stringify(object)
-    if (text.length > this.maxMemory) {
-      throw new Error(`attempted to store too much data. JSON stringified data is of length ${text.length}, which is more than the maximum of ${this.maxMemory}. You could try using the _writeMemory and _readMemory functions directly, storing data in binary rather than ascii JSON.`)
-    }
-
-    let buf = Buffer.alloc(4096, ' ')
-    buf.write(text)
-
-    this._writeMemory(buf)
+  // saves the object in a compact binary form, MessagePack. Pass `{ compress: true }` to deflate it as well, which
+  // fits far more on the card when it repeats itself, like a map or a deck. If the card is pulled out partway through,
+  // `getData()` returns what was saved before. Older versions of the SDK saved JSON, and can't read what this saves
+  storeData = function(object, { compress } = {}) {
+    this.setItem('data', object, { compress })
   }
 
   _readMemory = function() {
@@ -89,10 +127,119 @@ class Well extends EventEmitter {
     return this.plinth.readMemory(this.id, this.maxMemory)
   }
 
+  // read or write just part of the card's memory, starting `address` bytes in. Much quicker than rewriting all of it,
+  // and it wears the memory chip out more slowly, so use these for things that change often like scores and counters
+  _readMemoryAt = function(address, length) {
+    this._checkCardPresent()
+    return this.plinth.readMemoryAt(this.id, address, length)
+  }
+
+  _writeMemoryAt = function(address, buffer) {
+    this._checkCardPresent()
+    this.plinth.writeMemoryAt(this.id, address, buffer)
+  }
+
+  // returns the object last saved with `storeData()`, or null if nothing's been saved on this card. Cards saved on by
+  // older versions, with their JSON across the whole of memory, give back that object and keep it when saved to
   getData = function() {
-    let text = this._readMemory().toString().trim()
-    console.log('text', text)
-    return JSON.parse(text)
+    return this.getItem('data')
+  }
+
+  // throws away an object saved by an older version which is too long to keep while anything new is saved, which
+  // otherwise throws a 'LegacyDataTooLong' error. Read it with `getData()` first if you want to save some of it again
+  discardLegacyData = function() {
+    this._checkCardPresent()
+    this.plinth.discardLegacyData(this.id)
+  }
+
+  // keep a value on the card under a short name, up to 32 bytes, so separate parts of a game can each save their own
+  // data without rewriting everyone else's. Buffers are kept as they are, anything else as MessagePack, which can hold
+  // whatever JSON can, and Buffers too. Pass `{ compress: true }` to deflate it as well
+  setItem = function(key, value, { compress } = {}) {
+    this._checkCardPresent()
+    this.plinth.setItem(this.id, key, value, compress)
+  }
+
+  // returns the value kept under the key, or null if there isn't one
+  getItem = function(key) {
+    this._checkCardPresent()
+    return this.plinth.getItem(this.id, key)
+  }
+
+  // returns true if there was an item to delete
+  deleteItem = function(key) {
+    this._checkCardPresent()
+    return this.plinth.deleteItem(this.id, key)
+  }
+
+  // returns the keys of every item on the card, in the order they were first set
+  getItemKeys = function() {
+    this._checkCardPresent()
+    return this.plinth.itemKeys(this.id)
+  }
+
+  // choose the display controller used for this well, either 'GDEW029T5D' (prototype cards) or 'GDEY029T94' (devkit cards)
+  // by default it's detected from the card, falling back to the usual panel for the plinth. Pass null to go back to the default
+  setPanelType = function(panelType) {
+    this.plinth.setPanelType(this.id, panelType)
+  }
+
+  // returns the name of the display controller that will be used for this well
+  getPanelType = function() {
+    return this.plinth.panelType(this.id)
+  }
+
+  // records the panel type in the memory of the card in this well, so it's detected on any plinth
+  storePanelType = function(panelType) {
+    this._checkCardPresent()
+    this.plinth.storePanelType(this.id, panelType)
+  }
+
+  // turn every image shown on this well by 0, 90, 180 or 270 degrees clockwise, and optionally mirror it first.
+  // At 90 and 270 degrees images are landscape, 296 pixels wide and 128 tall. Use 180 for plinths mounted upside down
+  setOrientation = function(rotation, { mirrored = false } = {}) {
+    this.plinth.setOrientation(this.id, rotation, mirrored)
+  }
+
+  // returns { rotation, mirrored }
+  getOrientation = function() {
+    return this.plinth.orientation(this.id)
+  }
+
+  // use a custom waveform for this well's grayscale refreshes: a Buffer with the LUT in the format the panel's controller takes.
+  // Start from `defaultLut(panelType)`. It's checked against the well's panel type, so set that first. Pass null to go back to the built in one
+  setLut = function(lut) {
+    this.plinth.setLut(this.id, lut)
+  }
+
+  // use different waveforms depending on how warm the card is, eg. a slower one which drives the pixels harder in the cold.
+  // Pass an array of `{ from, lut }`, where each `lut` is used from `from` degrees C up to the next one's. Below all of them
+  // the coldest is used. The panel's temperature is read at the start of every grayscale refresh. A `setLut()` waveform wins.
+  // Only devkit cards have a sensor, and current plinths can't read it back, so they stay on their `setLut()` or built in
+  // waveform. Pass [] to turn it off
+  setTemperatureLuts = function(luts) {
+    this.plinth.setTemperatureLuts(this.id, luts)
+  }
+
+  // returns the temperature of the card's panel in degrees C, or null if it can't tell. That's always null on the devkit and
+  // prototype plinths, as neither can read what the panel sends back. Simulated cards report theirs
+  getPanelTemperature = function() {
+    this._checkCardPresent()
+    return this.plinth.panelTemperature(this.id)
+  }
+
+  // returns the image last shown on this well as a packed Buffer, or null if nothing has been shown since the plinth started,
+  // or the card has been swapped since.
+  // it's how the panel has it: portrait, grayscale, and after any orientation
+  getCurrentImage = function() {
+    return this.plinth.currentImage(this.id)
+  }
+
+  // returns the hash of the image the card in this well was showing when it was last written, or null if none was stored.
+  // compare it with `imageHash(image)` to check what the card shows. See `plinth.setPersistImageHashes()`
+  getStoredImageHash = function() {
+    this._checkCardPresent()
+    return this.plinth.storedImageHash(this.id)
   }
 
   // returns a boolean which is true if this well contains a card, false otherwise
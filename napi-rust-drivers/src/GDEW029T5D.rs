@@ -1,4 +1,3 @@
-use std::io::{Error, ErrorKind};
 use std::thread;
 use std::time::{Duration, Instant};
 use rand::Rng;
@@ -6,6 +5,9 @@ use rand::Rng;
 use embedded_hal::digital::v2::{ InputPin, OutputPin };
 use embedded_hal::blocking::spi::Transfer;
 
+use crate::epd::{ check_image_size, Capabilities, ColorResolution, EpdDriver, DEFAULT_BUSY_TIMEOUT };
+use crate::error::WyldcardError;
+
 
 // const SPI_CLOCK: u8 = 11;
 // const SPI_MOSI: u8 = 10;
@@ -19,6 +21,8 @@ pub struct GDEW029T5DController<SPI, RESET, BUSY, DC, CS> {
     data_or_command: DC,
     chip_select: CS,
     spi: SPI,
+    busy_timeout: Duration,
+    lut: Option<Vec<u8>>, // replaces the 4 color grayscale waveform
 }
 
 struct WaveformLut {
@@ -29,94 +33,141 @@ struct WaveformLut {
     bb: [u8; 42],
 }
 
+pub const LUT_SIZE: usize = 44 + 42 * 4;
+
+impl WaveformLut {
+    // the tables one after another, in the order they're sent
+    fn from_bytes(lut: &[u8]) -> WaveformLut {
+        WaveformLut {
+            vcom: lut[0..44].try_into().unwrap(),
+            ww: lut[44..86].try_into().unwrap(),
+            bw: lut[86..128].try_into().unwrap(),
+            wb: lut[128..170].try_into().unwrap(),
+            bb: lut[170..212].try_into().unwrap(),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        [&self.vcom[..], &self.ww, &self.bw, &self.wb, &self.bb].concat()
+    }
+}
+
+// the five tables sent with commands 0x20 to 0x24 (VCOM, white to white, black to white, white to black and black
+// to black) one after another. Each is 7 groups of 6 bytes: the level for each of 4 phases, the 4 phase lengths
+// in frames, then how many times the group repeats. The VCOM table has 2 more bytes on the end
+pub fn check_lut(lut: &[u8]) -> Result<(), WyldcardError> {
+    if lut.len() != LUT_SIZE {
+        return Err(WyldcardError::InvalidArgument(format!("GDEW029T5D waveforms are {} bytes, not {}", LUT_SIZE, lut.len())));
+    }
+
+    let tables = [&lut[0..42], &lut[44..86], &lut[86..128], &lut[128..170], &lut[170..212]];
+    let has_phases = tables.iter().any(|table| table.chunks(6).any(|group| group[5] > 0 && group[1..5].iter().any(|frames| *frames > 0)));
+    if !has_phases {
+        return Err(WyldcardError::InvalidArgument(String::from("waveform has no phases, so it would never change the screen")));
+    }
+    Ok(())
+}
+
+pub fn default_lut() -> Vec<u8> {
+    get_4_grayscale_lut().to_bytes()
+}
+
 impl<SPI, RESET, BUSY, DC, CS, E> GDEW029T5DController<SPI, RESET, BUSY, DC, CS>
     where SPI: Transfer<u8, Error = E>,
           RESET: OutputPin,
+          RESET::Error: std::fmt::Debug,
           BUSY: InputPin,
           BUSY::Error: std::fmt::Debug,
           DC: OutputPin,
+          DC::Error: std::fmt::Debug,
           CS: OutputPin,
+          CS::Error: std::fmt::Debug,
           E: std::fmt::Debug {
             
-    fn reset_ic(&mut self) {
-        println!("setting reset pin low, three times");
+    fn reset_ic(&mut self) -> Result<(), WyldcardError> {
         for _ in 0..3 { 
-            self.reset.set_low();
+            self.reset.set_low().map_err(WyldcardError::bus)?;
             thread::sleep(Duration::from_millis(10));
-            self.reset.set_high();
+            self.reset.set_high().map_err(WyldcardError::bus)?;
             thread::sleep(Duration::from_millis(10));
         }
+
+        Ok(())
     }
 
-    fn wait_for_idle(&mut self) -> Duration {
-        println!("waiting for idle signal");
+    fn wait_for_idle(&mut self) -> Result<Duration, WyldcardError> {
         let start = Instant::now();
-        while self.busy.is_low().expect("read busy pin") {
+        while self.busy.is_low().map_err(WyldcardError::bus)? {
+            if start.elapsed() > self.busy_timeout {
+                return Err(WyldcardError::BusyTimeout(self.busy_timeout));
+            }
             thread::sleep(Duration::from_millis(10));
         }
-        let wait_duration = start.elapsed();
-        println!("epd was busy for {:?}", wait_duration);
-        wait_duration
+        Ok(start.elapsed())
     }
     
-    fn write_command(&mut self, command: &[u8]) {
+    fn write_command(&mut self, command: &[u8]) -> Result<(), WyldcardError> {
         let command: &mut [u8] = &mut command.to_owned();
-        self.chip_select.set_low();
+        self.chip_select.set_low().map_err(WyldcardError::bus)?;
+
+        self.data_or_command.set_low().map_err(WyldcardError::bus)?;
+        self.spi.transfer(command).map_err(WyldcardError::bus)?;
 
-        self.data_or_command.set_low();
-        self.spi.transfer(command).expect("error with spi write");
+        self.chip_select.set_high().map_err(WyldcardError::bus)?;
 
-        self.chip_select.set_high();
+        Ok(())
     }
     
-    fn write_data(&mut self, data: &[u8]) {
+    fn write_data(&mut self, data: &[u8]) -> Result<(), WyldcardError> {
         let data = &mut data.to_owned();
-        self.chip_select.set_low();
+        self.chip_select.set_low().map_err(WyldcardError::bus)?;
 
-        self.data_or_command.set_high();
-        self.spi.transfer(data).expect("error with spi write");
+        self.data_or_command.set_high().map_err(WyldcardError::bus)?;
+        self.spi.transfer(data).map_err(WyldcardError::bus)?;
 
-        self.chip_select.set_high();
+        self.chip_select.set_high().map_err(WyldcardError::bus)?;
+
+        Ok(())
     }
 
     // sends a command, followed by data. This uses one less toggle of the Chip Select gpio
     // which increases our performance (since we're behind a gpio expander)
-    fn write_command_with_data(&mut self, command: &[u8], data: &[u8]) {
+    fn write_command_with_data(&mut self, command: &[u8], data: &[u8]) -> Result<(), WyldcardError> {
         let command = &mut command.to_owned();
         let data = &mut data.to_owned();
-        self.chip_select.set_low();
+        self.chip_select.set_low().map_err(WyldcardError::bus)?;
+
+        self.data_or_command.set_low().map_err(WyldcardError::bus)?;
+        self.spi.transfer(command).map_err(WyldcardError::bus)?;
 
-        self.data_or_command.set_low();
-        self.spi.transfer(command).expect("error with spi write");
+        self.data_or_command.set_high().map_err(WyldcardError::bus)?;
+        self.spi.transfer(data).map_err(WyldcardError::bus)?;
 
-        self.data_or_command.set_high();
-        self.spi.transfer(data).expect("error with spi write");
+        self.chip_select.set_high().map_err(WyldcardError::bus)?;
 
-        self.chip_select.set_high();
+        Ok(())
     }
 
-    pub fn start_epd(&mut self) -> Result<(), Error> {
-        self.chip_select.set_high();
+    pub fn start_epd(&mut self) -> Result<(), WyldcardError> {
+        self.chip_select.set_high().map_err(WyldcardError::bus)?;
         
-        self.reset_ic();
+        self.reset_ic()?;
 
         // stuff added for custom lut:
 
-        self.write_command_with_data(&[0x06], &vec![0x17, 0x17, 0x17]); // booster soft start 
+        self.write_command_with_data(&[0x06], &[0x17, 0x17, 0x17])?; // booster soft start 
 
-        self.write_command_with_data(&[0x01], &vec![0x03, 0x00, 0x2b, 0x2b, 0x13]); // power setting
+        self.write_command_with_data(&[0x01], &[0x03, 0x00, 0x2b, 0x2b, 0x13])?; // power setting
 
         // end extra lut stuff (not sure if needed? or can use all the time?)
     
-        println!("sending Power On command");
-        self.write_command(&[0x04]);
-        let power_on_time = self.wait_for_idle();
+        self.write_command(&[0x04])?;
+        let power_on_time = self.wait_for_idle()?;
         if power_on_time < Duration::from_millis(50) {
-            return Err(Error::new(ErrorKind::NotConnected, "Power On command returned in less than 50ms, card is probably not present"));
+            return Err(WyldcardError::CardNotPresent); // Power On returned in less than 50ms, there's no panel answering
         }
     
-        println!("setting up epd");
-        self.write_command_with_data(&[0x00], &[0b1011_1111]); // we might want the first bit to be `1`, for our resolution, but the example has it as zero
+        self.write_command_with_data(&[0x00], &[0b1011_1111])?; // we might want the first bit to be `1`, for our resolution, but the example has it as zero
         // nevermind, it's overridden by the next command. meaning the next command could be superflous?
         // third bit flipped for custom lut!!
 
@@ -126,147 +177,155 @@ impl<SPI, RESET, BUSY, DC, CS, E> GDEW029T5DController<SPI, RESET, BUSY, DC, CS>
         // self.write_data(&[0x28]);
 
         // also added for custom lut
-        self.write_command_with_data(&[0x82], &[0x12]); // vcom_DC setting
+        self.write_command_with_data(&[0x82], &[0x12])?; // vcom_DC setting
     
-        self.write_command_with_data(&[0x50], &[0x97]); // vcom and data settings
+        self.write_command_with_data(&[0x50], &[0x97])?; // vcom and data settings
 
         Ok(())
     }
 
     #[allow(dead_code)]
-    fn display_single_color_image(&mut self, black: bool) {
-        self.wait_for_idle();
-        println!("displaying default image");
-
-        let pixel: &[u8];
+    fn display_single_color_image(&mut self, black: bool) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
 
-        match black {
-            true => { pixel = &[0x00]},
-            false => { pixel = &[0xff]},
-        }
+        let pixel: &[u8] = if black { &[0x00] } else { &[0xff] };
 
-        self.write_command(&[0x10]);
+        self.write_command(&[0x10])?;
         for _ in 0..4736 {
-            self.write_data(pixel);
+            self.write_data(pixel)?;
         }
-        self.write_command(&[0x13]);
+        self.write_command(&[0x13])?;
         for _ in 0..4736 {
-            self.write_data(pixel);
+            self.write_data(pixel)?;
         }
     
-        self.write_command(&[0x12]);    
+        self.write_command(&[0x12])?;    
         thread::sleep(Duration::from_millis(10));
-        self.wait_for_idle();
+        self.wait_for_idle()?;
+
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn display_alternating_pixel_grid(&mut self) {
-        self.wait_for_idle();
-        println!("displaying alternating pixel grid");
+    pub fn display_alternating_pixel_grid(&mut self) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
 
 
-        self.write_command(&[0x10]);
+        self.write_command(&[0x10])?;
         for _ in 0..296/2 {
             for _ in 0..128/8 {
-                self.write_data(&[0b01010101]);
+                self.write_data(&[0b01010101])?;
             }
             for _ in 0..128/8 {
-                self.write_data(&[0b10101010]);
+                self.write_data(&[0b10101010])?;
             }
         }
-        self.write_command(&[0x13]);
+        self.write_command(&[0x13])?;
         for _ in 0..296/2 {
             for _ in 0..128/8 {
-                self.write_data(&[0b01010101]);
+                self.write_data(&[0b01010101])?;
             }
             for _ in 0..128/8 {
-                self.write_data(&[0b10101010]);
+                self.write_data(&[0b10101010])?;
             }
         }
     
-        self.write_command(&[0x12]);    
+        self.write_command(&[0x12])?;    
         thread::sleep(Duration::from_millis(10));
-        self.wait_for_idle();
+        self.wait_for_idle()?;
+
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn display_random_static(&mut self) {
-        self.wait_for_idle();
-        println!("displaying alternating pixel grid");
+    pub fn display_random_static(&mut self) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
 
         let mut rng = rand::thread_rng();
 
-        self.write_command(&[0x10]);
+        self.write_command(&[0x10])?;
         for _ in 0..296 {
             for _ in 0..128/8 {
-                self.write_data(&[rng.gen()]);
+                self.write_data(&[rng.gen()])?;
             }
         }
-        self.write_command(&[0x13]);
+        self.write_command(&[0x13])?;
         for _ in 0..296 {
             for _ in 0..128/8 {
-                self.write_data(&[rng.gen()]);
+                self.write_data(&[rng.gen()])?;
             }
         }
     
-        self.write_command(&[0x12]);    
+        self.write_command(&[0x12])?;    
         thread::sleep(Duration::from_millis(10));
-        self.wait_for_idle();
+        self.wait_for_idle()?;
+
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn display_four_color_image(&mut self) {
-        self.wait_for_idle();
-        println!("displaying 4 color grayscale image");
+    pub fn display_four_color_image(&mut self) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
 
-        self.load_lut(get_4_grayscale_lut());
+        self.load_grayscale_lut()?;
 
-        self.write_command(&[0x10]);
+        self.write_command(&[0x10])?;
         for _ in 0..(4736/4) {
-            self.write_data(&[0xff]);
+            self.write_data(&[0xff])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0xff]);
+            self.write_data(&[0xff])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0x00]);
+            self.write_data(&[0x00])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0x00]);
+            self.write_data(&[0x00])?;
         }
 
-        self.write_command(&[0x13]);
+        self.write_command(&[0x13])?;
         for _ in 0..(4736/4) {
-            self.write_data(&[0xff]);
+            self.write_data(&[0xff])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0x00]);
+            self.write_data(&[0x00])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0xff]);
+            self.write_data(&[0xff])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0x00]);
+            self.write_data(&[0x00])?;
         }
     
-        self.write_command(&[0x12]);    
+        self.write_command(&[0x12])?;    
         thread::sleep(Duration::from_millis(10));
-        self.wait_for_idle();
+        self.wait_for_idle()?;
+
+        Ok(())
     }
     
     #[allow(dead_code)]
-    pub fn display_black_image(&mut self) {
-        self.display_single_color_image(true);
+    pub fn display_black_image(&mut self) -> Result<(), WyldcardError> {
+        self.display_single_color_image(true)
     }
 
     #[allow(dead_code)]
-    pub fn display_white_image(&mut self) {
-        self.display_single_color_image(false);
+    pub fn display_white_image(&mut self) -> Result<(), WyldcardError> {
+        self.display_single_color_image(false)
+    }
+
+    pub fn display_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        self.load_image(buf)?;
+        self.begin_update()?;
+        self.wait_for_idle()?;
+
+        Ok(())
     }
 
-    pub fn display_image(&mut self, buf: Vec<u8>) {
-        self.wait_for_idle();
-        println!("displaying image from input buffer");
+    // write the image and the grayscale waveform to the panel, ready for the next refresh
+    pub fn load_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        check_image_size(&buf, self.capabilities().image_size(ColorResolution::FourColorGrayscale))?;
+        self.wait_for_idle()?;
 
         let mut old_data: [u8; 4736] = [0; 4736];
         let mut new_data: [u8; 4736] = [0; 4736];
@@ -279,59 +338,81 @@ impl<SPI, RESET, BUSY, DC, CS, E> GDEW029T5DController<SPI, RESET, BUSY, DC, CS>
             let mut odd_bits: u8 = 0;
             let mut even_bits: u8 = 0;
 
-            odd_bits = odd_bits | (sixteen_bits as u8 & 0b0000_0001);
-            sixteen_bits = sixteen_bits >> 1;
-            even_bits = even_bits | (sixteen_bits as u8 & 0b0000_0001);
-            sixteen_bits = sixteen_bits >> 1;
+            odd_bits |= sixteen_bits as u8 & 0b0000_0001;
+            sixteen_bits >>= 1;
+            even_bits |= sixteen_bits as u8 & 0b0000_0001;
+            sixteen_bits >>= 1;
             for _i in 0..7 {
-                odd_bits = odd_bits << 1;
-                odd_bits = odd_bits | (sixteen_bits as u8 & 0b0000_0001);
-                sixteen_bits = sixteen_bits >> 1;
-                even_bits = even_bits << 1;
-                even_bits = even_bits | (sixteen_bits as u8 & 0b0000_0001);
-                sixteen_bits = sixteen_bits >> 1;
+                odd_bits <<= 1;
+                odd_bits |= sixteen_bits as u8 & 0b0000_0001;
+                sixteen_bits >>= 1;
+                even_bits <<= 1;
+                even_bits |= sixteen_bits as u8 & 0b0000_0001;
+                sixteen_bits >>= 1;
             }
 
             old_data[i/2] = odd_bits;
             new_data[i/2] = even_bits; 
         }
         
-        self.load_lut(get_4_grayscale_lut());
+        self.load_grayscale_lut()?;
 
-        self.write_command_with_data(&[0x10], &old_data);
+        self.write_command_with_data(&[0x10], &old_data)?;
 
-        self.write_command_with_data(&[0x13], &new_data);
-    
-        self.write_command(&[0x12]);
+        self.write_command_with_data(&[0x13], &new_data)?;
+
+        Ok(())
+    }
+
+    // start refreshing the display. The panel stays busy until it's done
+    pub fn begin_update(&mut self) -> Result<(), WyldcardError> {
+        self.write_command(&[0x12])?;
         thread::sleep(Duration::from_millis(10));
-        self.wait_for_idle();
+
+        Ok(())
+    }
+
+    // the custom waveform if one was given, otherwise the usual one
+    fn load_grayscale_lut(&mut self) -> Result<(), WyldcardError> {
+        let lut = match &self.lut {
+            Some(lut) => WaveformLut::from_bytes(lut),
+            None => get_4_grayscale_lut(),
+        };
+        self.load_lut(lut)
     }
 
-    fn load_lut(&mut self, lut: WaveformLut) {
-        self.write_command_with_data(&[0x20], &lut.vcom);
+    fn load_lut(&mut self, lut: WaveformLut) -> Result<(), WyldcardError> {
+        self.write_command_with_data(&[0x20], &lut.vcom)?;
+
+        self.write_command_with_data(&[0x21], &lut.ww)?;
 
-        self.write_command_with_data(&[0x21], &lut.ww);
+        self.write_command_with_data(&[0x22], &lut.bw)?;
 
-        self.write_command_with_data(&[0x22], &lut.bw);
+        self.write_command_with_data(&[0x23], &lut.wb)?;
 
-        self.write_command_with_data(&[0x23], &lut.wb);
+        self.write_command_with_data(&[0x24], &lut.bb)?;
 
-        self.write_command_with_data(&[0x24], &lut.bb);
+        Ok(())
     }
 
-    pub fn sleep(&mut self) {
-        self.wait_for_idle();
-        println!("putting display to sleep");
-        self.write_command_with_data(&[0x50], &[0xf7]);
+    pub fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.busy_timeout = timeout;
+    }
+
+    pub fn sleep(&mut self) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
+        self.write_command_with_data(&[0x50], &[0xf7])?;
     
-        self.write_command(&[0x02]);
-        self.wait_for_idle();
-        self.write_command_with_data(&[0x07], &[0xA5]);
+        self.write_command(&[0x02])?;
+        self.wait_for_idle()?;
+        self.write_command_with_data(&[0x07], &[0xA5])?;
+
+        Ok(())
     }
 
-    pub fn new(reset: RESET, busy: BUSY, data_or_command: DC, mut chip_select: CS, spi: SPI) -> Result<Self, E> {
+    pub fn new(reset: RESET, busy: BUSY, data_or_command: DC, mut chip_select: CS, spi: SPI) -> Result<Self, WyldcardError> {
         
-        chip_select.set_high();
+        chip_select.set_high().map_err(WyldcardError::bus)?;
 
         let display = GDEW029T5DController {
             reset,
@@ -339,12 +420,74 @@ impl<SPI, RESET, BUSY, DC, CS, E> GDEW029T5DController<SPI, RESET, BUSY, DC, CS>
             data_or_command,
             chip_select,
             spi,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
+            lut: None,
         };
 
         Ok(display)
     }
 }
 
+
+impl<SPI, RESET, BUSY, DC, CS, E> EpdDriver for GDEW029T5DController<SPI, RESET, BUSY, DC, CS>
+    where SPI: Transfer<u8, Error = E>,
+          RESET: OutputPin,
+          RESET::Error: std::fmt::Debug,
+          BUSY: InputPin,
+          BUSY::Error: std::fmt::Debug,
+          DC: OutputPin,
+          DC::Error: std::fmt::Debug,
+          CS: OutputPin,
+          CS::Error: std::fmt::Debug,
+          E: std::fmt::Debug {
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            width: 128,
+            height: 296,
+            gray_levels: 4,
+            monochrome: false,
+            partial_refresh: false,
+        }
+    }
+
+    fn start_epd(&mut self) -> Result<(), WyldcardError> {
+        self.start_epd()
+    }
+
+    fn display_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        self.display_image(buf)
+    }
+
+    fn load_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        self.load_image(buf)
+    }
+
+    fn begin_update(&mut self) -> Result<(), WyldcardError> {
+        self.begin_update()
+    }
+
+    fn display_black_image(&mut self) -> Result<(), WyldcardError> {
+        self.display_black_image()
+    }
+
+    fn display_white_image(&mut self) -> Result<(), WyldcardError> {
+        self.display_white_image()
+    }
+
+    fn sleep(&mut self) -> Result<(), WyldcardError> {
+        self.sleep()
+    }
+
+    fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.set_busy_timeout(timeout)
+    }
+
+    fn set_lut(&mut self, lut: Option<Vec<u8>>) {
+        self.lut = lut;
+    }
+}
+
 fn get_4_grayscale_lut() -> WaveformLut {
     WaveformLut{
         vcom: [
@@ -394,4 +537,45 @@ fn get_4_grayscale_lut() -> WaveformLut {
             0x00	,0x00	,0x00	,0x00	,0x00	,0x00,                
         ]
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epd::fakes::{ FakePin, FakeSpi };
+
+    // unlike the GDEY029T94, the busy pin is low while the panel is working
+    fn controller(busy: bool, spi: FakeSpi) -> GDEW029T5DController<FakeSpi, FakePin, FakePin, FakePin, FakePin> {
+        GDEW029T5DController::new(FakePin::default(), FakePin::reading(!busy), FakePin::default(), FakePin::default(), spi).unwrap()
+    }
+
+    #[test]
+    fn a_panel_which_stays_busy_times_out() {
+        let mut display = controller(true, FakeSpi::default());
+        display.set_busy_timeout(Duration::from_millis(30));
+        assert!(matches!(display.start_epd(), Err(WyldcardError::BusyTimeout(timeout)) if timeout == Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn failing_pins_are_bus_errors() {
+        let failing_chip_select = GDEW029T5DController::new(FakePin::default(), FakePin::reading(true), FakePin::default(), FakePin::failing(), FakeSpi::default());
+        assert!(matches!(failing_chip_select, Err(WyldcardError::BusError(_))));
+
+        let mut failing_reset = GDEW029T5DController::new(FakePin::failing(), FakePin::reading(true), FakePin::default(), FakePin::default(), FakeSpi::default()).unwrap();
+        assert!(matches!(failing_reset.start_epd(), Err(WyldcardError::BusError(_))));
+
+        let mut failing_busy = GDEW029T5DController::new(FakePin::default(), FakePin::failing(), FakePin::default(), FakePin::default(), FakeSpi::default()).unwrap();
+        assert!(matches!(failing_busy.sleep(), Err(WyldcardError::BusError(_))));
+    }
+
+    #[test]
+    fn a_failing_spi_transfer_is_a_bus_error() {
+        let mut display = controller(false, FakeSpi { fails: true, ..FakeSpi::default() });
+        assert!(matches!(display.start_epd(), Err(WyldcardError::BusError(_))));
+    }
+
+    #[test]
+    fn images_of_the_wrong_size_are_invalid() {
+        let mut display = controller(false, FakeSpi::default());
+        assert!(matches!(display.display_image(vec![0; 10]), Err(WyldcardError::InvalidImage(_))));
+    }
+}
@@ -1,11 +1,11 @@
-use std::io::{ Error };
 use std::thread;
 use std::time::{Duration, Instant};
 
 use embedded_hal::digital::v2::{ InputPin, OutputPin };
 use embedded_hal::blocking::spi::Transfer;
 
-const IDLE_TIMEOUT: Duration = Duration::new(6, 0);
+use crate::epd::{ check_image_size, Capabilities, ColorResolution, EpdDriver, Window, DEFAULT_BUSY_TIMEOUT };
+use crate::error::WyldcardError;
 
 // resolution: 128 x 296
 pub struct GDEY029T94Controller<SPI, RESET, BUSY, DC, CS> {
@@ -14,268 +14,433 @@ pub struct GDEY029T94Controller<SPI, RESET, BUSY, DC, CS> {
     data_or_command: DC,
     chip_select: CS,
     spi: SPI,
+    busy_timeout: Duration,
     color_resolution: ColorResolution,
+    lut: Option<Vec<u8>>, // replaces the 4 color grayscale waveform
+    temperature_luts: Vec<(f32, Vec<u8>)>, // picked from by temperature when there's no `lut`
+    data_line_readable: bool, // whether the temperature can be read back, see `set_data_line_readable()`
 }
 
 struct WaveformLut {
     lut: [u8; 153],
 }
 
-enum ColorResolution {
-    BlackAndWhiteMonochrome,
-    FourColorGrayscale,
+pub const LUT_SIZE: usize = 153;
+
+// the waveform the 0x32 command takes: 5 groups of 12 bytes choosing the voltage for each phase, then 12 groups
+// of 7 timing bytes (phase A and B lengths, A/B repeats, phase C and D lengths, C/D repeats, group repeats),
+// then 6 frame rate bytes and 3 gate scan bytes
+pub fn check_lut(lut: &[u8]) -> Result<(), WyldcardError> {
+    if lut.len() != LUT_SIZE {
+        return Err(WyldcardError::InvalidArgument(format!("GDEY029T94 waveforms are {} bytes, not {}", LUT_SIZE, lut.len())));
+    }
+
+    let has_phases = lut[60..144].chunks(7).any(|group| [group[0], group[1], group[3], group[4]].iter().any(|length| *length > 0));
+    if !has_phases {
+        return Err(WyldcardError::InvalidArgument(String::from("waveform has no phases, so it would never change the screen")));
+    }
+    Ok(())
+}
+
+pub fn default_lut() -> Vec<u8> {
+    get_4_grayscale_lut().lut.to_vec()
 }
 
 impl<SPI, RESET, BUSY, DC, CS, E> GDEY029T94Controller<SPI, RESET, BUSY, DC, CS>
     where SPI: Transfer<u8, Error = E>,
           RESET: OutputPin,
+          RESET::Error: std::fmt::Debug,
           BUSY: InputPin,
           BUSY::Error: std::fmt::Debug,
           DC: OutputPin,
+          DC::Error: std::fmt::Debug,
           CS: OutputPin,
+          CS::Error: std::fmt::Debug,
           E: std::fmt::Debug {
             
-    fn reset_ic(&mut self) {
-        println!("toggling hardware reset pin");
-        self.reset.set_low();
+    fn reset_ic(&mut self) -> Result<(), WyldcardError> {
+        self.reset.set_low().map_err(WyldcardError::bus)?;
         thread::sleep(Duration::from_millis(10));
-        self.reset.set_high();
+        self.reset.set_high().map_err(WyldcardError::bus)?;
         thread::sleep(Duration::from_millis(10));
+
+        Ok(())
     }
 
-    fn wait_for_idle(&mut self) -> Duration {
-        println!("waiting for idle signal");
+    fn wait_for_idle(&mut self) -> Result<Duration, WyldcardError> {
         const IDLE_CHECK_FREQUENCY: Duration = Duration::from_millis(10);
         let start = Instant::now();
-        while self.busy.is_high().expect("read busy pin") && start.elapsed() < IDLE_TIMEOUT {
+        while self.busy.is_high().map_err(WyldcardError::bus)? {
+            if start.elapsed() > self.busy_timeout {
+                return Err(WyldcardError::BusyTimeout(self.busy_timeout));
+            }
             thread::sleep(IDLE_CHECK_FREQUENCY);
         }
-        let wait_duration = start.elapsed();
-        println!("epd was busy for {:?}", wait_duration);
-        wait_duration
+        Ok(start.elapsed())
     }
     
-    fn write_command(&mut self, command: &[u8]) {
+    fn write_command(&mut self, command: &[u8]) -> Result<(), WyldcardError> {
         let command: &mut [u8] = &mut command.to_owned();
-        self.chip_select.set_low();
+        self.chip_select.set_low().map_err(WyldcardError::bus)?;
 
-        self.data_or_command.set_low();
-        self.spi.transfer(command).expect("error with spi write");
+        self.data_or_command.set_low().map_err(WyldcardError::bus)?;
+        self.spi.transfer(command).map_err(WyldcardError::bus)?;
 
-        self.chip_select.set_high();
+        self.chip_select.set_high().map_err(WyldcardError::bus)?;
+
+        Ok(())
     }
     
-    fn write_data(&mut self, data: &[u8]) {
+    fn write_data(&mut self, data: &[u8]) -> Result<(), WyldcardError> {
         let data = &mut data.to_owned();
-        self.chip_select.set_low();
+        self.chip_select.set_low().map_err(WyldcardError::bus)?;
 
-        self.data_or_command.set_high();
-        self.spi.transfer(data).expect("error with spi write");
+        self.data_or_command.set_high().map_err(WyldcardError::bus)?;
+        self.spi.transfer(data).map_err(WyldcardError::bus)?;
 
-        self.chip_select.set_high();
+        self.chip_select.set_high().map_err(WyldcardError::bus)?;
+
+        Ok(())
     }
 
     // sends a command, followed by data. This uses one less toggle of the Chip Select gpio
     // which increases our performance (since we're behind a gpio expander)
-    fn write_command_with_data(&mut self, command: &[u8], data: &[u8]) {
+    fn write_command_with_data(&mut self, command: &[u8], data: &[u8]) -> Result<(), WyldcardError> {
         let command = &mut command.to_owned();
         let data = &mut data.to_owned();
-        self.chip_select.set_low();
+        self.chip_select.set_low().map_err(WyldcardError::bus)?;
+
+        self.data_or_command.set_low().map_err(WyldcardError::bus)?;
+        self.spi.transfer(command).map_err(WyldcardError::bus)?;
 
-        self.data_or_command.set_low();
-        self.spi.transfer(command).expect("error with spi write");
+        self.data_or_command.set_high().map_err(WyldcardError::bus)?;
+        self.spi.transfer(data).map_err(WyldcardError::bus)?;
 
-        self.data_or_command.set_high();
-        self.spi.transfer(data).expect("error with spi write");
+        self.chip_select.set_high().map_err(WyldcardError::bus)?;
 
-        self.chip_select.set_high();
+        Ok(())
     }
 
-    pub fn start_epd(&mut self) -> Result<(), Error> {
+    // sends a command, then reads the controller's answer back off the data line
+    fn read_command(&mut self, command: &[u8], length: usize) -> Result<Vec<u8>, WyldcardError> {
+        let command = &mut command.to_owned();
+        let mut data = vec![0; length];
+        self.chip_select.set_low().map_err(WyldcardError::bus)?;
+
+        self.data_or_command.set_low().map_err(WyldcardError::bus)?;
+        self.spi.transfer(command).map_err(WyldcardError::bus)?;
+
+        self.data_or_command.set_high().map_err(WyldcardError::bus)?;
+        self.spi.transfer(&mut data).map_err(WyldcardError::bus)?;
+
+        self.chip_select.set_high().map_err(WyldcardError::bus)?;
+
+        Ok(data)
+    }
+
+    pub fn start_epd(&mut self) -> Result<(), WyldcardError> {
         match self.color_resolution {
             ColorResolution::BlackAndWhiteMonochrome => self.init_epd_monochrome(),
             ColorResolution::FourColorGrayscale => self.init_epd_4_color_grayscale(),
         }
     }
 
-    fn init_epd_monochrome(&mut self) -> Result<(), Error> {
+    fn init_epd_monochrome(&mut self) -> Result<(), WyldcardError> {
         // println!("i wonder if we start busy? we do!");
         // self.wait_for_idle();
 
-        self.reset_ic();
-        self.wait_for_idle();
+        self.reset_ic()?;
+        self.wait_for_idle()?;
 
-        self.write_command(&[0x12]);  //software reset
-        self.wait_for_idle();
+        self.write_command(&[0x12])?;  //software reset
+        self.wait_for_idle()?;
 
-        self.write_command(&[0x01]); //Driver output control
-        self.write_data(&[0x27]); //TODO these can be combined into one data write?
-        self.write_data(&[0x01]);
-        self.write_data(&[0x00]); // number of MUX lines, how they're arranged...
+        self.write_command(&[0x01])?; //Driver output control
+        self.write_data(&[0x27])?; //TODO these can be combined into one data write?
+        self.write_data(&[0x01])?;
+        self.write_data(&[0x00])?; // number of MUX lines, how they're arranged...
 
-        self.write_command_with_data(&[0x11], &[0x01]); // data entry mode (decrement ram pixel counter, zigzag)
+        // RAM is addressed the same way as in 4 color grayscale mode, so an image is drawn the same way up in either mode
+        self.write_command_with_data(&[0x11], &[0x03])?; // data entry mode
 
-        self.write_command(&[0x44]); // set RAM X address start/end position
-        self.write_data(&[0x00]);
-        self.write_data(&[0x0F]);    // #0x0F-->(15+1)*8=128
+        self.write_command(&[0x44])?; // set RAM X address start/end position
+        self.write_data(&[0x00])?;
+        self.write_data(&[0x0F])?;    // #0x0F-->(15+1)*8=128
 
-        self.write_command(&[0x45]); // set RAM Y address start/end position
-        self.write_data(&[0x27]);    // 0x0127-->(295+1)=296
-        self.write_data(&[0x01]);
-        self.write_data(&[0x00]);    // 0x0127-->(295+1)=296
-        self.write_data(&[0x00]);
+        self.write_command(&[0x45])?; // set RAM Y address start/end position
+        self.write_data(&[0x00])?;    // 0x0127-->(295+1)=296
+        self.write_data(&[0x00])?;
+        self.write_data(&[0x27])?;    // 0x0127-->(295+1)=296
+        self.write_data(&[0x01])?;
 
-        self.write_command_with_data(&[0x3C], &[0x05]);  // border waveform, follow LUT0
+        self.write_command_with_data(&[0x3C], &[0x05])?;  // border waveform, follow LUT0
 
-        self.write_command(&[0x21]); // settings for updating the display, "Update Display Control". normal black and white and red, source from S8 to S167
-        self.write_data(&[0x00]);
-        self.write_data(&[0x80]);
+        self.write_command(&[0x21])?; // settings for updating the display, "Update Display Control". normal black and white and red, source from S8 to S167
+        self.write_data(&[0x00])?;
+        self.write_data(&[0x80])?;
 
-        self.write_command_with_data(&[0x18], &[0x80]); // read temperature from built-in temperature sensor
+        self.write_command_with_data(&[0x18], &[0x80])?; // read temperature from built-in temperature sensor
 
-        self.write_command(&[0x4E]); // set RAM x address count to 0
-        self.write_data(&[0x00]);
+        self.write_command(&[0x4E])?; // set RAM x address count to 0
+        self.write_data(&[0x00])?;
 
-        self.write_command(&[0x4F]); // set RAM y address count to 0x127
-        self.write_data(&[0x27]);
-        self.write_data(&[0x01]);
+        self.write_command(&[0x4F])?; // set RAM y address count to 0x127
+        self.write_data(&[0x27])?;
+        self.write_data(&[0x01])?;
 
-        self.wait_for_idle();
+        self.wait_for_idle()?;
 
         Ok(())
     }
 
-    fn init_epd_4_color_grayscale(&mut self) -> Result<(), Error> {
+    fn init_epd_4_color_grayscale(&mut self) -> Result<(), WyldcardError> {
         // println!("i wonder if we start busy? we do!");
         // self.wait_for_idle();
 
-        self.reset_ic();
-        self.wait_for_idle();
+        self.reset_ic()?;
+        self.wait_for_idle()?;
 
-        self.write_command(&[0x12]);  //software reset
-        self.wait_for_idle();
+        self.write_command(&[0x12])?;  //software reset
+        self.wait_for_idle()?;
 
         // these commands are undocumented and may or may not do anything
-        self.write_command(&[0x74]); // set analog block control
-        self.write_data(&[0x54]);
-        self.write_command(&[0x7E]); // set digital block control
-        self.write_data(&[0x3B]);
+        self.write_command(&[0x74])?; // set analog block control
+        self.write_data(&[0x54])?;
+        self.write_command(&[0x7E])?; // set digital block control
+        self.write_data(&[0x3B])?;
+
+        self.write_command(&[0x01])?; //Driver output control
+        self.write_data(&[0x27])?; //TODO these can be combined into one data write?
+        self.write_data(&[0x01])?;
+        self.write_data(&[0x00])?; // number of MUX lines, how they're arranged...
+
+        self.write_command_with_data(&[0x11], &[0x03])?; // data entry mode (decrement ram pixel counter, zigzag)
+
+        self.write_command(&[0x44])?; // set RAM X address start/end position
+        self.write_data(&[0x00])?;
+        self.write_data(&[0x0F])?;    // #0x0F-->(15+1)*8=128
+
+        self.write_command(&[0x45])?; // set RAM Y address start/end position
+        self.write_data(&[0x00])?;    // 0x0127-->(295+1)=296
+        self.write_data(&[0x00])?;
+        self.write_data(&[0x27])?;    // 0x0127-->(295+1)=296
+        self.write_data(&[0x01])?;
+
+        self.write_command_with_data(&[0x3C], &[0x00])?;  // border waveform
+
+        self.write_command_with_data(&[0x2C], &[0x30])?;  // VCOM Voltage
+
+        self.write_command_with_data(&[0x3F], &[0x22])?;  // EOPQ, Option for LUT end: 'normal'
+
+        self.write_command_with_data(&[0x03], &[0x15])?;  // VGH, 19V
+
+        self.write_command(&[0x04])?;  // source driving voltage control
+        self.write_data(&[0x41])?;     // VSH1: 15V
+        self.write_data(&[0xA8])?;     // VSH2: 5V
+        self.write_data(&[0x32])?;     // VSL : -15V
+
+        let lut = self.grayscale_lut()?;
+        self.write_command(&[0x32])?;  // write LUT
+        self.write_data(&lut)?;
+
+        self.write_command(&[0x21])?; // settings for updating the display, "Update Display Control"
+        self.write_data(&[0x88])?;    // invert black and white, invert red
+        self.write_data(&[0x80])?;    // source from S8 to S167
+
+        self.write_command_with_data(&[0x18], &[0x80])?; // read temperature from built-in temperature sensor
+
+        self.write_command(&[0x4E])?; // set RAM x address count to 0
+        self.write_data(&[0x00])?;
+
+        self.write_command(&[0x4F])?; // set RAM y address count to 0x127
+        self.write_data(&[0x27])?;
+        self.write_data(&[0x01])?;
+
+        self.wait_for_idle()?;
+
+        Ok(())
+    }
+
+    // the waveform for a 4 color grayscale refresh: the one set with `set_lut()`, otherwise the temperature waveform
+    // for how warm the panel is right now, otherwise the built in one. Needs the controller awake
+    fn grayscale_lut(&mut self) -> Result<Vec<u8>, WyldcardError> {
+        if let Some(lut) = &self.lut {
+            return Ok(lut.clone());
+        }
+        if self.temperature_luts.is_empty() {
+            return Ok(default_lut());
+        }
+
+        // a temperature which couldn't be read gets the built in waveform, which suits any temperature well enough
+        let lut = self.sense_temperature()?.and_then(|temperature| pick_temperature_lut(&self.temperature_luts, temperature));
+        Ok(lut.map_or_else(default_lut, |lut| lut.to_vec()))
+    }
+
+    // measures the temperature with the built in sensor, in degrees C. Needs the controller awake. None if the
+    // register couldn't be read back, see `temperature_from_register()`, and the sensor isn't run at all when it
+    // can't be
+    fn sense_temperature(&mut self) -> Result<Option<f32>, WyldcardError> {
+        if !self.data_line_readable {
+            return Ok(None);
+        }
+        self.write_command_with_data(&[0x18], &[0x80])?; // read temperature from built-in temperature sensor
+        self.write_command_with_data(&[0x22], &[0xA1])?; // update control: enable clock signal, load temperature value, disable OSC
+        self.write_command(&[0x20])?; // Master Activation, run the sequence
+        self.wait_for_idle()?;
+
+        let register = self.read_command(&[0x1B], 2)?; // read temperature register
+        Ok(temperature_from_register(register[0], register[1]))
+    }
+
+    // wakes the controller up to read its temperature, then puts it back into deep sleep, so it needs starting again
+    // before it's drawn on. None if the temperature couldn't be read, without waking it when it can't be
+    pub fn read_temperature(&mut self) -> Result<Option<f32>, WyldcardError> {
+        if !self.data_line_readable {
+            return Ok(None);
+        }
+        self.reset_ic()?;
+        self.wait_for_idle()?;
+
+        self.write_command(&[0x12])?;  //software reset
+        self.wait_for_idle()?;
+
+        let temperature = self.sense_temperature()?;
+        self.sleep()?;
+        Ok(temperature)
+    }
 
-        self.write_command(&[0x01]); //Driver output control
-        self.write_data(&[0x27]); //TODO these can be combined into one data write?
-        self.write_data(&[0x01]);
-        self.write_data(&[0x00]); // number of MUX lines, how they're arranged...
+    // black and white only, using the fast partial update waveform. Leaves the RAM window for the caller to set
+    fn init_epd_partial(&mut self) -> Result<(), WyldcardError> {
+        self.reset_ic()?;
+        self.wait_for_idle()?;
 
-        self.write_command_with_data(&[0x11], &[0x03]); // data entry mode (decrement ram pixel counter, zigzag)
+        self.write_command(&[0x12])?;  //software reset
+        self.wait_for_idle()?;
 
-        self.write_command(&[0x44]); // set RAM X address start/end position
-        self.write_data(&[0x00]);
-        self.write_data(&[0x0F]);    // #0x0F-->(15+1)*8=128
+        self.write_command(&[0x01])?; //Driver output control
+        self.write_data(&[0x27])?;
+        self.write_data(&[0x01])?;
+        self.write_data(&[0x00])?;
 
-        self.write_command(&[0x45]); // set RAM Y address start/end position
-        self.write_data(&[0x00]);    // 0x0127-->(295+1)=296
-        self.write_data(&[0x00]);
-        self.write_data(&[0x27]);    // 0x0127-->(295+1)=296
-        self.write_data(&[0x01]);
+        self.write_command_with_data(&[0x11], &[0x03])?; // data entry mode, same orientation as the 4 color grayscale mode
 
-        self.write_command_with_data(&[0x3C], &[0x00]);  // border waveform
+        self.write_command_with_data(&[0x3C], &[0x80])?;  // border waveform, keep the border as it is
 
-        self.write_command_with_data(&[0x2C], &[0x30]);  // VCOM Voltage
+        self.write_command(&[0x32])?;  // write LUT
+        self.write_data(&get_partial_lut().lut)?;
 
-        self.write_command_with_data(&[0x3F], &[0x22]);  // EOPQ, Option for LUT end: 'normal'
+        self.write_command_with_data(&[0x3F], &[0x22])?;  // EOPQ, Option for LUT end: 'normal'
+        self.write_command_with_data(&[0x03], &[0x17])?;  // VGH, 20V
 
-        self.write_command_with_data(&[0x03], &[0x15]);  // VGH, 19V
+        self.write_command(&[0x04])?;  // source driving voltage control
+        self.write_data(&[0x41])?;     // VSH1: 15V
+        self.write_data(&[0xB0])?;     // VSH2: 5.8V
+        self.write_data(&[0x32])?;     // VSL : -15V
 
-        self.write_command(&[0x04]);  // source driving voltage control
-        self.write_data(&[0x41]);     // VSH1: 15V
-        self.write_data(&[0xA8]);     // VSH2: 5V
-        self.write_data(&[0x32]);     // VSL : -15V
+        self.write_command_with_data(&[0x2C], &[0x36])?;  // VCOM Voltage
 
-        self.write_command(&[0x32]);  // write LUT
-        self.write_data(&get_4_grayscale_lut().lut);
+        self.write_command(&[0x37])?;  // display option: select display mode 2, used for partial updates
+        self.write_data(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00])?;
 
-        self.write_command(&[0x21]); // settings for updating the display, "Update Display Control"
-        self.write_data(&[0x88]);    // invert black and white, invert red
-        self.write_data(&[0x80]);    // source from S8 to S167
+        self.write_command(&[0x21])?; // settings for updating the display, "Update Display Control"
+        self.write_data(&[0x00])?;    // normal RAM contents, no inversion
+        self.write_data(&[0x80])?;    // source from S8 to S167
 
-        self.write_command_with_data(&[0x18], &[0x80]); // read temperature from built-in temperature sensor
+        self.wait_for_idle()?;
 
-        self.write_command(&[0x4E]); // set RAM x address count to 0
-        self.write_data(&[0x00]);
+        Ok(())
+    }
+
+    // restrict RAM reads and writes to the window, and move the address counters to its first pixel
+    fn set_ram_window(&mut self, window: &Window) -> Result<(), WyldcardError> {
+        let x_start = (window.x / 8) as u8;
+        let x_end = ((window.x + window.width - 1) / 8) as u8;
+        let y_start = window.y;
+        let y_end = window.y + window.height - 1;
+
+        self.write_command_with_data(&[0x44], &[x_start, x_end])?; // set RAM X address start/end position
+        self.write_command_with_data(&[0x45], &[y_start as u8, (y_start >> 8) as u8, y_end as u8, (y_end >> 8) as u8])?; // set RAM Y address start/end position
 
-        self.write_command(&[0x4F]); // set RAM y address count to 0x127
-        self.write_data(&[0x27]);
-        self.write_data(&[0x01]);
+        self.write_command_with_data(&[0x4E], &[x_start])?; // set RAM x address count
+        self.write_command_with_data(&[0x4F], &[y_start as u8, (y_start >> 8) as u8])?; // set RAM y address count
+
+        Ok(())
+    }
 
-        self.wait_for_idle();
+    fn update_display(&mut self) -> Result<(), WyldcardError> {
+        self.begin_update()?;
+        self.wait_for_idle()?;
 
         Ok(())
     }
 
-    fn update_display(&mut self) {
+    // start the update sequence, displaying contents of EPD RAM. The panel stays busy until it's done
+    pub fn begin_update(&mut self) -> Result<(), WyldcardError> {
         match self.color_resolution {
-            ColorResolution::BlackAndWhiteMonochrome => self.update_display_monochrome(),
-            ColorResolution::FourColorGrayscale => self.update_display_4_color_grayscale(),
+            ColorResolution::BlackAndWhiteMonochrome => self.begin_update_monochrome(),
+            ColorResolution::FourColorGrayscale => self.begin_update_4_color_grayscale(),
         }
     }
 
-    // run the update sequence, displaying contents of EPD RAM
-    fn update_display_monochrome(&mut self) {
-        println!("updating display, using contents of RAM");
+    fn begin_update_monochrome(&mut self) -> Result<(), WyldcardError> {
+        self.write_command_with_data(&[0x22], &[0xF7])?; // update control: enable clock signal, enable analog, load temperature value, display with DISPLAY Mode 1, disable analog, disable OSC
+        self.write_command(&[0x20])?; // Master Activation, activate display update sequence
 
-        self.write_command_with_data(&[0x22], &[0xF7]); // update control: enable clock signal, enable analog, load temperature value, display with DISPLAY Mode 1, disable analog, disable OSC
-        self.write_command(&[0x20]); // Master Activation, activate display update sequence
+        Ok(())
+    }
 
-        self.wait_for_idle();
+    fn begin_update_4_color_grayscale(&mut self) -> Result<(), WyldcardError> {
+        self.write_command_with_data(&[0x22], &[0xC7])?; // update control: enable clock signal, enable analog, load temperature value, display with DISPLAY Mode 1, disable analog, disable OSC
+        self.write_command(&[0x20])?; // Master Activation, activate display update sequence
+
+        Ok(())
     }
 
-    // run the update sequence, displaying contents of EPD RAM
-    fn update_display_4_color_grayscale(&mut self) {
-        println!("updating display, using contents of RAM");
+    // run the update sequence for a partial update. The controller compares the Black and White RAM with
+    // the Red RAM, which holds the previous frame, and only drives pixels which changed
+    fn update_display_partial(&mut self) -> Result<(), WyldcardError> {
+        self.write_command_with_data(&[0x22], &[0xCF])?; // update control: enable clock signal, enable analog, display with DISPLAY Mode 2, disable analog, disable OSC
+        self.write_command(&[0x20])?; // Master Activation, activate display update sequence
 
-        self.write_command_with_data(&[0x22], &[0xC7]); // update control: enable clock signal, enable analog, load temperature value, display with DISPLAY Mode 1, disable analog, disable OSC
-        self.write_command(&[0x20]); // Master Activation, activate display update sequence
+        self.wait_for_idle()?;
 
-        self.wait_for_idle();
+        Ok(())
     }
 
     #[allow(dead_code)]
-    fn display_single_color_image(&mut self, black: bool) {
-        println!("displaying single color image");
-        self.wait_for_idle();
+    fn display_single_color_image(&mut self, black: bool) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
         
 
-        let pixel: &[u8];
-
-        match black {
-            true => { pixel = &[0x00]},
-            false => { pixel = &[0xff]},
-        }
+        let pixel: &[u8] = if black { &[0x00] } else { &[0xff] };
 
-        self.write_command(&[0x24]);  // write to Black and White RAM. 0 black, 1 white
+        self.write_command(&[0x24])?;  // write to Black and White RAM. 0 black, 1 white
         for _ in 0..4736 {
-            self.write_data(pixel);
+            self.write_data(pixel)?;
         }
 
-        self.update_display();
+        self.update_display()?;
+
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn display_alternating_pixel_grid(&mut self) {
-        self.wait_for_idle();
-        println!("displaying alternating pixel grid");
+    pub fn display_alternating_pixel_grid(&mut self) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
 
-        self.write_command(&[0x24]);  // write to Black and White RAM. 0 black, 1 white
+        self.write_command(&[0x24])?;  // write to Black and White RAM. 0 black, 1 white
         for _ in 0..296/2 {
             for _ in 0..128/8 {
-                self.write_data(&[0b01010101]);
+                self.write_data(&[0b01010101])?;
             }
             for _ in 0..128/8 {
-                self.write_data(&[0b10101010]);
+                self.write_data(&[0b10101010])?;
             }
         }
         
-        self.update_display();
+        self.update_display()?;
+
+        Ok(())
     }
 
     #[allow(dead_code)]
@@ -304,54 +469,78 @@ impl<SPI, RESET, BUSY, DC, CS, E> GDEY029T94Controller<SPI, RESET, BUSY, DC, CS>
     }
 
     #[allow(dead_code)]
-    pub fn display_four_color_image(&mut self) {
-        self.wait_for_idle();
-        println!("displaying 4 color grayscale image");
+    pub fn display_four_color_image(&mut self) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
 
-        self.write_command(&[0x24]);  // write to Black and White RAM
+        self.write_command(&[0x24])?;  // write to Black and White RAM
         for _ in 0..(4736/4) {
-            self.write_data(&[0xff]);
+            self.write_data(&[0xff])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0x00]);
+            self.write_data(&[0x00])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0xff]);
+            self.write_data(&[0xff])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0x00]);
+            self.write_data(&[0x00])?;
         }
 
-        self.write_command(&[0x26]);  // write to Red RAM
+        self.write_command(&[0x26])?;  // write to Red RAM
         for _ in 0..(4736/4) {
-            self.write_data(&[0xff]);
+            self.write_data(&[0xff])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0xff]);
+            self.write_data(&[0xff])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0x00]);
+            self.write_data(&[0x00])?;
         }
         for _ in 0..(4736/4) {
-            self.write_data(&[0x00]);
+            self.write_data(&[0x00])?;
         }
     
-        self.update_display();
+        self.update_display()?;
+
+        Ok(())
     }
     
     #[allow(dead_code)]
-    pub fn display_black_image(&mut self) {
-        self.display_single_color_image(true);
+    pub fn display_black_image(&mut self) -> Result<(), WyldcardError> {
+        self.display_single_color_image(true)
     }
 
     #[allow(dead_code)]
-    pub fn display_white_image(&mut self) {
-        self.display_single_color_image(false);
+    pub fn display_white_image(&mut self) -> Result<(), WyldcardError> {
+        self.display_single_color_image(false)
+    }
+
+    // the image format depends on the color resolution the display was set up with: one bit per pixel in
+    // black and white, where 1 is white, or two bits per pixel in 4 color grayscale
+    pub fn display_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        self.load_image(buf)?;
+        self.update_display()
+    }
+
+    // write the image to EPD RAM, ready for the next update
+    pub fn load_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        check_image_size(&buf, self.capabilities().image_size(self.color_resolution))?;
+        match self.color_resolution {
+            ColorResolution::BlackAndWhiteMonochrome => self.load_monochrome_image(buf),
+            ColorResolution::FourColorGrayscale => self.load_4_color_grayscale_image(buf),
+        }
+    }
+
+    fn load_monochrome_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
+
+        self.write_command_with_data(&[0x24], &buf)?;
+
+        Ok(())
     }
 
-    pub fn display_image(&mut self, buf: Vec<u8>) {
-        self.wait_for_idle();
-        println!("displaying image from input buffer");
+    fn load_4_color_grayscale_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
 
         let mut old_data: [u8; 4736] = [0; 4736];
         let mut new_data: [u8; 4736] = [0; 4736];
@@ -364,44 +553,99 @@ impl<SPI, RESET, BUSY, DC, CS, E> GDEY029T94Controller<SPI, RESET, BUSY, DC, CS>
             let mut odd_bits: u8 = 0;
             let mut even_bits: u8 = 0;
 
-            odd_bits = odd_bits | (sixteen_bits as u8 & 0b0000_0001);
-            sixteen_bits = sixteen_bits >> 1;
-            even_bits = even_bits | (sixteen_bits as u8 & 0b0000_0001);
-            sixteen_bits = sixteen_bits >> 1;
+            odd_bits |= sixteen_bits as u8 & 0b0000_0001;
+            sixteen_bits >>= 1;
+            even_bits |= sixteen_bits as u8 & 0b0000_0001;
+            sixteen_bits >>= 1;
             for _i in 0..7 {
-                odd_bits = odd_bits << 1;
-                odd_bits = odd_bits | (sixteen_bits as u8 & 0b0000_0001);
-                sixteen_bits = sixteen_bits >> 1;
-                even_bits = even_bits << 1;
-                even_bits = even_bits | (sixteen_bits as u8 & 0b0000_0001);
-                sixteen_bits = sixteen_bits >> 1;
+                odd_bits <<= 1;
+                odd_bits |= sixteen_bits as u8 & 0b0000_0001;
+                sixteen_bits >>= 1;
+                even_bits <<= 1;
+                even_bits |= sixteen_bits as u8 & 0b0000_0001;
+                sixteen_bits >>= 1;
             }
 
             old_data[i/2] = even_bits;
             new_data[i/2] = odd_bits; 
         }
 
-        self.write_command_with_data(&[0x24], &old_data);
+        self.write_command_with_data(&[0x24], &old_data)?;
 
-        self.write_command_with_data(&[0x26], &new_data);
-    
-        self.update_display();
+        self.write_command_with_data(&[0x26], &new_data)?;
+
+        Ok(())
     }
 
-    pub fn sleep(&mut self) {
-        self.wait_for_idle();
-        println!("putting display to sleep");
+    // redraw only the pixels inside the window, in black and white, without flashing the rest of the screen.
+    // Light grays become white and dark grays become black. `shown` is the image on screen now, if it's known
+    pub fn display_partial(&mut self, buf: Vec<u8>, window: Window, shown: Option<&[u8]>) -> Result<(), WyldcardError> {
+        let capabilities = self.capabilities();
+        check_image_size(&buf, capabilities.image_size(ColorResolution::FourColorGrayscale))?;
+        if let Some(shown) = shown {
+            check_image_size(shown, capabilities.image_size(ColorResolution::FourColorGrayscale))?;
+        }
+        if !window.fits(&capabilities) {
+            return Err(WyldcardError::InvalidArgument(format!("window {:?} doesn't fit on a {}x{} display", window, capabilities.width, capabilities.height)));
+        }
+        self.init_epd_partial()?;
+
+        // the update drives every pixel whose two RAMs differ, and after a grayscale refresh they hold the image's two
+        // bit planes. So both are loaded with what's on screen, in black and white, and only the window differs
+        let screen = Window { x: 0, y: 0, width: capabilities.width, height: capabilities.height };
+        let base = monochrome_region(shown.unwrap_or(&buf), &screen);
+        self.set_ram_window(&screen)?;
+        self.write_command_with_data(&[0x24], &base)?;
+        self.set_ram_window(&screen)?;
+        self.write_command_with_data(&[0x26], &base)?;
+
+        let window = window.byte_aligned();
+        let region = monochrome_region(&buf, &window);
+
+        // with nothing known about what's on screen, the window would match in both RAMs and not be drawn at all.
+        // Its inverse in the Red RAM has every pixel in it driven instead
+        if shown.is_none() {
+            let inverse: Vec<u8> = region.iter().map(|byte| !byte).collect();
+            self.set_ram_window(&window)?;
+            self.write_command_with_data(&[0x26], &inverse)?;
+        }
+
+        self.set_ram_window(&window)?;
+        self.write_command_with_data(&[0x24], &region)?;
 
-        self.write_command_with_data(&[0x10], &[0x01]);
+        self.update_display_partial()?;
+
+        // the Red RAM now needs to match what's on screen, ready for the next partial update
+        self.set_ram_window(&window)?;
+        self.write_command_with_data(&[0x26], &region)?;
+
+        Ok(())
+    }
+
+    pub fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.busy_timeout = timeout;
+    }
+
+    // takes effect the next time the display is started
+    pub fn set_color_resolution(&mut self, color_resolution: ColorResolution) {
+        self.color_resolution = color_resolution;
+    }
+
+    pub fn sleep(&mut self) -> Result<(), WyldcardError> {
+        self.wait_for_idle()?;
+
+        self.write_command_with_data(&[0x10], &[0x01])?;
 
         thread::sleep(Duration::from_millis(10));
+
+        Ok(())
     }
 
-    pub fn new(reset: RESET, busy: BUSY, data_or_command: DC, mut chip_select: CS, spi: SPI) -> Result<Self, E> {
+    pub fn new(reset: RESET, busy: BUSY, data_or_command: DC, mut chip_select: CS, spi: SPI) -> Result<Self, WyldcardError> {
         
         let color_resolution = ColorResolution::FourColorGrayscale;
 
-        chip_select.set_high();
+        chip_select.set_high().map_err(WyldcardError::bus)?;
 
         let display = GDEY029T94Controller {
             reset,
@@ -409,13 +653,164 @@ impl<SPI, RESET, BUSY, DC, CS, E> GDEY029T94Controller<SPI, RESET, BUSY, DC, CS>
             data_or_command,
             chip_select,
             spi,
+            busy_timeout: DEFAULT_BUSY_TIMEOUT,
             color_resolution,
+            lut: None,
+            temperature_luts: Vec::new(),
+            data_line_readable: false,
         };
 
         Ok(display)
     }
 }
 
+
+impl<SPI, RESET, BUSY, DC, CS, E> EpdDriver for GDEY029T94Controller<SPI, RESET, BUSY, DC, CS>
+    where SPI: Transfer<u8, Error = E>,
+          RESET: OutputPin,
+          RESET::Error: std::fmt::Debug,
+          BUSY: InputPin,
+          BUSY::Error: std::fmt::Debug,
+          DC: OutputPin,
+          DC::Error: std::fmt::Debug,
+          CS: OutputPin,
+          CS::Error: std::fmt::Debug,
+          E: std::fmt::Debug {
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            width: 128,
+            height: 296,
+            gray_levels: 4,
+            monochrome: true,
+            partial_refresh: true,
+        }
+    }
+
+    fn start_epd(&mut self) -> Result<(), WyldcardError> {
+        self.start_epd()
+    }
+
+    fn display_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        self.display_image(buf)
+    }
+
+    fn load_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError> {
+        self.load_image(buf)
+    }
+
+    fn begin_update(&mut self) -> Result<(), WyldcardError> {
+        self.begin_update()
+    }
+
+    fn display_black_image(&mut self) -> Result<(), WyldcardError> {
+        self.display_black_image()
+    }
+
+    fn display_white_image(&mut self) -> Result<(), WyldcardError> {
+        self.display_white_image()
+    }
+
+    fn sleep(&mut self) -> Result<(), WyldcardError> {
+        self.sleep()
+    }
+
+    fn set_busy_timeout(&mut self, timeout: Duration) {
+        self.set_busy_timeout(timeout)
+    }
+
+    fn display_partial(&mut self, buf: Vec<u8>, window: Window, shown: Option<&[u8]>) -> Result<(), WyldcardError> {
+        self.display_partial(buf, window, shown)
+    }
+
+    fn set_color_resolution(&mut self, color_resolution: ColorResolution) {
+        self.set_color_resolution(color_resolution)
+    }
+
+    fn set_lut(&mut self, lut: Option<Vec<u8>>) {
+        self.lut = lut;
+    }
+
+    fn read_temperature(&mut self) -> Result<Option<f32>, WyldcardError> {
+        self.read_temperature()
+    }
+
+    fn set_temperature_luts(&mut self, luts: Vec<(f32, Vec<u8>)>) {
+        self.temperature_luts = luts;
+    }
+
+    fn set_data_line_readable(&mut self, readable: bool) {
+        self.data_line_readable = readable;
+    }
+}
+
+// the temperature register holds a 12 bit two's complement count of 1/16ths of a degree, its top 8 bits in the
+// first byte and the rest at the top of the second.
+// The panel sends it back on the same data line it's written on, which the plinth's SPI bus only reads if the
+// line is wired through to MISO. Otherwise the read comes back as all zeros or all ones, and gives None. That
+// loses a true 0°C too, which only means the built in waveform is used
+fn temperature_from_register(high: u8, low: u8) -> Option<f32> {
+    if (high, low) == (0x00, 0x00) || (high, low) == (0xFF, 0xFF) {
+        return None;
+    }
+    let sixteenths = ((((high as u16) << 8) | low as u16) as i16) >> 4;
+    Some(sixteenths as f32 / 16.0)
+}
+
+// each waveform is used from its temperature up to the next one's. Below all of them, the coldest one is used
+fn pick_temperature_lut(luts: &[(f32, Vec<u8>)], temperature: f32) -> Option<&[u8]> {
+    luts.iter()
+        .filter(|(from, _)| *from <= temperature)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .or_else(|| luts.iter().min_by(|a, b| a.0.total_cmp(&b.0)))
+        .map(|(_, lut)| lut.as_slice())
+}
+
+// takes a window (aligned to whole bytes) out of a full screen 2-bit image, keeping just the high bit of each
+// pixel, which is all the black and white RAM has room for
+fn monochrome_region(buf: &[u8], window: &Window) -> Vec<u8> {
+    let mut region = Vec::with_capacity(window.width as usize / 8 * window.height as usize);
+
+    for y in window.y..window.y + window.height {
+        for x_byte in (window.x..window.x + window.width).step_by(8) {
+            let mut byte: u8 = 0;
+            for x in x_byte..x_byte + 8 {
+                let pixel_index = y as usize * 128 + x as usize;
+                let pixel = (buf[pixel_index / 4] >> (6 - 2 * (pixel_index % 4))) & 0b11;
+                byte = (byte << 1) | (pixel >> 1);
+            }
+            region.push(byte);
+        }
+    }
+
+    region
+}
+
+fn get_partial_lut() -> WaveformLut {
+    WaveformLut{
+        lut: [
+            0x00,0x40,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x80,0x80,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x40,0x40,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x80,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x0A,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x01,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x01,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x00,0x00,0x00,0x00,0x00,0x00,0x00,
+            0x22,0x22,0x22,0x22,0x22,0x22,0x00,0x00,0x00,
+        ]
+    }
+}
+
 fn get_4_grayscale_lut() -> WaveformLut {
     WaveformLut{
         lut: [
@@ -439,4 +834,227 @@ fn get_4_grayscale_lut() -> WaveformLut {
             0x22,0x22,0x22,0x22,0x22,0x22,0x00,0x00,0x00,
         ]
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::convert::Infallible;
+    use std::rc::Rc;
+    use crate::epd::fakes::{ FakePin, FakeSpi };
+
+    // the busy pin is high while the panel is working
+    fn controller(busy: bool, spi: FakeSpi) -> GDEY029T94Controller<FakeSpi, FakePin, FakePin, FakePin, FakePin> {
+        GDEY029T94Controller::new(FakePin::default(), FakePin::reading(busy), FakePin::default(), FakePin::default(), spi).unwrap()
+    }
+
+    #[test]
+    fn a_panel_which_stays_busy_times_out() {
+        let mut display = controller(true, FakeSpi::default());
+        display.set_busy_timeout(Duration::from_millis(30));
+        assert!(matches!(display.start_epd(), Err(WyldcardError::BusyTimeout(timeout)) if timeout == Duration::from_millis(30)));
+    }
+
+    #[test]
+    fn failing_pins_are_bus_errors() {
+        let failing_chip_select = GDEY029T94Controller::new(FakePin::default(), FakePin::reading(false), FakePin::default(), FakePin::failing(), FakeSpi::default());
+        assert!(matches!(failing_chip_select, Err(WyldcardError::BusError(_))));
+
+        let mut failing_reset = GDEY029T94Controller::new(FakePin::failing(), FakePin::reading(false), FakePin::default(), FakePin::default(), FakeSpi::default()).unwrap();
+        assert!(matches!(failing_reset.start_epd(), Err(WyldcardError::BusError(_))));
+
+        let mut failing_busy = GDEY029T94Controller::new(FakePin::default(), FakePin::failing(), FakePin::default(), FakePin::default(), FakeSpi::default()).unwrap();
+        assert!(matches!(failing_busy.sleep(), Err(WyldcardError::BusError(_))));
+    }
+
+    #[test]
+    fn a_failing_spi_transfer_is_a_bus_error() {
+        let mut display = controller(false, FakeSpi { fails: true, ..FakeSpi::default() });
+        assert!(matches!(display.start_epd(), Err(WyldcardError::BusError(_))));
+    }
+
+    #[test]
+    fn images_and_windows_which_dont_fit_the_panel_are_invalid() {
+        let mut display = controller(false, FakeSpi::default());
+        assert!(matches!(display.display_image(vec![0; 10]), Err(WyldcardError::InvalidImage(_))));
+
+        let image = vec![0xFF; display.capabilities().image_size(ColorResolution::FourColorGrayscale)];
+        assert!(matches!(display.display_partial(vec![0; 10], Window { x: 0, y: 0, width: 8, height: 8 }, None), Err(WyldcardError::InvalidImage(_))));
+        assert!(matches!(display.display_partial(image, Window { x: 124, y: 0, width: 8, height: 8 }, None), Err(WyldcardError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn reading_the_temperature_leaves_the_panel_asleep() {
+        let spi = FakeSpi::default();
+        let mut display = controller(false, spi.clone());
+        display.set_data_line_readable(true);
+        assert_eq!(display.read_temperature().unwrap(), None); // the fake bus reads back zeros
+
+        let sent = spi.sent.borrow();
+        assert_eq!(sent[sent.len() - 2..], [vec![0x10], vec![0x01]]); // deep sleep mode 1
+    }
+
+    #[test]
+    fn temperatures_are_read_from_the_register() {
+        assert_eq!(temperature_from_register(0x19, 0x00), Some(25.0));
+        assert_eq!(temperature_from_register(0x19, 0x80), Some(25.5));
+        assert_eq!(temperature_from_register(0xFF, 0x00), Some(-1.0));
+        assert_eq!(temperature_from_register(0x00, 0x00), None);
+        assert_eq!(temperature_from_register(0xFF, 0xFF), None);
+    }
+
+    #[test]
+    fn a_temperature_which_cant_be_read_gets_the_built_in_waveform() {
+        let spi = FakeSpi::default();
+        let mut display = controller(false, spi.clone());
+        display.set_data_line_readable(true);
+        let mut cold = default_lut();
+        cold[60] = 0x0F;
+        display.set_temperature_luts(vec![(-40.0, cold)]);
+        display.start_epd().unwrap();
+
+        let sent = spi.sent.borrow();
+        assert!(sent.contains(&vec![0x1B]), "the temperature register wasn't read");
+        // 0x32 is also sent as data before the waveform, the source voltage
+        let lut = sent.iter().rposition(|transfer| *transfer == [0x32]).map(|index| &sent[index + 1]);
+        assert_eq!(lut, Some(&default_lut()));
+    }
+
+    // on a 3-wire bus the register can't be read, so there's no point running the sensor for it
+    #[test]
+    fn a_data_line_which_cant_be_read_skips_the_sensor() {
+        let spi = FakeSpi::default();
+        let mut display = controller(false, spi.clone());
+        display.set_temperature_luts(vec![(-40.0, default_lut())]);
+        display.start_epd().unwrap();
+        assert_eq!(display.read_temperature().unwrap(), None);
+
+        let sent = spi.sent.borrow();
+        assert!(!sent.contains(&vec![0x1B]));
+        assert!(!sent.contains(&vec![0xA1]), "the sensor was run");
+        assert!(!sent.contains(&vec![0x10]), "reading the temperature woke the panel and put it back to sleep");
+    }
+
+    // the controller's two RAMs, which outlast the controller being reset and put to sleep like the real ones do.
+    // Keeps what they held when the last partial update was started
+    struct FakeRam {
+        data_or_command: FakePin,
+        command: u8,
+        parameters: Vec<u8>,
+        x_range: (u8, u8),
+        y_range: (u16, u16),
+        x: u8,
+        y: u16,
+        black_and_white: Vec<u8>,
+        red: Vec<u8>,
+        update_control: u8,
+        at_partial_update: Option<(Vec<u8>, Vec<u8>)>,
+    }
+
+    struct FakeRamSpi(Rc<RefCell<FakeRam>>);
+
+    impl Transfer<u8> for FakeRamSpi {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+            let mut ram = self.0.borrow_mut();
+            if !ram.data_or_command.high.get() {
+                ram.command = words[0];
+                ram.parameters.clear();
+                if ram.command == 0x20 && ram.update_control == 0xCF {
+                    ram.at_partial_update = Some((ram.black_and_white.clone(), ram.red.clone()));
+                }
+                return Ok(words);
+            }
+
+            ram.parameters.extend_from_slice(words);
+            let parameters = ram.parameters.clone();
+            match (ram.command, parameters.as_slice()) {
+                (0x44, [start, end]) => ram.x_range = (*start, *end),
+                (0x45, [start_low, start_high, end_low, end_high]) => ram.y_range = (u16::from_le_bytes([*start_low, *start_high]), u16::from_le_bytes([*end_low, *end_high])),
+                (0x4E, [x]) => ram.x = *x,
+                (0x4F, [low, high]) => ram.y = u16::from_le_bytes([*low, *high]),
+                (0x22, [control]) => ram.update_control = *control,
+                (0x24 | 0x26, _) => {
+                    // data entry mode 0x03: along x, then down a row, wrapping around inside the window
+                    for byte in words.iter() {
+                        let index = ram.y as usize * 16 + ram.x as usize;
+                        if ram.command == 0x24 { ram.black_and_white[index] = *byte } else { ram.red[index] = *byte }
+                        ram.x += 1;
+                        if ram.x > ram.x_range.1 {
+                            ram.x = ram.x_range.0;
+                            ram.y = if ram.y >= ram.y_range.1 { ram.y_range.0 } else { ram.y + 1 };
+                        }
+                    }
+                },
+                _ => {},
+            }
+            Ok(words)
+        }
+    }
+
+    fn fake_ram() -> Rc<RefCell<FakeRam>> {
+        Rc::new(RefCell::new(FakeRam {
+            data_or_command: FakePin::default(),
+            command: 0,
+            parameters: Vec::new(),
+            x_range: (0, 15),
+            y_range: (0, 295),
+            x: 0,
+            y: 0,
+            black_and_white: vec![0; 4736],
+            red: vec![0; 4736],
+            update_control: 0,
+            at_partial_update: None,
+        }))
+    }
+
+    fn controller_on(ram: &Rc<RefCell<FakeRam>>) -> GDEY029T94Controller<FakeRamSpi, FakePin, FakePin, FakePin, FakePin> {
+        let data_or_command = ram.borrow().data_or_command.clone();
+        GDEY029T94Controller::new(FakePin::default(), FakePin::reading(false), data_or_command, FakePin::default(), FakeRamSpi(ram.clone())).unwrap()
+    }
+
+    #[test]
+    fn a_partial_update_after_a_grayscale_refresh_only_drives_the_window() {
+        let ram = fake_ram();
+        // light and dark gray stripes, so the two RAMs hold different bit planes
+        let gray: Vec<u8> = (0..4736 * 2).map(|index| if index % 64 < 32 { 0b1010_1010 } else { 0b0101_0101 }).collect();
+        let mut display = controller_on(&ram);
+        display.start_epd().unwrap();
+        display.display_image(gray.clone()).unwrap();
+        display.sleep().unwrap();
+        assert_ne!(ram.borrow().black_and_white, ram.borrow().red);
+
+        let window = Window { x: 16, y: 100, width: 32, height: 20 };
+        let mut display = controller_on(&ram);
+        let inverted = gray.iter().map(|byte| !byte).collect();
+        display.display_partial(inverted, window, Some(&gray)).unwrap();
+
+        let (black_and_white, red) = ram.borrow_mut().at_partial_update.take().expect("no partial update was started");
+        for (index, (new, old)) in black_and_white.iter().zip(&red).enumerate() {
+            let (x, y) = (index % 16 * 8, index / 16);
+            let in_window = (16..48).contains(&x) && (100..120).contains(&y);
+            assert_eq!(new != old, in_window, "byte at x {} y {}", x, y);
+        }
+        // light gray was on screen, and the window turns it black
+        assert_eq!(red[100 * 16 + 2], 0xFF);
+        assert_eq!(black_and_white[100 * 16 + 2], 0x00);
+    }
+
+    // after a restart or a card swap, nothing's known about what's on screen
+    #[test]
+    fn a_partial_update_without_the_shown_image_drives_every_pixel_in_the_window() {
+        let ram = fake_ram();
+        let image: Vec<u8> = (0..4736 * 2).map(|index| if index % 64 < 32 { 0x00 } else { 0xFF }).collect();
+        let window = Window { x: 16, y: 100, width: 32, height: 20 };
+        let mut display = controller_on(&ram);
+        display.display_partial(image, window, None).unwrap();
+
+        let (black_and_white, red) = ram.borrow_mut().at_partial_update.take().expect("no partial update was started");
+        for (index, (new, old)) in black_and_white.iter().zip(&red).enumerate() {
+            let (x, y) = (index % 16 * 8, index / 16);
+            let in_window = (16..48).contains(&x) && (100..120).contains(&y);
+            assert_eq!(new ^ old, if in_window { 0xFF } else { 0x00 }, "byte at x {} y {}", x, y);
+        }
+    }
+}
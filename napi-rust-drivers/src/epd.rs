@@ -0,0 +1,402 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::v2::{ InputPin, OutputPin };
+use embedded_hal::blocking::spi::Transfer;
+
+use crate::GDEW029T5D::{ self, GDEW029T5DController };
+use crate::GDEY029T94::{ self, GDEY029T94Controller };
+use crate::error::WyldcardError;
+use crate::framebuffer::{ Rotation, WIDTH, HEIGHT };
+
+// how long to wait for a panel to stop being busy before giving up on it. A full grayscale refresh takes a few seconds
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(6);
+
+// the panels we know how to drive. Add new controllers here, and to `new_driver()` below
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PanelType {
+  GDEW029T5D,
+  GDEY029T94,
+}
+
+impl PanelType {
+  // cards record which panel they carry as a single byte in their memory
+  pub fn id(&self) -> u8 {
+    match self {
+      PanelType::GDEW029T5D => 0xD5,
+      PanelType::GDEY029T94 => 0x94,
+    }
+  }
+
+  pub fn from_id(id: u8) -> Option<PanelType> {
+    match id {
+      0xD5 => Some(PanelType::GDEW029T5D),
+      0x94 => Some(PanelType::GDEY029T94),
+      _ => None,
+    }
+  }
+
+  // the GDEY029T94 holds its busy pin high while it works, the GDEW029T5D pulls it low
+  pub fn busy_when_high(&self) -> bool {
+    match self {
+      PanelType::GDEW029T5D => false,
+      PanelType::GDEY029T94 => true,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ColorResolution {
+  BlackAndWhiteMonochrome, // 1 bit per pixel, 8 pixels per byte
+  FourColorGrayscale, // 2 bits per pixel, 4 pixels per byte
+}
+
+// what a panel is able to do, so callers can decide at runtime how to drive it
+#[derive(Copy, Clone, Debug)]
+pub struct Capabilities {
+  pub width: u16,
+  pub height: u16,
+  pub gray_levels: u8,
+  pub monochrome: bool, // whether the panel has a separate, faster, black and white mode
+  pub partial_refresh: bool,
+}
+
+impl Capabilities {
+  // length in bytes of a full screen image
+  pub fn image_size(&self, color_resolution: ColorResolution) -> usize {
+    self.width as usize * self.height as usize * color_resolution.bits_per_pixel() / 8
+  }
+}
+
+pub fn check_image_size(image: &[u8], expected_size: usize) -> Result<(), WyldcardError> {
+  if image.len() != expected_size {
+    return Err(WyldcardError::InvalidImage(format!("image is {} bytes, but this display expects {} bytes", image.len(), expected_size)));
+  }
+  Ok(())
+}
+
+impl ColorResolution {
+  pub fn bits_per_pixel(&self) -> usize {
+    match self {
+      ColorResolution::BlackAndWhiteMonochrome => 1,
+      ColorResolution::FourColorGrayscale => 2,
+    }
+  }
+}
+
+// a rectangular region of the screen, in pixels
+#[derive(Copy, Clone, Debug)]
+pub struct Window {
+  pub x: u16,
+  pub y: u16,
+  pub width: u16,
+  pub height: u16,
+}
+
+impl Window {
+  pub fn fits(&self, capabilities: &Capabilities) -> bool {
+    self.width > 0 && self.height > 0
+      && self.x as u32 + self.width as u32 <= capabilities.width as u32
+      && self.y as u32 + self.height as u32 <= capabilities.height as u32
+  }
+
+  // controller RAM is addressed 8 pixels at a time along x, so widen the window out to whole bytes
+  pub fn byte_aligned(&self) -> Window {
+    let left = self.x / 8 * 8;
+    let right = (self.x + self.width).div_ceil(8) * 8;
+    Window { x: left, y: self.y, width: right - left, height: self.height }
+  }
+}
+
+// a custom waveform: the raw bytes a panel's controller takes into its LUT registers, which say how to drive each
+// pixel from one gray to another. Each controller lays them out differently, see `check_lut()` in its module
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+  panel_type: PanelType,
+  bytes: Vec<u8>,
+}
+
+impl Lut {
+  // checks the waveform's length and layout suit the panel
+  pub fn new(panel_type: PanelType, bytes: Vec<u8>) -> Result<Lut, WyldcardError> {
+    match panel_type {
+      PanelType::GDEW029T5D => GDEW029T5D::check_lut(&bytes)?,
+      PanelType::GDEY029T94 => GDEY029T94::check_lut(&bytes)?,
+    }
+    Ok(Lut { panel_type, bytes })
+  }
+
+  // the waveform each panel uses unless it's given another, a starting point for making new ones
+  pub fn default_for(panel_type: PanelType) -> Lut {
+    let bytes = match panel_type {
+      PanelType::GDEW029T5D => GDEW029T5D::default_lut(),
+      PanelType::GDEY029T94 => GDEY029T94::default_lut(),
+    };
+    Lut { panel_type, bytes }
+  }
+
+  pub fn panel_type(&self) -> PanelType {
+    self.panel_type
+  }
+
+  pub fn bytes(&self) -> &[u8] {
+    &self.bytes
+  }
+}
+
+// how images are turned on their way to a well's screen, so landscape layouts and plinths mounted upside down don't
+// need their art redrawn. Images are mirrored left to right first, then rotated clockwise. At 90 and 270 degrees
+// images are landscape, 296 pixels wide and 128 tall, but still take the same number of bytes
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Orientation {
+  pub rotation: Rotation,
+  pub mirrored: bool,
+}
+
+impl Orientation {
+  // the width and height of images in this orientation
+  pub fn size(&self) -> (u32, u32) {
+    match self.rotation {
+      Rotation::Deg0 | Rotation::Deg180 => (WIDTH, HEIGHT),
+      Rotation::Deg90 | Rotation::Deg270 => (HEIGHT, WIDTH),
+    }
+  }
+
+  // where a pixel of an image in this orientation lands on the screen
+  fn screen_position(&self, x: u32, y: u32) -> (u32, u32) {
+    let x = if self.mirrored { self.size().0 - 1 - x } else { x };
+    match self.rotation {
+      Rotation::Deg0 => (x, y),
+      Rotation::Deg90 => (WIDTH - 1 - y, x),
+      Rotation::Deg180 => (WIDTH - 1 - x, HEIGHT - 1 - y),
+      Rotation::Deg270 => (y, HEIGHT - 1 - x),
+    }
+  }
+
+  // rearranges a full screen image in this orientation into the panel's own portrait layout. Works on both packed
+  // grayscale and monochrome images, whose size has already been checked
+  pub fn orient_image(&self, image: Vec<u8>, color_resolution: ColorResolution) -> Vec<u8> {
+    if *self == Orientation::default() {
+      return image;
+    }
+
+    let bits_per_pixel = color_resolution.bits_per_pixel();
+    let pixels_per_byte = 8 / bits_per_pixel;
+    let mask = (1 << bits_per_pixel) - 1;
+    // the first pixel of each byte is in the most significant bits
+    let shift = |index: usize| 8 - bits_per_pixel * (index % pixels_per_byte + 1);
+
+    let (width, height) = self.size();
+    let mut oriented = vec![0; image.len()];
+    for y in 0..height {
+      for x in 0..width {
+        let index = (y * width + x) as usize;
+        let pixel = (image[index / pixels_per_byte] >> shift(index)) & mask;
+
+        let (screen_x, screen_y) = self.screen_position(x, y);
+        let screen_index = (screen_y * WIDTH + screen_x) as usize;
+        oriented[screen_index / pixels_per_byte] |= pixel << shift(screen_index);
+      }
+    }
+
+    oriented
+  }
+
+  // the region of the screen a window on an image in this orientation covers
+  pub fn orient_window(&self, window: Window) -> Result<Window, WyldcardError> {
+    let (width, height) = self.size();
+    if window.width == 0 || window.height == 0
+      || window.x as u32 + window.width as u32 > width
+      || window.y as u32 + window.height as u32 > height {
+      return Err(WyldcardError::InvalidArgument(format!("window {:?} doesn't fit on a {}x{} image", window, width, height)));
+    }
+
+    let (x0, y0) = self.screen_position(window.x as u32, window.y as u32);
+    let (x1, y1) = self.screen_position((window.x + window.width - 1) as u32, (window.y + window.height - 1) as u32);
+    Ok(Window {
+      x: x0.min(x1) as u16,
+      y: y0.min(y1) as u16,
+      width: (x0.abs_diff(x1) + 1) as u16,
+      height: (y0.abs_diff(y1) + 1) as u16,
+    })
+  }
+}
+
+// the common set of operations every e-paper controller supports
+pub trait EpdDriver {
+  fn capabilities(&self) -> Capabilities;
+  fn start_epd(&mut self) -> Result<(), WyldcardError>;
+  fn display_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError>;
+  // `display_image()` in two halves, so several panels can refresh at once: `load_image()` sends the image to
+  // the panel's RAM, then `begin_update()` starts the refresh and returns without waiting for the panel to finish
+  fn load_image(&mut self, buf: Vec<u8>) -> Result<(), WyldcardError>;
+  fn begin_update(&mut self) -> Result<(), WyldcardError>;
+  #[allow(dead_code)]
+  fn display_black_image(&mut self) -> Result<(), WyldcardError>;
+  #[allow(dead_code)]
+  fn display_white_image(&mut self) -> Result<(), WyldcardError>;
+  fn sleep(&mut self) -> Result<(), WyldcardError>;
+  fn set_busy_timeout(&mut self, timeout: Duration);
+
+  // panels without a black and white mode only ever run in 4 color grayscale, see `Capabilities::monochrome`
+  fn set_color_resolution(&mut self, _color_resolution: ColorResolution) {}
+
+  // a waveform, already checked with `Lut::new()`, to use for 4 color grayscale refreshes instead of the built in
+  // one. Black and white and partial refreshes keep their own
+  fn set_lut(&mut self, lut: Option<Vec<u8>>);
+
+  // the panel's temperature in degrees C, or None for panels whose sensor can't be read. Reading it wakes the
+  // controller and sends it back into deep sleep, so it needs starting again afterwards
+  fn read_temperature(&mut self) -> Result<Option<f32>, WyldcardError> {
+    Ok(None)
+  }
+
+  // waveforms to choose between by the panel's temperature when it's started in 4 color grayscale, as (the lowest
+  // temperature each is for, lut). Only used when there's no `set_lut()` waveform. Panels which can't read their
+  // temperature ignore them
+  fn set_temperature_luts(&mut self, _luts: Vec<(f32, Vec<u8>)>) {}
+
+  // whether the bus can read back what the panel sends on its data line, which takes it being wired through to MISO.
+  // Panels only run their temperature sensor when it can, so off by default
+  fn set_data_line_readable(&mut self, _readable: bool) {}
+
+  // redraw only the pixels inside `window`. `buf` is a full screen image, just like for `display_image()`, and
+  // `shown` is the full screen image the panel shows now, if it's known. Otherwise the rest of `buf` is taken to be
+  // on screen already, and every pixel in the window is driven. Partial updates need their own setup, so unlike `display_image()` this starts the panel itself.
+  // Panels without partial refresh redraw the whole screen instead
+  fn display_partial(&mut self, buf: Vec<u8>, _window: Window, _shown: Option<&[u8]>) -> Result<(), WyldcardError> {
+    self.start_epd()?;
+    self.display_image(buf)
+  }
+}
+
+// waits on just the busy pin, without needing the rest of the display, so the SPI bus stays free for other panels
+pub fn wait_while_busy<BUSY>(busy: &BUSY, busy_when_high: bool, timeout: Duration) -> Result<Duration, WyldcardError>
+  where BUSY: InputPin,
+        BUSY::Error: std::fmt::Debug {
+  let start = Instant::now();
+  while busy.is_high().map_err(WyldcardError::bus)? == busy_when_high {
+    if start.elapsed() > timeout {
+      return Err(WyldcardError::BusyTimeout(timeout));
+    }
+    thread::sleep(Duration::from_millis(10));
+  }
+  Ok(start.elapsed())
+}
+
+pub fn new_driver<'a, SPI, RESET, BUSY, DC, CS, E>(panel: PanelType, reset: RESET, busy: BUSY, data_or_command: DC, chip_select: CS, spi: SPI) -> Result<Box<dyn EpdDriver + 'a>, WyldcardError>
+  where SPI: Transfer<u8, Error = E> + 'a,
+        RESET: OutputPin + 'a,
+        RESET::Error: std::fmt::Debug,
+        BUSY: InputPin + 'a,
+        BUSY::Error: std::fmt::Debug,
+        DC: OutputPin + 'a,
+        DC::Error: std::fmt::Debug,
+        CS: OutputPin + 'a,
+        CS::Error: std::fmt::Debug,
+        E: std::fmt::Debug + 'a {
+  let driver: Box<dyn EpdDriver> = match panel {
+    PanelType::GDEW029T5D => Box::new(GDEW029T5DController::new(reset, busy, data_or_command, chip_select, spi)?),
+    PanelType::GDEY029T94 => Box::new(GDEY029T94Controller::new(reset, busy, data_or_command, chip_select, spi)?),
+  };
+
+  Ok(driver)
+}
+
+// widens a black and white image to 4 color grayscale, for panels which can't show it directly
+pub fn monochrome_to_grayscale(buf: &[u8]) -> Vec<u8> {
+  let mut grayscale = Vec::with_capacity(buf.len() * 2);
+
+  for byte in buf {
+    for half in [byte >> 4, byte & 0x0F] {
+      let mut out: u8 = 0;
+      for bit in (0..4).rev() {
+        let pixel = if (half >> bit) & 1 == 1 { 0b11 } else { 0b00 };
+        out = (out << 2) | pixel;
+      }
+      grayscale.push(out);
+    }
+  }
+
+  grayscale
+}
+
+// pins and an SPI bus to drive the controllers with when there's no panel. Any of them can be made to fail, the
+// busy pin reads back whatever level it's set to, and the bus keeps a copy of every transfer
+#[cfg(test)]
+pub mod fakes {
+  use std::cell::{ Cell, RefCell };
+  use std::rc::Rc;
+
+  use embedded_hal::blocking::spi::Transfer;
+  use embedded_hal::digital::v2::{ InputPin, OutputPin };
+
+  #[derive(Debug)]
+  pub struct Failed;
+
+  #[derive(Clone, Default)]
+  pub struct FakePin {
+    pub high: Rc<Cell<bool>>,
+    pub fails: bool,
+  }
+
+  impl FakePin {
+    pub fn reading(high: bool) -> FakePin {
+      FakePin { high: Rc::new(Cell::new(high)), fails: false }
+    }
+
+    pub fn failing() -> FakePin {
+      FakePin { fails: true, ..FakePin::default() }
+    }
+
+    fn level(&self) -> Result<bool, Failed> {
+      if self.fails { Err(Failed) } else { Ok(self.high.get()) }
+    }
+  }
+
+  impl OutputPin for FakePin {
+    type Error = Failed;
+
+    fn set_low(&mut self) -> Result<(), Failed> {
+      self.level()?;
+      self.high.set(false);
+      Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Failed> {
+      self.level()?;
+      self.high.set(true);
+      Ok(())
+    }
+  }
+
+  impl InputPin for FakePin {
+    type Error = Failed;
+
+    fn is_high(&self) -> Result<bool, Failed> {
+      self.level()
+    }
+
+    fn is_low(&self) -> Result<bool, Failed> {
+      self.level().map(|high| !high)
+    }
+  }
+
+  #[derive(Clone, Default)]
+  pub struct FakeSpi {
+    pub sent: Rc<RefCell<Vec<Vec<u8>>>>,
+    pub fails: bool,
+  }
+
+  impl Transfer<u8> for FakeSpi {
+    type Error = Failed;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Failed> {
+      if self.fails {
+        return Err(Failed);
+      }
+      self.sent.borrow_mut().push(words.to_vec());
+      Ok(words)
+    }
+  }
+}
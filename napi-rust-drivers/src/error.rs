@@ -0,0 +1,108 @@
+use std::fmt;
+use std::time::Duration;
+
+// everything that can go wrong while driving a plinth. Each variant reaches javascript with its name as the error's `code`
+#[derive(Debug, Clone)]
+pub enum WyldcardError {
+  BusError(String), // a GPIO, I2C or SPI transfer failed
+  CardNotPresent,
+  BusyTimeout(Duration), // the panel stayed busy for longer than this, usually because the card was pulled out
+  InvalidImage(String),
+  InvalidWell(usize),
+  InvalidArgument(String),
+  MemoryOutOfRange { address: usize, length: usize },
+  MemoryWriteFailed { address: usize, reason: String }, // `address` is the first byte which may not have been written
+  LegacyDataTooLong(usize), // the card's old JSON is this long, and runs into where records are written
+}
+
+impl WyldcardError {
+  // the hardware crates only promise their errors are Debug, so keep that description
+  pub fn bus<E: fmt::Debug>(e: E) -> WyldcardError {
+    WyldcardError::BusError(format!("{:?}", e))
+  }
+
+  pub fn code(&self) -> &'static str {
+    match self {
+      WyldcardError::BusError(_) => "BusError",
+      WyldcardError::CardNotPresent => "CardNotPresent",
+      WyldcardError::BusyTimeout(_) => "BusyTimeout",
+      WyldcardError::InvalidImage(_) => "InvalidImage",
+      WyldcardError::InvalidWell(_) => "InvalidWell",
+      WyldcardError::InvalidArgument(_) => "InvalidArgument",
+      WyldcardError::MemoryOutOfRange { .. } => "MemoryOutOfRange",
+      WyldcardError::MemoryWriteFailed { .. } => "MemoryWriteFailed",
+      WyldcardError::LegacyDataTooLong(_) => "LegacyDataTooLong",
+    }
+  }
+}
+
+impl fmt::Display for WyldcardError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      WyldcardError::BusError(e) => write!(f, "error talking to the card: {}", e),
+      WyldcardError::CardNotPresent => write!(f, "no card present"),
+      WyldcardError::BusyTimeout(timeout) => write!(f, "display was still busy after {:?}", timeout),
+      WyldcardError::InvalidImage(reason) => write!(f, "invalid image: {}", reason),
+      WyldcardError::InvalidWell(well) => write!(f, "there is no well {}", well),
+      WyldcardError::InvalidArgument(reason) => write!(f, "{}", reason),
+      WyldcardError::MemoryOutOfRange { address, length } => write!(f, "{} bytes at address {} is outside of the card's memory", length, address),
+      WyldcardError::MemoryWriteFailed { address, reason } => write!(f, "writing card memory failed at address {}: {}", address, reason),
+      WyldcardError::LegacyDataTooLong(length) => write!(f, "the card holds {} bytes of JSON saved by an older version, which has to be discarded before anything new can be saved", length),
+    }
+  }
+}
+
+impl std::error::Error for WyldcardError {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn every_error() -> Vec<WyldcardError> {
+    vec![
+      WyldcardError::BusError(String::from("Nack")),
+      WyldcardError::CardNotPresent,
+      WyldcardError::BusyTimeout(Duration::from_secs(6)),
+      WyldcardError::InvalidImage(String::from("too short")),
+      WyldcardError::InvalidWell(7),
+      WyldcardError::InvalidArgument(String::from("bad switch")),
+      WyldcardError::MemoryOutOfRange { address: 4000, length: 200 },
+      WyldcardError::MemoryWriteFailed { address: 64, reason: String::from("didn't read back") },
+      WyldcardError::LegacyDataTooLong(3000),
+    ]
+  }
+
+  #[test]
+  fn codes_are_the_variant_names() {
+    for e in every_error() {
+      let name = format!("{:?}", e);
+      let name = name.split(['(', ' ']).next().unwrap();
+      assert_eq!(e.code(), name);
+    }
+  }
+
+  #[test]
+  fn messages_say_what_went_wrong() {
+    let messages: Vec<String> = every_error().iter().map(WyldcardError::to_string).collect();
+    assert_eq!(messages, [
+      "error talking to the card: Nack",
+      "no card present",
+      "display was still busy after 6s",
+      "invalid image: too short",
+      "there is no well 7",
+      "bad switch",
+      "200 bytes at address 4000 is outside of the card's memory",
+      "writing card memory failed at address 64: didn't read back",
+      "the card holds 3000 bytes of JSON saved by an older version, which has to be discarded before anything new can be saved",
+    ]);
+  }
+
+  #[test]
+  fn bus_errors_keep_the_hardware_crates_description() {
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    enum I2cError { Nack(u8) }
+
+    assert!(matches!(WyldcardError::bus(I2cError::Nack(0x20)), WyldcardError::BusError(e) if e == "Nack(32)"));
+  }
+}
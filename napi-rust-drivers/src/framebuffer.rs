@@ -0,0 +1,186 @@
+use std::convert::Infallible;
+
+use embedded_graphics::pixelcolor::Gray2;
+use embedded_graphics::prelude::*;
+
+use crate::error::WyldcardError;
+
+pub const WIDTH: u32 = 128;
+pub const HEIGHT: u32 = 296;
+
+// a card's screen held in memory, in the packed 2-bit grayscale format the displays take: four pixels to a byte,
+// most significant bits first, 0b00 black through 0b11 white. Anything from embedded-graphics can draw on it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+  pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+  // starts out white, like a fresh card
+  pub fn new() -> Framebuffer {
+    Framebuffer { pixels: vec![0xFF; (WIDTH * HEIGHT / 4) as usize] }
+  }
+
+  pub fn from_packed(pixels: Vec<u8>) -> Result<Framebuffer, WyldcardError> {
+    if pixels.len() != (WIDTH * HEIGHT / 4) as usize {
+      return Err(WyldcardError::InvalidImage(format!("image is {} bytes, but a framebuffer holds {} bytes", pixels.len(), WIDTH * HEIGHT / 4)));
+    }
+    Ok(Framebuffer { pixels })
+  }
+
+  pub fn as_packed(&self) -> &[u8] {
+    &self.pixels
+  }
+
+  // pixels off the edge of the screen read as white
+  pub fn pixel(&self, x: i32, y: i32) -> Gray2 {
+    match pixel_index(x, y) {
+      Some(index) => Gray2::new((self.pixels[index / 4] >> (6 - 2 * (index % 4))) & 0b11),
+      None => Gray2::WHITE,
+    }
+  }
+
+  // pixels off the edge of the screen are ignored
+  pub fn set_pixel(&mut self, x: i32, y: i32, color: Gray2) {
+    if let Some(index) = pixel_index(x, y) {
+      let shift = 6 - 2 * (index % 4);
+      self.pixels[index / 4] = (self.pixels[index / 4] & !(0b11 << shift)) | (color.luma() << shift);
+    }
+  }
+
+  // copies a packed 2-bit image of the given size onto the screen with its top left corner at (x, y).
+  // `transparent` pixels in the image are skipped, so sprites don't need to be rectangular
+  pub fn blit(&mut self, image: &[u8], width: u32, height: u32, x: i32, y: i32, transparent: Option<Gray2>) -> Result<(), WyldcardError> {
+    // checked, as the sizes come straight from javascript and a huge one would wrap around to match a small image
+    let pixels = width.checked_mul(height).map(|pixels| pixels as usize);
+    if pixels.is_none() || pixels != image.len().checked_mul(4) {
+      return Err(WyldcardError::InvalidImage(format!("a {}x{} image can't be packed into {} bytes", width, height, image.len())));
+    }
+
+    for row in 0..height {
+      for column in 0..width {
+        let index = (row * width + column) as usize;
+        let color = Gray2::new((image[index / 4] >> (6 - 2 * (index % 4))) & 0b11);
+        if Some(color) != transparent {
+          self.set_pixel(x + column as i32, y + row as i32, color);
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
+impl Default for Framebuffer {
+  fn default() -> Self {
+    Framebuffer::new()
+  }
+}
+
+impl From<Framebuffer> for Vec<u8> {
+  fn from(framebuffer: Framebuffer) -> Vec<u8> {
+    framebuffer.pixels
+  }
+}
+
+fn pixel_index(x: i32, y: i32) -> Option<usize> {
+  if x < 0 || y < 0 || x >= WIDTH as i32 || y >= HEIGHT as i32 {
+    return None;
+  }
+  Some(y as usize * WIDTH as usize + x as usize)
+}
+
+impl OriginDimensions for Framebuffer {
+  fn size(&self) -> Size {
+    Size::new(WIDTH, HEIGHT)
+  }
+}
+
+impl DrawTarget for Framebuffer {
+  type Color = Gray2;
+  type Error = Infallible;
+
+  fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where I: IntoIterator<Item = Pixel<Self::Color>> {
+    for Pixel(point, color) in pixels {
+      self.set_pixel(point.x, point.y, color);
+    }
+    Ok(())
+  }
+}
+
+// how far a drawing is turned clockwise on the screen
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Rotation {
+  #[default]
+  Deg0,
+  Deg90,
+  Deg180,
+  Deg270,
+}
+
+// draws onto a framebuffer as though the card had been turned, so landscape layouts can be drawn the right way up.
+// At 90 and 270 degrees it's 296 pixels wide and 128 tall, and (0, 0) is the top left corner once the card is
+// turned so the drawing reads normally
+pub struct Rotated<'a> {
+  framebuffer: &'a mut Framebuffer,
+  rotation: Rotation,
+}
+
+impl<'a> Rotated<'a> {
+  pub fn new(framebuffer: &'a mut Framebuffer, rotation: Rotation) -> Rotated<'a> {
+    Rotated { framebuffer, rotation }
+  }
+}
+
+impl OriginDimensions for Rotated<'_> {
+  fn size(&self) -> Size {
+    match self.rotation {
+      Rotation::Deg0 | Rotation::Deg180 => Size::new(WIDTH, HEIGHT),
+      Rotation::Deg90 | Rotation::Deg270 => Size::new(HEIGHT, WIDTH),
+    }
+  }
+}
+
+impl DrawTarget for Rotated<'_> {
+  type Color = Gray2;
+  type Error = Infallible;
+
+  fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where I: IntoIterator<Item = Pixel<Self::Color>> {
+    let (width, height) = (WIDTH as i32, HEIGHT as i32);
+    for Pixel(point, color) in pixels {
+      let (x, y) = match self.rotation {
+        Rotation::Deg0 => (point.x, point.y),
+        Rotation::Deg90 => (width - 1 - point.y, point.x),
+        Rotation::Deg180 => (width - 1 - point.x, height - 1 - point.y),
+        Rotation::Deg270 => (point.y, height - 1 - point.x),
+      };
+      self.framebuffer.set_pixel(x, y, color);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn blitting_a_size_which_overflows_is_invalid() {
+    let mut framebuffer = Framebuffer::new();
+    // 65536 * 65536 wraps around to 0 pixels, which would match an empty image
+    assert!(matches!(framebuffer.blit(&[], 65536, 65536, 0, 0, None), Err(WyldcardError::InvalidImage(_))));
+    assert!(matches!(framebuffer.blit(&[0; 4], u32::MAX, 4, 0, 0, None), Err(WyldcardError::InvalidImage(_))));
+  }
+
+  #[test]
+  fn blitting_checks_the_image_is_the_size_given() {
+    let mut framebuffer = Framebuffer::new();
+    assert!(matches!(framebuffer.blit(&[0; 3], 4, 4, 0, 0, None), Err(WyldcardError::InvalidImage(_))));
+
+    framebuffer.blit(&[0; 4], 4, 4, 2, 2, None).unwrap();
+    assert_eq!(framebuffer.pixel(2, 2), Gray2::BLACK);
+    assert_eq!(framebuffer.pixel(6, 6), Gray2::WHITE);
+  }
+}
@@ -0,0 +1,413 @@
+use std::sync::OnceLock;
+
+use image::imageops::FilterType;
+use rand::{ Rng, SeedableRng };
+use rand::rngs::StdRng;
+
+use crate::error::WyldcardError;
+use crate::framebuffer::{ WIDTH, HEIGHT };
+
+// how to make an image of any shape fit the screen
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Fit {
+  Cover, // fill the screen, cropping whatever hangs over the edges. The same as `images/convert/convert.js`
+  Contain, // show the whole image, with white bars along the sides which don't reach the edge
+  Stretch, // fill the screen exactly, ignoring the image's aspect ratio
+}
+
+// how to spread the in-between shades of a photo across the four grays the display can show
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Dither {
+  None, // round every pixel to the nearest gray. Crisp, but smooth gradients turn into bands
+  FloydSteinberg, // pass each pixel's rounding error on to its neighbours. The most faithful for photos
+  Atkinson, // like Floyd-Steinberg, but only passes on part of the error, keeping more contrast and cleaner whites
+  Bayer, // a regular cross-hatch pattern. Looks printed, and doesn't crawl when part of an image changes
+  BlueNoise, // an even scattering of dots with no visible pattern
+}
+
+// adjusts the image's grays before they're reduced to four. The panels' white is a light gray, so art drawn
+// for a bright screen looks dull and muddy on them unless it's brightened and given a little more contrast
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneCurve {
+  pub gamma: f32, // below 1 brightens the midtones, above 1 darkens them
+  pub contrast: f32, // stretches the grays away from the middle. 1 leaves them alone
+}
+
+pub const LINEAR_TONE_CURVE: ToneCurve = ToneCurve { gamma: 1.0, contrast: 1.0 };
+pub const PANEL_TONE_CURVE: ToneCurve = ToneCurve { gamma: 0.8, contrast: 1.15 };
+
+// the grays the display can show, from 0b00 black to 0b11 white
+const GRAY_LEVELS: [f32; 4] = [0.0, 85.0, 170.0, 255.0];
+const GRAY_STEP: f32 = 85.0;
+
+// decodes a PNG, JPEG, GIF or BMP of any size and turns it into the packed 2-bit grayscale format the
+// displays take: four pixels to a byte, most significant bits first, 0b00 black through 0b11 white
+pub fn load_image(encoded: &[u8], width: u32, height: u32, fit: Fit, tone_curve: ToneCurve, dither: Dither) -> Result<Vec<u8>, WyldcardError> {
+  // nothing bigger than a whole card's screen can be shown or blitted, and checking first keeps huge sizes from
+  // overflowing or allocating gigabytes before the image has even been decoded
+  let pixels = match width.checked_mul(height) {
+    Some(pixels) if pixels <= WIDTH * HEIGHT => pixels,
+    _ => return Err(WyldcardError::InvalidImage(format!("a {}x{} image has more pixels than a {}x{} screen", width, height, WIDTH, HEIGHT))),
+  };
+  if pixels == 0 || !pixels.is_multiple_of(4) {
+    return Err(WyldcardError::InvalidArgument(format!("can't pack a {}x{} image into whole bytes", width, height)));
+  }
+  if tone_curve.gamma <= 0.0 || tone_curve.contrast < 0.0 {
+    return Err(WyldcardError::InvalidArgument(format!("gamma must be above 0 and contrast can't be negative, got {:?}", tone_curve)));
+  }
+
+  let decoded = image::load_from_memory(encoded).map_err(|e| WyldcardError::InvalidImage(e.to_string()))?;
+
+  let resized = match fit {
+    Fit::Cover => decoded.resize_to_fill(width, height, FilterType::Lanczos3),
+    Fit::Contain => decoded.resize(width, height, FilterType::Lanczos3),
+    Fit::Stretch => decoded.resize_exact(width, height, FilterType::Lanczos3),
+  };
+  let resized = resized.to_luma_alpha8();
+
+  // `Contain` can leave the image smaller than the screen, so centre it on white
+  let left = (width - resized.width()) / 2;
+  let top = (height - resized.height()) / 2;
+
+  let mut gray = vec![u8::MAX as f32; pixels as usize];
+  for y in 0..resized.height() {
+    for x in 0..resized.width() {
+      let [luma, alpha] = resized.get_pixel(x, y).0;
+      // transparent parts of the image show the white of the card through them
+      let alpha = alpha as f32 / u8::MAX as f32;
+      gray[((top + y) * width + left + x) as usize] = luma as f32 * alpha + u8::MAX as f32 * (1.0 - alpha);
+    }
+  }
+
+  apply_tone_curve(&mut gray, tone_curve);
+  Ok(pack_gray_levels(&quantize(&mut gray, width as usize, dither)))
+}
+
+fn apply_tone_curve(gray: &mut [f32], tone_curve: ToneCurve) {
+  if tone_curve == LINEAR_TONE_CURVE {
+    return;
+  }
+
+  for luma in gray.iter_mut() {
+    let stretched = ((*luma / 255.0 - 0.5) * tone_curve.contrast + 0.5).clamp(0.0, 1.0);
+    *luma = stretched.powf(tone_curve.gamma) * 255.0;
+  }
+}
+
+// reduces each pixel to one of the four gray levels, 0 to 3. Error diffusion works on `gray` in place
+fn quantize(gray: &mut [f32], width: usize, dither: Dither) -> Vec<u8> {
+  match dither {
+    Dither::None => gray.iter().map(|luma| nearest_level(*luma)).collect(),
+    Dither::FloydSteinberg => diffuse_error(gray, width, &[(1, 0, 7.0 / 16.0), (-1, 1, 3.0 / 16.0), (0, 1, 5.0 / 16.0), (1, 1, 1.0 / 16.0)]),
+    Dither::Atkinson => diffuse_error(gray, width, &[(1, 0, 1.0 / 8.0), (2, 0, 1.0 / 8.0), (-1, 1, 1.0 / 8.0), (0, 1, 1.0 / 8.0), (1, 1, 1.0 / 8.0), (0, 2, 1.0 / 8.0)]),
+    Dither::Bayer => threshold(gray, width, &bayer_matrix(), 8),
+    Dither::BlueNoise => threshold(gray, width, blue_noise_matrix(), BLUE_NOISE_SIZE),
+  }
+}
+
+fn nearest_level(luma: f32) -> u8 {
+  (luma / GRAY_STEP).round().clamp(0.0, 3.0) as u8
+}
+
+// rounds each pixel in turn, pushing what was lost onto the neighbours not yet visited, as (dx, dy, share)
+fn diffuse_error(gray: &mut [f32], width: usize, neighbours: &[(isize, usize, f32)]) -> Vec<u8> {
+  let height = gray.len() / width;
+  let mut levels = Vec::with_capacity(gray.len());
+
+  for y in 0..height {
+    for x in 0..width {
+      let luma = gray[y * width + x];
+      let level = nearest_level(luma);
+      let error = luma - GRAY_LEVELS[level as usize];
+      levels.push(level);
+
+      for (dx, dy, share) in neighbours {
+        let (nx, ny) = (x as isize + dx, y + dy);
+        if nx >= 0 && (nx as usize) < width && ny < height {
+          gray[ny * width + nx as usize] += error * share;
+        }
+      }
+    }
+  }
+
+  levels
+}
+
+// nudges each pixel up or down by up to half a gray step, following a tiled matrix of thresholds between 0 and 1,
+// before rounding it
+fn threshold(gray: &[f32], width: usize, matrix: &[f32], matrix_size: usize) -> Vec<u8> {
+  gray.iter().enumerate().map(|(i, luma)| {
+    let (x, y) = (i % width, i / width);
+    let offset = matrix[(y % matrix_size) * matrix_size + x % matrix_size] - 0.5;
+    nearest_level(luma + offset * GRAY_STEP)
+  }).collect()
+}
+
+// the classic 8x8 ordered dither, built up from the 2x2 one
+fn bayer_matrix() -> Vec<f32> {
+  let mut matrix = vec![0u32];
+  let mut size = 1;
+  while size < 8 {
+    let mut bigger = vec![0; size * size * 4];
+    for y in 0..size {
+      for x in 0..size {
+        let rank = matrix[y * size + x] * 4;
+        bigger[y * size * 2 + x] = rank;
+        bigger[y * size * 2 + x + size] = rank + 2;
+        bigger[(y + size) * size * 2 + x] = rank + 3;
+        bigger[(y + size) * size * 2 + x + size] = rank + 1;
+      }
+    }
+    matrix = bigger;
+    size *= 2;
+  }
+
+  matrix.iter().map(|rank| (*rank as f32 + 0.5) / 64.0).collect()
+}
+
+const BLUE_NOISE_SIZE: usize = 32;
+
+// a tile of thresholds where each rank is as far as possible from the ranks just below it, made with Ulichney's
+// void-and-cluster method. It's the same every time, and only worked out the first time it's needed
+fn blue_noise_matrix() -> &'static [f32] {
+  static MATRIX: OnceLock<Vec<f32>> = OnceLock::new();
+  MATRIX.get_or_init(|| {
+    let size = BLUE_NOISE_SIZE;
+    let count = size * size;
+
+    // how crowded each pixel is by the dots around it, with the tile wrapping around at the edges
+    let kernel: Vec<f32> = (0..count).map(|i| {
+      let (dx, dy) = ((i % size).min(size - i % size), (i / size).min(size - i / size));
+      (-((dx * dx + dy * dy) as f32) / (2.0 * 1.5 * 1.5)).exp()
+    }).collect();
+    let spread = |energy: &mut [f32], at: usize, sign: f32| {
+      for (i, e) in energy.iter_mut().enumerate() {
+        let dx = (i % size + size - at % size) % size;
+        let dy = (i / size + size - at / size) % size;
+        *e += sign * kernel[dy * size + dx];
+      }
+    };
+    // the tightest cluster is the busiest dot, the largest void the emptiest gap
+    let busiest = |dots: &[bool], energy: &[f32], dot: bool| {
+      let candidates = (0..count).filter(|i| dots[*i] == dot);
+      if dot {
+        candidates.max_by(|a, b| energy[*a].total_cmp(&energy[*b])).unwrap()
+      } else {
+        candidates.min_by(|a, b| energy[*a].total_cmp(&energy[*b])).unwrap()
+      }
+    };
+
+    // start from a sprinkling of random dots, then move dots out of clusters and into voids until they're even
+    let mut rng = StdRng::seed_from_u64(0x3a11_ca4d);
+    let mut dots = vec![false; count];
+    let mut energy = vec![0.0; count];
+    while dots.iter().filter(|dot| **dot).count() < count / 10 {
+      let at = rng.gen_range(0..count);
+      if !dots[at] {
+        dots[at] = true;
+        spread(&mut energy, at, 1.0);
+      }
+    }
+    loop {
+      let cluster = busiest(&dots, &energy, true);
+      dots[cluster] = false;
+      spread(&mut energy, cluster, -1.0);
+      let void = busiest(&dots, &energy, false);
+      dots[void] = true;
+      spread(&mut energy, void, 1.0);
+      if void == cluster {
+        break;
+      }
+    }
+
+    // rank the starting dots by taking away the most clustered first, then rank the rest by filling the largest voids
+    let mut ranks = vec![0; count];
+    let initial_dots = dots.clone();
+    let initial_energy = energy.clone();
+    let mut remaining = dots.iter().filter(|dot| **dot).count();
+    while remaining > 0 {
+      let cluster = busiest(&dots, &energy, true);
+      dots[cluster] = false;
+      spread(&mut energy, cluster, -1.0);
+      remaining -= 1;
+      ranks[cluster] = remaining;
+    }
+
+    let (mut dots, mut energy) = (initial_dots, initial_energy);
+    for rank in dots.iter().filter(|dot| **dot).count()..count {
+      let void = busiest(&dots, &energy, false);
+      dots[void] = true;
+      spread(&mut energy, void, 1.0);
+      ranks[void] = rank;
+    }
+
+    ranks.iter().map(|rank| (*rank as f32 + 0.5) / count as f32).collect()
+  })
+}
+
+// packs 2-bit gray levels four to a byte, the first pixel in the most significant bits
+fn pack_gray_levels(levels: &[u8]) -> Vec<u8> {
+  levels.chunks(4)
+    .map(|pixels| pixels.iter().fold(0, |byte, level| (byte << 2) | level))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use embedded_graphics::pixelcolor::Gray2;
+
+  fn load(width: u32, height: u32) -> Result<Vec<u8>, WyldcardError> {
+    load_image(&[], width, height, Fit::Cover, PANEL_TONE_CURVE, Dither::None)
+  }
+
+  #[test]
+  fn sizes_which_overflow_are_invalid() {
+    assert!(matches!(load(65536, 65536), Err(WyldcardError::InvalidImage(_))));
+    assert!(matches!(load(u32::MAX, 2), Err(WyldcardError::InvalidImage(_))));
+  }
+
+  #[test]
+  fn images_can_be_no_bigger_than_the_screen() {
+    assert!(matches!(load(WIDTH, HEIGHT + 4), Err(WyldcardError::InvalidImage(_))));
+    // any shape is fine as long as it fits, so it fails on decoding the empty file instead
+    assert!(matches!(load(HEIGHT, WIDTH), Err(WyldcardError::InvalidImage(e)) if !e.contains("pixels")));
+  }
+
+  // a PNG with each pixel's gray and alpha given by `pixel(x, y)`
+  fn png(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 2]) -> Vec<u8> {
+    let image = image::GrayAlphaImage::from_fn(width, height, |x, y| image::LumaA(pixel(x, y)));
+    let mut encoded = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut encoded, image::ImageOutputFormat::Png).unwrap();
+    encoded.into_inner()
+  }
+
+  // each row of the packed image as gray levels, 0 black to 3 white
+  fn rows(packed: &[u8], width: usize) -> Vec<Vec<u8>> {
+    let levels: Vec<u8> = packed.iter().flat_map(|byte| (0..4).map(move |pixel| (byte >> (6 - 2 * pixel)) & 0b11)).collect();
+    levels.chunks(width).map(|row| row.to_vec()).collect()
+  }
+
+  fn load_png(encoded: &[u8], width: u32, height: u32, fit: Fit) -> Vec<Vec<u8>> {
+    rows(&load_image(encoded, width, height, fit, LINEAR_TONE_CURVE, Dither::None).unwrap(), width as usize)
+  }
+
+  #[test]
+  fn cover_crops_the_sides_which_hang_over() {
+    // black on the left half, white on the right
+    let wide = png(16, 4, |x, _| if x < 8 { [0, 255] } else { [255, 255] });
+    assert_eq!(load_png(&wide, 4, 4, Fit::Cover), vec![vec![0, 0, 3, 3]; 4]);
+  }
+
+  #[test]
+  fn contain_centres_the_image_on_white() {
+    let black = png(16, 4, |_, _| [0, 255]);
+    let shown = load_png(&black, 8, 8, Fit::Contain);
+    let expected: Vec<Vec<u8>> = (0..8).map(|y| vec![if (3..5).contains(&y) { 0 } else { 3 }; 8]).collect();
+    assert_eq!(shown, expected);
+  }
+
+  #[test]
+  fn stretch_fills_the_screen() {
+    let black = png(16, 4, |_, _| [0, 255]);
+    assert_eq!(load_png(&black, 8, 8, Fit::Stretch), vec![vec![0; 8]; 8]);
+  }
+
+  #[test]
+  fn transparent_pixels_show_white() {
+    let see_through = png(4, 1, |x, _| [0, if x < 2 { 255 } else { 0 }]);
+    assert_eq!(load_png(&see_through, 4, 1, Fit::Stretch), vec![vec![0, 0, 3, 3]]);
+  }
+
+  #[test]
+  fn the_tone_curve_moves_the_grays_but_not_black_and_white() {
+    let mut gray = vec![0.0, 63.75, 127.5, 255.0];
+    apply_tone_curve(&mut gray, LINEAR_TONE_CURVE);
+    assert_eq!(gray, [0.0, 63.75, 127.5, 255.0]);
+
+    apply_tone_curve(&mut gray, ToneCurve { gamma: 0.5, contrast: 1.0 });
+    assert_eq!(gray, [0.0, 127.5, 0.5f32.sqrt() * 255.0, 255.0]);
+
+    let mut gray = vec![0.0, 63.75, 127.5, 191.25, 255.0];
+    apply_tone_curve(&mut gray, ToneCurve { gamma: 1.0, contrast: 2.0 });
+    assert_eq!(gray, [0.0, 0.0, 127.5, 255.0, 255.0]);
+
+    // the panel's curve brightens the middle
+    let mut gray = vec![127.5];
+    apply_tone_curve(&mut gray, PANEL_TONE_CURVE);
+    assert!(gray[0] > 127.5);
+  }
+
+  #[test]
+  fn without_dithering_each_pixel_rounds_to_the_nearest_gray() {
+    let mut gray = vec![0.0, 42.0, 43.0, 128.0, 212.0, 213.0, 300.0, -10.0];
+    assert_eq!(quantize(&mut gray, 8, Dither::None), [0, 0, 1, 2, 2, 3, 3, 0]);
+  }
+
+  // how much of a flat gray, `between` of the way from dark gray to light gray, comes out light gray. Checks it only
+  // uses those two grays, without black or white thrown in
+  fn light_gray_share(dither: Dither, between: f32) -> f32 {
+    let size = 64;
+    let mut gray = vec![85.0 + GRAY_STEP * between; size * size];
+    let levels = quantize(&mut gray, size, dither);
+    assert!(levels.iter().all(|level| *level == 1 || *level == 2), "{:?} used black or white", dither);
+    levels.iter().filter(|level| **level == 2).count() as f32 / levels.len() as f32
+  }
+
+  #[test]
+  fn dithering_a_flat_gray_keeps_its_brightness() {
+    for dither in [Dither::FloydSteinberg, Dither::Bayer, Dither::BlueNoise] {
+      for between in [0.25, 0.5] {
+        let share = light_gray_share(dither, between);
+        assert!((share - between).abs() < 0.03, "{:?} made {} light gray rather than {}", dither, share, between);
+      }
+    }
+  }
+
+  // it passes on only three quarters of the error, so grays lean towards the nearer level
+  #[test]
+  fn atkinson_dithering_keeps_more_contrast() {
+    let share = light_gray_share(Dither::Atkinson, 0.5);
+    assert!((share - 0.5).abs() < 0.03, "{}", share);
+    let share = light_gray_share(Dither::Atkinson, 0.25);
+    assert!(share > 0.1 && share < 0.25, "{}", share);
+  }
+
+  #[test]
+  fn flat_black_and_white_stay_solid_whatever_the_dithering() {
+    for dither in [Dither::None, Dither::FloydSteinberg, Dither::Atkinson, Dither::Bayer, Dither::BlueNoise] {
+      assert!(quantize(&mut vec![0.0; 64], 8, dither).iter().all(|level| *level == 0), "{:?}", dither);
+      assert!(quantize(&mut vec![255.0; 64], 8, dither).iter().all(|level| *level == 3), "{:?}", dither);
+    }
+  }
+
+  #[test]
+  fn the_bayer_matrix_is_the_classic_one() {
+    let ranks: Vec<u32> = bayer_matrix().iter().map(|threshold| (threshold * 64.0 - 0.5).round() as u32).collect();
+    assert_eq!(ranks[..8], [0, 32, 8, 40, 2, 34, 10, 42]);
+    assert_eq!(ranks[8..16], [48, 16, 56, 24, 50, 18, 58, 26]);
+    let mut sorted = ranks.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..64).collect::<Vec<_>>());
+  }
+
+  // images dithered with blue noise come out the same on every plinth and after every restart
+  #[test]
+  fn the_blue_noise_matrix_is_the_same_every_time() {
+    let ranks: Vec<usize> = blue_noise_matrix().iter().map(|threshold| (threshold * 1024.0 - 0.5).round() as usize).collect();
+    assert_eq!(ranks[..8], [671, 57, 901, 164, 642, 115, 906, 407]);
+    let mut sorted = ranks.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..1024).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn gray_levels_are_packed_first_pixel_in_the_top_bits() {
+    assert_eq!(pack_gray_levels(&[0, 1, 2, 3]), [0b00_01_10_11]);
+    assert_eq!(pack_gray_levels(&[3, 0, 0, 0, 0, 0, 0, 3]), [0b11_00_00_00, 0b00_00_00_11]);
+    // the same layout the framebuffer and drivers read
+    let framebuffer = crate::framebuffer::Framebuffer::from_packed(pack_gray_levels(&[[1, 2, 3, 0]; 128 * 296 / 4].concat())).unwrap();
+    assert_eq!(framebuffer.pixel(0, 0), Gray2::new(1));
+    assert_eq!(framebuffer.pixel(2, 0), Gray2::new(3));
+  }
+}
@@ -0,0 +1,220 @@
+use crate::error::WyldcardError;
+use crate::msgpack::{ self, Data };
+use crate::plinth::Plinth;
+use crate::storage::{ read_record, write_record };
+
+// the store is kept as a single record, so every change is written all at once and a card pulled out mid write keeps
+// the store it had before. It starts with a zero byte, which no JSON text does, then the format version, then an index
+// of the items: their count, then for each its key length, key, kind and value length. The values follow in the same order
+const FORMAT: [u8; 2] = [0x00, 0x01];
+pub const MAX_KEY_LENGTH: usize = 32; // bytes
+const MAX_ITEMS: usize = u8::MAX as usize;
+const MAX_UNPACKED_SIZE: usize = 64 * 1024; // bytes a deflated value may grow back to, so a corrupt card can't use up all the memory
+
+// the key `storeData()` keeps its object under. Records written before the store existed hold that object on its own
+pub const DATA_KEY: &str = "data";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+  Binary(Vec<u8>),
+  Json(String), // JSON text, parsed by whoever reads it
+  MessagePack(Vec<u8>),
+  Deflated(Vec<u8>), // MessagePack, compressed with deflate
+}
+
+impl Value {
+  // MessagePack, deflated as well if `compress` is set and that comes out smaller
+  pub fn pack(data: &Data, compress: bool) -> Value {
+    let packed = msgpack::encode(data);
+    if compress {
+      let deflated = miniz_oxide::deflate::compress_to_vec(&packed, 9);
+      if deflated.len() < packed.len() {
+        return Value::Deflated(deflated);
+      }
+    }
+    Value::MessagePack(packed)
+  }
+
+  // the data in a `pack()`ed value
+  pub fn unpack(&self) -> Result<Data, WyldcardError> {
+    match self {
+      Value::MessagePack(packed) => msgpack::decode(packed),
+      Value::Deflated(deflated) => {
+        let packed = miniz_oxide::inflate::decompress_to_vec_with_limit(deflated, MAX_UNPACKED_SIZE)
+          .map_err(|e| WyldcardError::InvalidArgument(format!("card data couldn't be decompressed: {:?}", e)))?;
+        msgpack::decode(&packed)
+      },
+      Value::Binary(_) | Value::Json(_) => Err(WyldcardError::InvalidArgument(String::from("only MessagePack values can be unpacked"))),
+    }
+  }
+
+  fn kind(&self) -> u8 {
+    match self {
+      Value::Binary(_) => 0,
+      Value::Json(_) => 1,
+      Value::MessagePack(_) => 2,
+      Value::Deflated(_) => 3,
+    }
+  }
+
+  fn bytes(&self) -> &[u8] {
+    match self {
+      Value::Binary(bytes) | Value::MessagePack(bytes) | Value::Deflated(bytes) => bytes,
+      Value::Json(text) => text.as_bytes(),
+    }
+  }
+}
+
+// the items on one card, in the order they were first set
+#[derive(Clone, Debug, Default)]
+pub struct KeyValueStore {
+  items: Vec<(String, Value)>,
+}
+
+impl KeyValueStore {
+  // an empty store if nothing's been saved on the card yet. A record of plain JSON, whether from before the store or
+  // from before records, comes back as the `data` item, so saving the store keeps it
+  pub fn load<P: Plinth>(plinth: &P, well: usize) -> Result<KeyValueStore, WyldcardError> {
+    match read_record(plinth, well)? {
+      Some(record) if record.starts_with(&FORMAT) => KeyValueStore::decode(&record[FORMAT.len()..]),
+      Some(record) => match String::from_utf8(record) {
+        Ok(json) => Ok(KeyValueStore { items: vec![(String::from(DATA_KEY), Value::Json(json))] }),
+        Err(_) => Err(WyldcardError::InvalidArgument(String::from("the card holds a record which isn't a key-value store"))),
+      },
+      None => Ok(KeyValueStore::default()),
+    }
+  }
+
+  pub fn save<P: Plinth>(&self, plinth: &P, well: usize) -> Result<(), WyldcardError> {
+    write_record(plinth, well, &self.encode())
+  }
+
+  pub fn get(&self, key: &str) -> Option<&Value> {
+    self.items.iter().find(|(item_key, _)| item_key == key).map(|(_, value)| value)
+  }
+
+  pub fn set(&mut self, key: &str, value: Value) -> Result<(), WyldcardError> {
+    check_key(key)?;
+    if let Some(item) = self.items.iter_mut().find(|(item_key, _)| item_key == key) {
+      item.1 = value;
+      return Ok(());
+    }
+
+    if self.items.len() >= MAX_ITEMS {
+      return Err(WyldcardError::InvalidArgument(format!("cards can hold at most {} items", MAX_ITEMS)));
+    }
+    self.items.push((String::from(key), value));
+    Ok(())
+  }
+
+  // returns whether there was an item to delete
+  pub fn delete(&mut self, key: &str) -> bool {
+    let count = self.items.len();
+    self.items.retain(|(item_key, _)| item_key != key);
+    self.items.len() != count
+  }
+
+  pub fn keys(&self) -> impl Iterator<Item = &str> {
+    self.items.iter().map(|(key, _)| key.as_str())
+  }
+
+  fn encode(&self) -> Vec<u8> {
+    let mut encoded = FORMAT.to_vec();
+    encoded.push(self.items.len() as u8);
+    for (key, value) in &self.items {
+      encoded.push(key.len() as u8);
+      encoded.extend_from_slice(key.as_bytes());
+      encoded.push(value.kind());
+      encoded.extend_from_slice(&(value.bytes().len() as u16).to_le_bytes());
+    }
+    for (_, value) in &self.items {
+      encoded.extend_from_slice(value.bytes());
+    }
+    encoded
+  }
+
+  fn decode(encoded: &[u8]) -> Result<KeyValueStore, WyldcardError> {
+    let corrupt = || WyldcardError::InvalidArgument(String::from("the card's key-value store is corrupt"));
+    let mut reader = encoded.iter().copied();
+    let mut next = |length: usize| -> Result<Vec<u8>, WyldcardError> {
+      let bytes: Vec<u8> = reader.by_ref().take(length).collect();
+      if bytes.len() != length {
+        return Err(corrupt());
+      }
+      Ok(bytes)
+    };
+
+    let count = next(1)?[0];
+    let mut index = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+      let key_length = next(1)?[0] as usize;
+      let key = String::from_utf8(next(key_length)?).map_err(|_| corrupt())?;
+      let kind = next(1)?[0];
+      let value_length = next(2)?;
+      index.push((key, kind, u16::from_le_bytes([value_length[0], value_length[1]]) as usize));
+    }
+
+    let mut items = Vec::with_capacity(index.len());
+    for (key, kind, value_length) in index {
+      let bytes = next(value_length)?;
+      let value = match kind {
+        0 => Value::Binary(bytes),
+        1 => Value::Json(String::from_utf8(bytes).map_err(|_| corrupt())?),
+        2 => Value::MessagePack(bytes),
+        3 => Value::Deflated(bytes),
+        _ => return Err(corrupt()),
+      };
+      items.push((key, value));
+    }
+    Ok(KeyValueStore { items })
+  }
+}
+
+fn check_key(key: &str) -> Result<(), WyldcardError> {
+  if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+    return Err(WyldcardError::InvalidArgument(format!("keys must be 1 to {} bytes long, '{}' is {}", MAX_KEY_LENGTH, key, key.len())));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::plinth::{ Plinth, SimulatedCard, SimulatedPlinth };
+
+  #[test]
+  fn a_legacy_card_keeps_its_data_when_an_item_is_set() {
+    let legacy_json = r#"{"id":"abc"}"#;
+    let mut card = SimulatedCard::new();
+    card.memory.fill(b' ');
+    card.memory[..legacy_json.len()].copy_from_slice(legacy_json.as_bytes());
+    let plinth = SimulatedPlinth::new();
+    plinth.insert_card(0, card).unwrap();
+
+    let mut store = KeyValueStore::load(&plinth, 0).unwrap();
+    store.set("score", Value::Binary(vec![1])).unwrap();
+    store.save(&plinth, 0).unwrap();
+
+    let store = KeyValueStore::load(&plinth, 0).unwrap();
+    assert_eq!(store.get(DATA_KEY), Some(&Value::Json(String::from(legacy_json))));
+    assert_eq!(store.get("score"), Some(&Value::Binary(vec![1])));
+
+    // and it's been carried over into the store, so writing over the old JSON doesn't lose it
+    plinth.write_memory_at(0, 0, &mut vec![0; legacy_json.len()]).unwrap();
+    assert_eq!(KeyValueStore::load(&plinth, 0).unwrap().get(DATA_KEY), Some(&Value::Json(String::from(legacy_json))));
+  }
+
+  #[test]
+  fn a_legacy_card_too_long_to_keep_says_so_rather_than_that_the_store_is_full() {
+    let legacy_json = format!(r#"{{"log":"{}"}}"#, "x".repeat(3000));
+    let mut card = SimulatedCard::new();
+    card.memory.fill(b' ');
+    card.memory[..legacy_json.len()].copy_from_slice(legacy_json.as_bytes());
+    let plinth = SimulatedPlinth::new();
+    plinth.insert_card(0, card).unwrap();
+
+    let mut store = KeyValueStore::load(&plinth, 0).unwrap();
+    store.set("score", Value::Binary(vec![1])).unwrap();
+    assert!(matches!(store.save(&plinth, 0), Err(WyldcardError::LegacyDataTooLong(_))));
+  }
+}
@@ -1,4 +1,6 @@
 #![deny(clippy::all)]
+// napi leaves out its exports when testing, so everything only javascript calls looks unused
+#![cfg_attr(test, allow(dead_code))]
 
 #[macro_use]
 extern crate napi_derive;
@@ -6,137 +8,896 @@ extern crate napi_derive;
 use napi::{
   bindgen_prelude::*,
   threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode},
-  JsBoolean, JsString,
+  JsBuffer, JsObject, JsString, JsUnknown, ValueType,
 };
-use rppal::gpio::Level;
+use std::sync::Arc;
+use std::time::Duration;
+
+use embedded_graphics::mono_font::{ ascii::FONT_6X10, MonoTextStyle };
+use embedded_graphics::pixelcolor::Gray2;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{ Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle };
+use embedded_graphics::text::{ Baseline, Text };
 
 mod plinth;
+mod epd;
+mod error;
+mod framebuffer;
+mod imaging;
+mod key_value;
+mod msgpack;
+mod storage;
+mod text;
+// named after the panels' controllers, as their datasheets write them
+#[allow(non_snake_case)]
 mod GDEW029T5D;
+#[allow(non_snake_case)]
 mod GDEY029T94;
 
-use crate::plinth::{ Plinth, DevKitV1, Prototype };
+use crate::plinth::{ image_hash, Plinth, IMAGE_HASH_ADDRESS, DevKitV1, Prototype, RefreshOptions, SimulatedPlinth, SimulatedCard };
+use crate::epd::{ ColorResolution, Lut, Orientation, PanelType, Window };
+use crate::error::WyldcardError;
+use crate::framebuffer::{ Rotated, Rotation };
+use crate::imaging::{ Dither, Fit, ToneCurve, PANEL_TONE_CURVE };
+use crate::key_value::{ KeyValueStore, Value };
+use crate::msgpack::Data;
+use crate::text::{ draw_text_box, FontSize, HorizontalAlignment, TextBoxStyle, VerticalAlignment };
+
+// errors thrown into javascript carry a `code` saying what went wrong, eg. 'CardNotPresent'. See `WyldcardError::code()`
+type ErrorCode = &'static str;
+
+impl From<WyldcardError> for Error<ErrorCode> {
+  fn from(e: WyldcardError) -> Self {
+    Error::new(e.code(), e.to_string())
+  }
+}
+
+// a refresh takes seconds, so it runs on a worker thread and javascript gets a Promise which resolves once the
+// panel is idle again. The event loop, and with it the switch callbacks and other wells, carry on in the meantime
+struct DisplayTask<P> {
+  plinth: Arc<P>,
+  refresh: Refresh,
+}
+
+enum Refresh {
+  Full(usize, Vec<u8>, ColorResolution, RefreshOptions),
+  Partial(usize, Vec<u8>, Window),
+  Together(Vec<(usize, Vec<u8>)>, ColorResolution, RefreshOptions),
+}
+
+impl<P: Plinth + Send + Sync + 'static> Task for DisplayTask<P> {
+  type Output = std::result::Result<(), WyldcardError>;
+  type JsValue = ();
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    Ok(match &mut self.refresh {
+      Refresh::Full(well, image, color_resolution, options) => self.plinth.display_image(*well, std::mem::take(image), *color_resolution, options),
+      Refresh::Partial(well, image, window) => self.plinth.display_partial(*well, std::mem::take(image), *window),
+      Refresh::Together(images, color_resolution, options) => self.plinth.display_images(std::mem::take(images), *color_resolution, options),
+    })
+  }
+
+  fn resolve(&mut self, env: Env, output: Self::Output) -> Result<()> {
+    output.map_err(|e| reject(env, e))
+  }
+}
+
+// tasks can only fail with a napi Status, so reject with a ready made javascript error to keep its `code`
+fn reject(env: Env, e: WyldcardError) -> Error {
+  JsError::from(Error::<ErrorCode>::from(e)).into_unknown(env).into()
+}
+
+#[napi(object)]
+pub struct WellImage {
+  pub well: u8,
+  pub image: Buffer,
+}
+
+// a waveform for when the panel is at `from` degrees C or warmer
+#[napi(object)]
+pub struct TemperatureLut {
+  pub from: f64,
+  pub lut: Buffer,
+}
+
+// images on a well are mirrored left to right and then turned `rotation` degrees clockwise
+#[napi(object)]
+pub struct WellOrientation {
+  pub rotation: u32,
+  pub mirrored: bool,
+}
+
+impl From<Orientation> for WellOrientation {
+  fn from(orientation: Orientation) -> WellOrientation {
+    let rotation = match orientation.rotation {
+      Rotation::Deg0 => 0,
+      Rotation::Deg90 => 90,
+      Rotation::Deg180 => 180,
+      Rotation::Deg270 => 270,
+    };
+    WellOrientation { rotation, mirrored: orientation.mirrored }
+  }
+}
+
+#[napi(object)]
+pub struct LoadImageOptions {
+  pub width: Option<u32>, // defaults to the size of a card's screen, 128x296
+  pub height: Option<u32>,
+  pub fit: Option<String>, // "cover" (the default), "contain" or "stretch"
+  pub dither: Option<String>, // "floyd-steinberg" (the default), "atkinson", "bayer", "blue-noise" or "none"
+  pub gamma: Option<f64>, // gamma and contrast default to a curve suited to the panels. Pass 1 for both to leave the image as it is
+  pub contrast: Option<f64>,
+}
+
+enum ImageSource {
+  Path(String),
+  Encoded(Vec<u8>),
+}
+
+// decoding and resizing a photo takes a while on a Pi, so it's done on a worker thread like a refresh
+pub struct LoadImageTask {
+  source: ImageSource,
+  width: u32,
+  height: u32,
+  fit: Fit,
+  tone_curve: ToneCurve,
+  dither: Dither,
+}
+
+impl Task for LoadImageTask {
+  type Output = std::result::Result<Vec<u8>, WyldcardError>;
+  type JsValue = Buffer;
+
+  fn compute(&mut self) -> Result<Self::Output> {
+    let encoded = match &mut self.source {
+      ImageSource::Path(path) => match std::fs::read(&path) {
+        Ok(encoded) => encoded,
+        Err(e) => return Ok(Err(WyldcardError::InvalidImage(format!("couldn't read {}: {}", path, e)))),
+      },
+      ImageSource::Encoded(encoded) => std::mem::take(encoded),
+    };
+    Ok(crate::imaging::load_image(&encoded, self.width, self.height, self.fit, self.tone_curve, self.dither))
+  }
+
+  fn resolve(&mut self, env: Env, output: Self::Output) -> Result<Buffer> {
+    output.map(Buffer::from).map_err(|e| reject(env, e))
+  }
+}
+
+// loads an image file, or the contents of one, ready to pass to `displayImage()`. Any size of PNG, JPEG, GIF or BMP
+// is resized to fit the screen and reduced to the display's four grays
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn load_image(source: Either<String, Buffer>, options: Option<LoadImageOptions>) -> Result<AsyncTask<LoadImageTask>, ErrorCode> {
+  let source = match source {
+    Either::A(path) => ImageSource::Path(path),
+    Either::B(encoded) => ImageSource::Encoded(encoded.into()),
+  };
+  let options = options.unwrap_or(LoadImageOptions { width: None, height: None, fit: None, dither: None, gamma: None, contrast: None });
+
+  Ok(AsyncTask::new(LoadImageTask {
+    source,
+    width: options.width.unwrap_or(128),
+    height: options.height.unwrap_or(296),
+    fit: parse_fit(options.fit.as_deref())?,
+    tone_curve: ToneCurve {
+      gamma: options.gamma.map_or(PANEL_TONE_CURVE.gamma, |gamma| gamma as f32),
+      contrast: options.contrast.map_or(PANEL_TONE_CURVE.contrast, |contrast| contrast as f32),
+    },
+    dither: parse_dither(options.dither.as_deref())?,
+  }))
+}
+
+// the hash of an image as 16 hex digits, for comparing with `storedImageHash()`
+#[napi(js_name = "imageHash")]
+pub fn js_image_hash(image: Either<Buffer, &Framebuffer>) -> String {
+  format_image_hash(image_hash(&image_bytes(image)))
+}
+
+// the waveform a panel uses for grayscale refreshes unless it's given another, as a starting point for custom ones
+#[napi]
+pub fn default_lut(panel_type: String) -> Result<Buffer, ErrorCode> {
+  Ok(Lut::default_for(parse_panel_type(&panel_type)?).bytes().to_vec().into())
+}
+
+// the card memory free for games, as the image hash and panel type take the rest
+#[napi]
+pub const MAX_MEMORY: u32 = IMAGE_HASH_ADDRESS as u32;
+
+// the most `storeData()` and the items can save between them, as two copies have to fit in `MAX_MEMORY`
+#[napi]
+pub const MAX_DATA_SIZE: u32 = storage::MAX_RECORD_SIZE as u32;
+
+// the methods every kind of plinth has, so the classes for the boards and the simulator stay in step
+macro_rules! plinth_methods {
+  ($class:ident, $plinth:ty) => {
+    #[napi]
+    impl $class {
+      // color mode is either "grayscale" (the default), with 2 bits per pixel, or "monochrome", with 1 bit per pixel.
+      // `lut` is a waveform for just this refresh. Resolves once the panel has finished refreshing
+      #[napi(ts_return_type = "Promise<void>")]
+      pub fn display_image(&self, well: u8, image: Either<Buffer, &Framebuffer>, color_mode: Option<String>, force: Option<bool>, lut: Option<Buffer>) -> Result<AsyncTask<DisplayTask<$plinth>>, ErrorCode> {
+        let color_resolution = parse_color_mode(color_mode.as_deref())?;
+        let buf = image_bytes(image);
+        Ok(AsyncTask::new(DisplayTask { plinth: self.plinth.clone(), refresh: Refresh::Full(well.into(), buf, color_resolution, refresh_options(force, lut)) }))
+      }
+
+      // shows an image on each of several wells, refreshing them all at once
+      #[napi(ts_return_type = "Promise<void>")]
+      pub fn display_images(&self, images: Vec<WellImage>, color_mode: Option<String>, force: Option<bool>, lut: Option<Buffer>) -> Result<AsyncTask<DisplayTask<$plinth>>, ErrorCode> {
+        let color_resolution = parse_color_mode(color_mode.as_deref())?;
+        let images = images.into_iter().map(|well_image| (well_image.well.into(), well_image.image.into())).collect();
+        Ok(AsyncTask::new(DisplayTask { plinth: self.plinth.clone(), refresh: Refresh::Together(images, color_resolution, refresh_options(force, lut)) }))
+      }
 
+      // redraws only the given region of the screen, in black and white, without a full screen flash
+      #[napi(ts_return_type = "Promise<void>")]
+      pub fn display_image_partial(&self, well: u8, image: Either<Buffer, &Framebuffer>, x: u16, y: u16, width: u16, height: u16) -> AsyncTask<DisplayTask<$plinth>> {
+        let buf = image_bytes(image);
+        AsyncTask::new(DisplayTask { plinth: self.plinth.clone(), refresh: Refresh::Partial(well.into(), buf, Window { x, y, width, height }) })
+      }
+
+      #[napi]
+      pub fn set_switch_callback(&self, well: u8, switch: String, callback: JsFunction) -> Result<(), ErrorCode> {
+        let s = parse_switch(&switch)?;
+
+        let tsfn: ThreadsafeFunction<u32, ErrorStrategy::CalleeHandled> = callback.create_threadsafe_function(0, |ctx| {
+          Ok(vec![ctx.value + 1])
+        }).map_err(|e| Error::new("GenericFailure", e.reason))?;
+
+        let switch_callback = move |_level| {
+          tsfn.call(Ok(1), ThreadsafeFunctionCallMode::NonBlocking);
+        };
+
+        Ok(self.plinth.set_switch_callback(well.into(), s, switch_callback)?)
+      }
+
+      #[napi]
+      pub fn read_memory(&self, well: u8, bytes: u32) -> Result<Buffer, ErrorCode> {
+        let mut buffer = vec![0; bytes as usize];
+        self.plinth.read_memory(well.into(), &mut buffer)?;
+        Ok(Buffer::from(buffer))
+      }
+
+      #[napi]
+      pub fn write_memory(&self, well: u8, data: Buffer) -> Result<(), ErrorCode> {
+        let mut data_to_write = Vec::from(data);
+        self.plinth.write_memory(well.into(), &mut data_to_write)?;
+        Ok(())
+      }
+
+      // like `read_memory()`, starting `address` bytes into the card's memory
+      #[napi]
+      pub fn read_memory_at(&self, well: u8, address: u32, bytes: u32) -> Result<Buffer, ErrorCode> {
+        let mut buffer = vec![0; bytes as usize];
+        self.plinth.read_memory_at(well.into(), address as usize, &mut buffer)?;
+        Ok(Buffer::from(buffer))
+      }
+
+      // only touches the bytes written, so it's cheaper and kinder to the memory chip than rewriting it all
+      #[napi]
+      pub fn write_memory_at(&self, well: u8, address: u32, data: Buffer) -> Result<(), ErrorCode> {
+        let mut data_to_write = Vec::from(data);
+        self.plinth.write_memory_at(well.into(), address as usize, &mut data_to_write)?;
+        Ok(())
+      }
+
+      // the data last saved in full with `write_record()`, or null if there isn't any
+      #[napi]
+      pub fn read_record(&self, well: u8) -> Result<Option<Buffer>, ErrorCode> {
+        Ok(storage::read_record(self.plinth.as_ref(), well.into())?.map(Buffer::from))
+      }
+
+      // saves data so that pulling the card out partway through leaves the previous record to read instead
+      #[napi]
+      pub fn write_record(&self, well: u8, data: Buffer) -> Result<(), ErrorCode> {
+        Ok(storage::write_record(self.plinth.as_ref(), well.into(), &data)?)
+      }
+
+      // throws away JSON saved by an older version which is too long to keep while records are written
+      #[napi]
+      pub fn discard_legacy_data(&self, well: u8) -> Result<(), ErrorCode> {
+        Ok(storage::discard_legacy_data(self.plinth.as_ref(), well.into())?)
+      }
+
+      // the value kept under `key` on the card, or null if there isn't one
+      #[napi(ts_return_type = "any")]
+      pub fn get_item(&self, env: Env, well: u8, key: String) -> Result<JsUnknown> {
+        let store = KeyValueStore::load(self.plinth.as_ref(), well.into()).map_err(|e| reject(env, e))?;
+        match store.get(&key) {
+          Some(value) => value_to_js(env, value),
+          None => Ok(env.get_null()?.into_unknown()),
+        }
+      }
+
+      // Buffers are kept as they are, anything else as MessagePack, deflated too if `compress` is set and it helps
+      #[napi(ts_args_type = "well: number, key: string, value: any, compress?: boolean | undefined | null")]
+      pub fn set_item(&self, env: Env, well: u8, key: String, value: JsUnknown, compress: Option<bool>) -> Result<()> {
+        let value = value_from_js(env, value, compress.unwrap_or(false))?;
+        let mut store = KeyValueStore::load(self.plinth.as_ref(), well.into()).map_err(|e| reject(env, e))?;
+        store.set(&key, value)
+          .and_then(|_| store.save(self.plinth.as_ref(), well.into()))
+          .map_err(|e| reject(env, e))
+      }
+
+      // returns whether there was an item to delete
+      #[napi]
+      pub fn delete_item(&self, well: u8, key: String) -> Result<bool, ErrorCode> {
+        let mut store = KeyValueStore::load(self.plinth.as_ref(), well.into())?;
+        if !store.delete(&key) {
+          return Ok(false);
+        }
+        store.save(self.plinth.as_ref(), well.into())?;
+        Ok(true)
+      }
+
+      #[napi]
+      pub fn item_keys(&self, well: u8) -> Result<Vec<String>, ErrorCode> {
+        Ok(KeyValueStore::load(self.plinth.as_ref(), well.into())?.keys().map(String::from).collect())
+      }
+
+      #[napi]
+      pub fn well_occupied(&self, well: u8) -> Result<bool, ErrorCode> {
+        let ret = self.plinth.well_occupied(well.into())?;
+        Ok(ret)
+      }
+
+      // how long to wait for a display to finish refreshing before throwing a 'BusyTimeout' error
+      #[napi]
+      pub fn set_busy_timeout(&self, milliseconds: u32) {
+        self.plinth.set_busy_timeout(Duration::from_millis(milliseconds.into()));
+      }
+
+      // pass null to detect the panel type from the card
+      #[napi]
+      pub fn set_panel_type(&self, well: u8, panel_type: Option<String>) -> Result<(), ErrorCode> {
+        let panel_type = match panel_type {
+          Some(name) => Some(parse_panel_type(&name)?),
+          None => None,
+        };
+        Ok(self.plinth.set_panel_type(well.into(), panel_type)?)
+      }
+
+      #[napi]
+      pub fn panel_type(&self, well: u8) -> Result<String, ErrorCode> {
+        Ok(format!("{:?}", self.plinth.panel_type(well.into())?))
+      }
+
+      #[napi]
+      pub fn store_panel_type(&self, well: u8, panel_type: String) -> Result<(), ErrorCode> {
+        let panel_type = parse_panel_type(&panel_type)?;
+        Ok(self.plinth.store_panel_type(well.into(), panel_type)?)
+      }
+
+      #[napi]
+      pub fn set_orientation(&self, well: u8, rotation: u32, mirrored: Option<bool>) -> Result<(), ErrorCode> {
+        let orientation = Orientation { rotation: parse_rotation(Some(rotation))?, mirrored: mirrored.unwrap_or(false) };
+        Ok(self.plinth.set_orientation(well.into(), orientation)?)
+      }
+
+      #[napi]
+      pub fn orientation(&self, well: u8) -> Result<WellOrientation, ErrorCode> {
+        Ok(self.plinth.orientation(well.into())?.into())
+      }
+
+      // a waveform for the panel in this well to use for its grayscale refreshes, or null for the built in one
+      #[napi]
+      pub fn set_lut(&self, well: u8, lut: Option<Buffer>) -> Result<(), ErrorCode> {
+        let lut = match lut {
+          Some(bytes) => Some(Lut::new(self.plinth.panel_type(well.into())?, bytes.into())?),
+          None => None,
+        };
+        Ok(self.plinth.set_lut(well.into(), lut)?)
+      }
+
+      // waveforms picked by the panel's temperature on each grayscale refresh. An empty array goes back to the built in one
+      #[napi]
+      pub fn set_temperature_luts(&self, well: u8, luts: Vec<TemperatureLut>) -> Result<(), ErrorCode> {
+        let luts = parse_temperature_luts(self.plinth.panel_type(well.into())?, luts)?;
+        Ok(self.plinth.set_temperature_luts(well.into(), luts)?)
+      }
+
+      // in degrees C, or null if the panel can't report it
+      #[napi]
+      pub fn panel_temperature(&self, well: u8) -> Result<Option<f64>, ErrorCode> {
+        Ok(self.plinth.panel_temperature(well.into())?.map(f64::from))
+      }
+
+      // the packed image last shown on this well, or null if it isn't known
+      #[napi]
+      pub fn current_image(&self, well: u8) -> Result<Option<Buffer>, ErrorCode> {
+        Ok(self.plinth.current_image(well.into())?.map(Buffer::from))
+      }
+
+      #[napi]
+      pub fn set_persist_image_hashes(&self, persist: bool) {
+        self.plinth.set_persist_image_hashes(persist)
+      }
+
+      // the hash stored on the card in this well, as 16 hex digits, or null if it doesn't have one
+      #[napi]
+      pub fn stored_image_hash(&self, well: u8) -> Result<Option<String>, ErrorCode> {
+        Ok(self.plinth.stored_image_hash(well.into())?.map(format_image_hash))
+      }
+    }
+  };
+}
 
 #[napi]
 struct JsPrototype {
-  plinth: Prototype,
+  plinth: Arc<Prototype>,
 }
 
 #[napi]
 impl JsPrototype {
   #[napi(constructor)]
-  pub fn new() -> Self {
-    JsPrototype { plinth: Prototype::new() }
+  pub fn new() -> Result<Self, ErrorCode> {
+    Ok(JsPrototype { plinth: Arc::new(Prototype::new()?) })
+  }
+}
+
+plinth_methods!(JsPrototype, Prototype);
+
+#[napi]
+struct JsDevkit {
+  plinth: Arc<DevKitV1>,
+}
+
+#[napi]
+impl JsDevkit {
+  #[napi(constructor)]
+  pub fn new() -> Result<Self, ErrorCode> {
+    Ok(JsDevkit { plinth: Arc::new(DevKitV1::new()?) })
+  }
+}
+
+plinth_methods!(JsDevkit, DevKitV1);
+
+// a card image to draw on, which can be passed straight to `displayImage()`. Coordinates are in pixels from the
+// top left corner, and anything drawn off the edge is cut off. It has the same name in rust as in javascript, as napi
+// looks up the class of a Framebuffer passed back in by its rust name
+#[napi]
+pub struct Framebuffer {
+  framebuffer: framebuffer::Framebuffer,
+}
+
+#[napi(object)]
+pub struct ShapeStyle {
+  pub fill: Option<String>,
+  pub stroke: Option<String>,
+  pub stroke_width: Option<u32>, // defaults to 1 pixel
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct TextBoxOptions {
+  pub color: Option<String>, // defaults to black
+  pub font: Option<String>, // 'tiny', 'small', 'medium', 'large' or 'x-large', defaults to small
+  pub bold: Option<bool>,
+  pub align: Option<String>, // 'left', 'center' or 'right'
+  pub vertical_align: Option<String>, // 'top', 'middle' or 'bottom'
+  pub line_spacing: Option<u32>, // extra pixels between lines, defaults to 0
+  pub rotation: Option<u32>, // 0, 90, 180 or 270 degrees clockwise
+}
+
+// drawing on a framebuffer can't fail, its error type is `Infallible`, so the `unwrap()`s below never panic
+#[napi]
+impl Framebuffer {
+  // starts out white, or as a copy of a full screen image
+  #[napi(constructor)]
+  pub fn new(image: Option<Buffer>) -> Result<Self, ErrorCode> {
+    let framebuffer = match image {
+      Some(image) => framebuffer::Framebuffer::from_packed(image.into())?,
+      None => framebuffer::Framebuffer::new(),
+    };
+    Ok(Framebuffer { framebuffer })
   }
 
   #[napi]
-  pub fn display_image(&self, well: u8, image: Buffer) {
-    let buf: Vec<u8> = image.into();
-    self.plinth.display_image(well.into(), buf);
+  pub fn clear(&mut self, color: String) -> Result<(), ErrorCode> {
+    self.framebuffer.clear(parse_color(&color)?).unwrap();
+    Ok(())
   }
 
   #[napi]
-  pub fn set_switch_callback(&mut self, well: u8, switch: String, callback: JsFunction) -> Result<()> {
-    let s:char = match switch.as_str() {
-      "a" => 'a',
-      "b" => 'b',
-      "c" => 'c',
-      _ => return Err(Error::new(Status::InvalidArg, "Must supply switch argument as a string 'a', 'b', or 'c'"))
-    };
+  pub fn get_pixel(&self, x: i32, y: i32) -> String {
+    color_name(self.framebuffer.pixel(x, y)).to_string()
+  }
 
-    let tsfn: ThreadsafeFunction<u32, ErrorStrategy::CalleeHandled> = callback.create_threadsafe_function(0, |ctx| {
-      Ok(vec![ctx.value + 1])
-    })?;
+  #[napi]
+  pub fn set_pixel(&mut self, x: i32, y: i32, color: String) -> Result<(), ErrorCode> {
+    self.framebuffer.set_pixel(x, y, parse_color(&color)?);
+    Ok(())
+  }
 
-    let switch_callback = move |level| {
-      tsfn.call(Ok(1), ThreadsafeFunctionCallMode::NonBlocking);
-    };
+  #[napi]
+  pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: String, stroke_width: Option<u32>) -> Result<(), ErrorCode> {
+    Line::new(Point::new(x0, y0), Point::new(x1, y1))
+      .into_styled(PrimitiveStyle::with_stroke(parse_color(&color)?, stroke_width.unwrap_or(1)))
+      .draw(&mut self.framebuffer).unwrap();
+    Ok(())
+  }
 
-    match self.plinth.set_switch_callback(well.into(), s, switch_callback) {
-      Ok(_) => Ok(()),
-      Err(e) => Err(Error::new(Status::GenericFailure, e))
-    }
+  #[napi]
+  pub fn draw_rect(&mut self, x: i32, y: i32, width: u32, height: u32, style: ShapeStyle) -> Result<(), ErrorCode> {
+    Rectangle::new(Point::new(x, y), Size::new(width, height))
+      .into_styled(parse_shape_style(&style)?)
+      .draw(&mut self.framebuffer).unwrap();
+    Ok(())
   }
 
   #[napi]
-  pub fn read_memory(&self, well: u8, bytes: u32) -> Result<Buffer> {
-    let mut buffer = vec![0; bytes as usize];
-    self.plinth.read_memory(well.into(), &mut buffer).map_err(|e| Error::from_reason(e))?;
-    Ok(Buffer::from(buffer))
+  pub fn draw_circle(&mut self, center_x: i32, center_y: i32, diameter: u32, style: ShapeStyle) -> Result<(), ErrorCode> {
+    Circle::with_center(Point::new(center_x, center_y), diameter)
+      .into_styled(parse_shape_style(&style)?)
+      .draw(&mut self.framebuffer).unwrap();
+    Ok(())
   }
 
+  // a single line of text in a small 6x10 pixel font, with its top left corner at (x, y)
   #[napi]
-  pub fn write_memory(&self, well: u8, data: Buffer) -> Result<()> {
-    let mut data_to_write = Vec::from(data);
-    self.plinth.write_memory(well.into(), &mut data_to_write).map_err(|e| Error::from_reason(e))?;
+  pub fn draw_text(&mut self, text: String, x: i32, y: i32, color: String) -> Result<(), ErrorCode> {
+    let style = MonoTextStyle::new(&FONT_6X10, parse_color(&color)?);
+    Text::with_baseline(&text, Point::new(x, y), style, Baseline::Top)
+      .draw(&mut self.framebuffer).unwrap();
     Ok(())
   }
 
+  // word wraps text to fit inside the box, in one of the bundled bitmap fonts. With a rotation of 90 or 270 the box is
+  // measured on the card turned landscape, 296 pixels wide and 128 tall. Returns false if some of the text didn't fit
   #[napi]
-  pub fn well_occupied(&self, well: u8) -> Result<bool> {
-    let ret = self.plinth.well_occupied(well.into());
-    Ok(ret)
+  pub fn draw_text_box(&mut self, text: String, x: i32, y: i32, width: u32, height: u32, options: Option<TextBoxOptions>) -> Result<bool, ErrorCode> {
+    let options = options.unwrap_or_default();
+    let style = TextBoxStyle {
+      font: parse_font_size(options.font.as_deref())?.font(options.bold.unwrap_or(false)),
+      color: match &options.color {
+        Some(color) => parse_color(color)?,
+        None => Gray2::BLACK,
+      },
+      horizontal_alignment: parse_align(options.align.as_deref())?,
+      vertical_alignment: parse_vertical_align(options.vertical_align.as_deref())?,
+      line_spacing: options.line_spacing.unwrap_or(0),
+    };
+    let mut target = Rotated::new(&mut self.framebuffer, parse_rotation(options.rotation)?);
+    let area = Rectangle::new(Point::new(x, y), Size::new(width, height));
+    Ok(draw_text_box(&mut target, &text, area, &style).unwrap())
+  }
+
+  // copies an image onto the framebuffer with its top left corner at (x, y). `image` is packed the same way as a
+  // full screen image, eg. from `loadImage(path, { width, height })`. Pixels of the `transparent` color are skipped
+  #[napi]
+  pub fn blit(&mut self, image: Buffer, width: u32, height: u32, x: i32, y: i32, transparent: Option<String>) -> Result<(), ErrorCode> {
+    let transparent = match transparent {
+      Some(color) => Some(parse_color(&color)?),
+      None => None,
+    };
+    Ok(self.framebuffer.blit(&image, width, height, x, y, transparent)?)
+  }
+
+  // the image, ready for `displayImage()` or to be saved
+  #[napi]
+  pub fn to_buffer(&self) -> Buffer {
+    Buffer::from(self.framebuffer.as_packed())
   }
 }
 
+// a plinth with no hardware behind it, for running and testing games anywhere.
+// The extra methods let tests play the part of the person sitting at the plinth
 #[napi]
-struct JsDevkit {
-  plinth: DevKitV1,
+struct JsSimulator {
+  plinth: Arc<SimulatedPlinth>,
 }
 
 #[napi]
-impl JsDevkit {
+impl JsSimulator {
   #[napi(constructor)]
   pub fn new() -> Self {
-    JsDevkit { plinth: DevKitV1::new() }
+    JsSimulator { plinth: Arc::new(SimulatedPlinth::new()) }
+  }
+
+  // place a blank card in the well, optionally with the given contents already in its memory
+  #[napi]
+  pub fn insert_card(&self, well: u8, memory: Option<Buffer>) -> Result<(), ErrorCode> {
+    let mut card = SimulatedCard::new();
+    if let Some(memory) = memory {
+      if memory.len() > card.memory.len() {
+        return Err(WyldcardError::MemoryOutOfRange { address: 0, length: memory.len() }.into());
+      }
+      card.memory[..memory.len()].copy_from_slice(&memory);
+    }
+
+    self.plinth.insert_card(well.into(), card)?;
+    Ok(())
   }
 
   #[napi]
-  pub fn display_image(&self, well: u8, image: Buffer) {
-    let buf: Vec<u8> = image.into();
-    self.plinth.display_image(well.into(), buf);
+  pub fn set_card_temperature(&self, well: u8, degrees: f64) -> Result<(), ErrorCode> {
+    Ok(self.plinth.set_card_temperature(well.into(), degrees as f32)?)
   }
 
+  // take the card out of the well, returning the contents of its memory
   #[napi]
-  pub fn set_switch_callback(&mut self, well: u8, switch: String, callback: JsFunction) -> Result<()> {
-    let s:char = match switch.as_str() {
-      "a" => 'a',
-      "b" => 'b',
-      "c" => 'c',
-      _ => return Err(Error::new(Status::InvalidArg, "Must supply switch argument as a string 'a', 'b', or 'c'"))
-    };
+  pub fn remove_card(&self, well: u8) -> Result<Option<Buffer>, ErrorCode> {
+    Ok(self.plinth.remove_card(well.into())?.map(|card| Buffer::from(card.memory)))
+  }
 
-    let tsfn: ThreadsafeFunction<u32, ErrorStrategy::CalleeHandled> = callback.create_threadsafe_function(0, |ctx| {
-      Ok(vec![ctx.value + 1])
-    })?;
+  #[napi]
+  pub fn press_button(&self, well: u8, switch: String) -> Result<(), ErrorCode> {
+    let s = parse_switch(&switch)?;
+    Ok(self.plinth.press_switch(well.into(), s)?)
+  }
 
-    let switch_callback = move |level| {
-      tsfn.call(Ok(1), ThreadsafeFunctionCallMode::NonBlocking);
-    };
+  #[napi]
+  pub fn release_button(&self, well: u8, switch: String) -> Result<(), ErrorCode> {
+    let s = parse_switch(&switch)?;
+    Ok(self.plinth.release_switch(well.into(), s)?)
+  }
 
-    match self.plinth.set_switch_callback(well.into(), s, switch_callback) {
-      Ok(_) => Ok(()),
-      Err(e) => Err(Error::new(Status::GenericFailure, e))
+  // the image on the card in this well, encoded as a png. Returns null if the well is empty
+  #[napi]
+  pub fn current_image_png(&self, well: u8) -> Result<Option<Buffer>, ErrorCode> {
+    match self.plinth.current_image(well.into())? {
+      Some(image) => Ok(Some(Buffer::from(encode_png(&image)?))),
+      None => Ok(None),
     }
   }
+}
 
-  #[napi]
-  pub fn read_memory(&self, well: u8, bytes: u32) -> Result<Buffer> {
-    let mut buffer = vec![0; bytes as usize];
-    self.plinth.read_memory(well.into(), &mut buffer).map_err(|e| Error::from_reason(e))?;
-    Ok(Buffer::from(buffer))
+plinth_methods!(JsSimulator, SimulatedPlinth);
+
+// images can be given to the displays either as a packed Buffer or as a Framebuffer
+fn image_bytes(image: Either<Buffer, &Framebuffer>) -> Vec<u8> {
+  match image {
+    Either::A(buffer) => buffer.into(),
+    Either::B(framebuffer) => framebuffer.framebuffer.clone().into(),
   }
+}
 
-  #[napi]
-  pub fn write_memory(&self, well: u8, data: Buffer) -> Result<()> {
-    let mut data_to_write = Vec::from(data);
-    self.plinth.write_memory(well.into(), &mut data_to_write).map_err(|e| Error::from_reason(e))?;
-    Ok(())
+// hashes are 64 bits, more than a javascript number can hold exactly
+fn format_image_hash(hash: u64) -> String {
+  format!("{:016x}", hash)
+}
+
+fn parse_color(color: &str) -> Result<Gray2, ErrorCode> {
+  match color {
+    "black" => Ok(Gray2::new(0b00)),
+    "dark-gray" => Ok(Gray2::new(0b01)),
+    "light-gray" => Ok(Gray2::new(0b10)),
+    "white" => Ok(Gray2::new(0b11)),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply color as a string 'black', 'dark-gray', 'light-gray' or 'white'")).into())
   }
+}
 
-  #[napi]
-  pub fn well_occupied(&self, well: u8) -> Result<bool> {
-    let ret = self.plinth.well_occupied(well.into());
-    Ok(ret)
+fn color_name(color: Gray2) -> &'static str {
+  match color.luma() {
+    0b00 => "black",
+    0b01 => "dark-gray",
+    0b10 => "light-gray",
+    _ => "white",
+  }
+}
+
+fn parse_shape_style(style: &ShapeStyle) -> Result<PrimitiveStyle<Gray2>, ErrorCode> {
+  let mut builder = PrimitiveStyleBuilder::new();
+  if let Some(fill) = &style.fill {
+    builder = builder.fill_color(parse_color(fill)?);
+  }
+  if let Some(stroke) = &style.stroke {
+    builder = builder.stroke_color(parse_color(stroke)?).stroke_width(style.stroke_width.unwrap_or(1));
   }
-}
\ No newline at end of file
+  Ok(builder.build())
+}
+
+fn parse_font_size(font: Option<&str>) -> Result<FontSize, ErrorCode> {
+  match font {
+    Some("tiny") => Ok(FontSize::Tiny),
+    None | Some("small") => Ok(FontSize::Small),
+    Some("medium") => Ok(FontSize::Medium),
+    Some("large") => Ok(FontSize::Large),
+    Some("x-large") => Ok(FontSize::ExtraLarge),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply font as a string 'tiny', 'small', 'medium', 'large' or 'x-large'")).into())
+  }
+}
+
+fn parse_align(align: Option<&str>) -> Result<HorizontalAlignment, ErrorCode> {
+  match align {
+    None | Some("left") => Ok(HorizontalAlignment::Left),
+    Some("center") => Ok(HorizontalAlignment::Center),
+    Some("right") => Ok(HorizontalAlignment::Right),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply align as a string 'left', 'center' or 'right'")).into())
+  }
+}
+
+fn parse_vertical_align(vertical_align: Option<&str>) -> Result<VerticalAlignment, ErrorCode> {
+  match vertical_align {
+    None | Some("top") => Ok(VerticalAlignment::Top),
+    Some("middle") => Ok(VerticalAlignment::Middle),
+    Some("bottom") => Ok(VerticalAlignment::Bottom),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply verticalAlign as a string 'top', 'middle' or 'bottom'")).into())
+  }
+}
+
+fn parse_rotation(rotation: Option<u32>) -> Result<Rotation, ErrorCode> {
+  match rotation {
+    None | Some(0) => Ok(Rotation::Deg0),
+    Some(90) => Ok(Rotation::Deg90),
+    Some(180) => Ok(Rotation::Deg180),
+    Some(270) => Ok(Rotation::Deg270),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply rotation as 0, 90, 180 or 270")).into())
+  }
+}
+
+fn parse_switch(switch: &str) -> Result<char, ErrorCode> {
+  match switch {
+    "a" => Ok('a'),
+    "b" => Ok('b'),
+    "c" => Ok('c'),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply switch argument as a string 'a', 'b', or 'c'")).into())
+  }
+}
+
+fn parse_color_mode(color_mode: Option<&str>) -> Result<ColorResolution, ErrorCode> {
+  match color_mode {
+    None | Some("grayscale") => Ok(ColorResolution::FourColorGrayscale),
+    Some("monochrome") => Ok(ColorResolution::BlackAndWhiteMonochrome),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply color mode as a string 'grayscale' or 'monochrome'")).into())
+  }
+}
+
+fn parse_fit(fit: Option<&str>) -> Result<Fit, ErrorCode> {
+  match fit {
+    None | Some("cover") => Ok(Fit::Cover),
+    Some("contain") => Ok(Fit::Contain),
+    Some("stretch") => Ok(Fit::Stretch),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply fit as a string 'cover', 'contain' or 'stretch'")).into())
+  }
+}
+
+fn parse_dither(dither: Option<&str>) -> Result<Dither, ErrorCode> {
+  match dither {
+    None | Some("floyd-steinberg") => Ok(Dither::FloydSteinberg),
+    Some("atkinson") => Ok(Dither::Atkinson),
+    Some("bayer") => Ok(Dither::Bayer),
+    Some("blue-noise") => Ok(Dither::BlueNoise),
+    Some("none") => Ok(Dither::None),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply dither as a string 'floyd-steinberg', 'atkinson', 'bayer', 'blue-noise' or 'none'")).into())
+  }
+}
+
+fn value_to_js(env: Env, value: &Value) -> Result<JsUnknown> {
+  match value {
+    Value::Binary(bytes) => Ok(env.create_buffer_with_data(bytes.clone())?.into_raw().into_unknown()),
+    Value::Json(text) => {
+      let json: JsObject = env.get_global()?.get_named_property("JSON")?;
+      let parse: JsFunction = json.get_named_property("parse")?;
+      parse.call(Some(&json), &[env.create_string(text)?])
+    },
+    Value::MessagePack(_) | Value::Deflated(_) => data_to_js(env, value.unpack().map_err(|e| reject(env, e))?),
+  }
+}
+
+fn value_from_js(env: Env, value: JsUnknown, compress: bool) -> Result<Value> {
+  if value.is_buffer()? {
+    return Ok(Value::Binary(JsBuffer::try_from(value)?.into_value()?.to_vec()));
+  }
+  Ok(Value::pack(&data_from_js(env, value, 0)?, compress))
+}
+
+// the largest whole number a javascript number holds exactly
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+// plain objects, arrays, strings, numbers, booleans, null and Buffers. Anything else is refused, rather than coming back
+// as something different, like a Date as an empty object
+fn data_from_js(env: Env, value: JsUnknown, depth: usize) -> Result<Data> {
+  if depth > msgpack::MAX_DEPTH {
+    return Err(reject(env, WyldcardError::InvalidArgument(String::from("the value is nested too deeply, or contains itself"))));
+  }
+
+  Ok(match value.get_type()? {
+    ValueType::Undefined | ValueType::Null => Data::Nil,
+    ValueType::Boolean => Data::Boolean(value.coerce_to_bool()?.get_value()?),
+    ValueType::Number => {
+      let number = value.coerce_to_number()?.get_double()?;
+      // whole numbers are packed as integers, which take less room. -0 isn't, as it would come back as 0
+      if number.fract() == 0.0 && number.abs() <= MAX_SAFE_INTEGER && !(number == 0.0 && number.is_sign_negative()) {
+        Data::Integer(number as i64)
+      } else {
+        Data::Float(number)
+      }
+    },
+    ValueType::String => Data::String(value.coerce_to_string()?.into_utf8()?.into_owned()?),
+    ValueType::Object if value.is_buffer()? => Data::Binary(JsBuffer::try_from(value)?.into_value()?.to_vec()),
+    ValueType::Object => {
+      let object = value.coerce_to_object()?;
+      if object.is_array()? {
+        let mut items = Vec::new();
+        for index in 0..object.get_array_length()? {
+          items.push(data_from_js(env, object.get_element::<JsUnknown>(index)?, depth + 1)?);
+        }
+        Data::Array(items)
+      } else if !is_plain_object(env, &object)? {
+        return Err(reject(env, WyldcardError::InvalidArgument(String::from("only plain objects can be stored on a card, not instances of classes like Date or Map"))));
+      } else {
+        let keys = object.get_property_names()?;
+        let mut entries = Vec::new();
+        for index in 0..keys.get_array_length()? {
+          let key = keys.get_element::<JsString>(index)?.into_utf8()?.into_owned()?;
+          let value = data_from_js(env, object.get_named_property::<JsUnknown>(&key)?, depth + 1)?;
+          entries.push((key, value));
+        }
+        Data::Map(entries)
+      }
+    },
+    other => return Err(reject(env, WyldcardError::InvalidArgument(format!("a {:?} can't be stored on a card", other)))),
+  })
+}
+
+// made with `{}` or `Object.create(null)`, rather than by a class
+fn is_plain_object(env: Env, object: &JsObject) -> Result<bool> {
+  let global_object = env.get_global()?.get_named_property::<JsUnknown>("Object")?.coerce_to_object()?;
+  let get_prototype_of: JsFunction = global_object.get_named_property("getPrototypeOf")?;
+  let prototype = get_prototype_of.call(Some(&global_object), &[object])?;
+  let object_prototype: JsUnknown = global_object.get_named_property("prototype")?;
+  Ok(prototype.get_type()? == ValueType::Null || env.strict_equals(prototype, object_prototype)?)
+}
+
+fn data_to_js(env: Env, data: Data) -> Result<JsUnknown> {
+  Ok(match data {
+    Data::Nil => env.get_null()?.into_unknown(),
+    Data::Boolean(boolean) => env.get_boolean(boolean)?.into_unknown(),
+    Data::Integer(integer) => env.create_double(integer as f64)?.into_unknown(),
+    Data::Float(float) => env.create_double(float)?.into_unknown(),
+    Data::String(string) => env.create_string_from_std(string)?.into_unknown(),
+    Data::Binary(bytes) => env.create_buffer_with_data(bytes)?.into_raw().into_unknown(),
+    Data::Array(items) => {
+      let mut array = env.create_array_with_length(items.len())?;
+      for (index, item) in items.into_iter().enumerate() {
+        array.set_element(index as u32, data_to_js(env, item)?)?;
+      }
+      array.into_unknown()
+    },
+    Data::Map(entries) => {
+      let mut object = env.create_object()?;
+      for (key, value) in entries {
+        object.set_named_property(&key, data_to_js(env, value)?)?;
+      }
+      object.into_unknown()
+    },
+  })
+}
+
+fn refresh_options(force: Option<bool>, lut: Option<Buffer>) -> RefreshOptions {
+  RefreshOptions { force: force.unwrap_or(false), lut: lut.map(Vec::from) }
+}
+
+fn parse_temperature_luts(panel_type: PanelType, luts: Vec<TemperatureLut>) -> Result<Vec<(f32, Lut)>, ErrorCode> {
+  luts.into_iter().map(|TemperatureLut { from, lut }| {
+    if !from.is_finite() {
+      return Err(WyldcardError::InvalidArgument(format!("waveform temperatures must be numbers of degrees, got {}", from)).into());
+    }
+    Ok((from as f32, Lut::new(panel_type, lut.into())?))
+  }).collect()
+}
+
+fn parse_panel_type(panel_type: &str) -> Result<PanelType, ErrorCode> {
+  match panel_type {
+    "GDEW029T5D" => Ok(PanelType::GDEW029T5D),
+    "GDEY029T94" => Ok(PanelType::GDEY029T94),
+    _ => Err(WyldcardError::InvalidArgument(String::from("Must supply panel type as a string 'GDEW029T5D' or 'GDEY029T94'")).into())
+  }
+}
+
+// our packed image format is already laid out like the pixel data of a 2-bit grayscale png:
+// rows of 128 pixels, four pixels to a byte, most significant bits first, 0b00 black through 0b11 white
+fn encode_png(image: &[u8]) -> Result<Vec<u8>, ErrorCode> {
+  let mut png_bytes = Vec::new();
+
+  let mut encoder = png::Encoder::new(&mut png_bytes, 128, 296);
+  encoder.set_color(png::ColorType::Grayscale);
+  encoder.set_depth(png::BitDepth::Two);
+
+  let mut writer = encoder.write_header().map_err(|e| WyldcardError::InvalidImage(e.to_string()))?;
+  writer.write_image_data(image).map_err(|e| WyldcardError::InvalidImage(e.to_string()))?;
+  writer.finish().map_err(|e| WyldcardError::InvalidImage(e.to_string()))?;
+
+  Ok(png_bytes)
+}
@@ -0,0 +1,462 @@
+use crate::error::WyldcardError;
+
+// structured data as MessagePack keeps it: the same shapes as JSON, plus raw bytes. It takes far less room than JSON
+// text, as numbers are binary and strings and containers carry their length in a byte or two rather than punctuation
+#[derive(Clone, Debug, PartialEq)]
+pub enum Data {
+  Nil,
+  Boolean(bool),
+  Integer(i64),
+  Float(f64),
+  String(String),
+  Binary(Vec<u8>),
+  Array(Vec<Data>),
+  Map(Vec<(String, Data)>),
+}
+
+// nesting deeper than this is refused, so a corrupt card can't overflow the stack
+pub const MAX_DEPTH: usize = 64;
+
+pub fn encode(data: &Data) -> Vec<u8> {
+  let mut encoded = Vec::new();
+  encode_into(data, &mut encoded);
+  encoded
+}
+
+// uses the smallest form for each value, as the spec suggests
+fn encode_into(data: &Data, out: &mut Vec<u8>) {
+  match data {
+    Data::Nil => out.push(0xc0),
+    Data::Boolean(false) => out.push(0xc2),
+    Data::Boolean(true) => out.push(0xc3),
+    Data::Integer(integer) => encode_integer(*integer, out),
+    // a float which loses nothing as an f32 is stored as one, in half the space
+    Data::Float(float) if (*float as f32) as f64 == *float => {
+      out.push(0xca);
+      out.extend_from_slice(&(*float as f32).to_be_bytes());
+    },
+    Data::Float(float) => {
+      out.push(0xcb);
+      out.extend_from_slice(&float.to_be_bytes());
+    },
+    Data::String(string) => {
+      encode_length(string.len(), Some(0xa0), 32, [0xd9, 0xda, 0xdb], out);
+      out.extend_from_slice(string.as_bytes());
+    },
+    Data::Binary(bytes) => {
+      encode_length(bytes.len(), None, 0, [0xc4, 0xc5, 0xc6], out);
+      out.extend_from_slice(bytes);
+    },
+    Data::Array(items) => {
+      encode_container_length(items.len(), 0x90, [0xdc, 0xdd], out);
+      for item in items {
+        encode_into(item, out);
+      }
+    },
+    Data::Map(entries) => {
+      encode_container_length(entries.len(), 0x80, [0xde, 0xdf], out);
+      for (key, value) in entries {
+        encode_into(&Data::String(key.clone()), out);
+        encode_into(value, out);
+      }
+    },
+  }
+}
+
+fn encode_integer(integer: i64, out: &mut Vec<u8>) {
+  match integer {
+    0..=0x7f => out.push(integer as u8),
+    -32..=-1 => out.push(integer as i8 as u8),
+    0x80..=0xff => out.extend_from_slice(&[0xcc, integer as u8]),
+    0x100..=0xffff => {
+      out.push(0xcd);
+      out.extend_from_slice(&(integer as u16).to_be_bytes());
+    },
+    0x1_0000..=0xffff_ffff => {
+      out.push(0xce);
+      out.extend_from_slice(&(integer as u32).to_be_bytes());
+    },
+    0x1_0000_0000.. => {
+      out.push(0xcf);
+      out.extend_from_slice(&(integer as u64).to_be_bytes());
+    },
+    -0x80..=-33 => out.extend_from_slice(&[0xd0, integer as i8 as u8]),
+    -0x8000..=-0x81 => {
+      out.push(0xd1);
+      out.extend_from_slice(&(integer as i16).to_be_bytes());
+    },
+    -0x8000_0000..=-0x8001 => {
+      out.push(0xd2);
+      out.extend_from_slice(&(integer as i32).to_be_bytes());
+    },
+    _ => {
+      out.push(0xd3);
+      out.extend_from_slice(&integer.to_be_bytes());
+    },
+  }
+}
+
+// strings and binary: a "fix" marker holding short lengths when the type has one, then 8, 16 and 32 bit lengths
+fn encode_length(length: usize, fix_marker: Option<u8>, fix_limit: usize, markers: [u8; 3], out: &mut Vec<u8>) {
+  match fix_marker {
+    Some(marker) if length < fix_limit => out.push(marker | length as u8),
+    _ if length <= 0xff => out.extend_from_slice(&[markers[0], length as u8]),
+    _ if length <= 0xffff => {
+      out.push(markers[1]);
+      out.extend_from_slice(&(length as u16).to_be_bytes());
+    },
+    _ => {
+      out.push(markers[2]);
+      out.extend_from_slice(&(length as u32).to_be_bytes());
+    },
+  }
+}
+
+// arrays and maps: a "fix" marker for up to 15 items, then 16 and 32 bit counts
+fn encode_container_length(length: usize, fix_marker: u8, markers: [u8; 2], out: &mut Vec<u8>) {
+  if length < 16 {
+    out.push(fix_marker | length as u8);
+  } else if length <= 0xffff {
+    out.push(markers[0]);
+    out.extend_from_slice(&(length as u16).to_be_bytes());
+  } else {
+    out.push(markers[1]);
+    out.extend_from_slice(&(length as u32).to_be_bytes());
+  }
+}
+
+// reads a single value, which has to take up all of `bytes`
+pub fn decode(bytes: &[u8]) -> Result<Data, WyldcardError> {
+  let mut reader = Reader { bytes, position: 0 };
+  let data = reader.value(0)?;
+  if reader.position != bytes.len() {
+    return Err(invalid("there are extra bytes after the value"));
+  }
+  Ok(data)
+}
+
+fn invalid(reason: &str) -> WyldcardError {
+  WyldcardError::InvalidArgument(format!("card data isn't valid MessagePack: {}", reason))
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl Reader<'_> {
+  fn take(&mut self, length: usize) -> Result<&[u8], WyldcardError> {
+    if self.bytes.len() - self.position < length {
+      return Err(invalid("it ends partway through a value"));
+    }
+    let taken = &self.bytes[self.position..self.position + length];
+    self.position += length;
+    Ok(taken)
+  }
+
+  // big endian, like every number in MessagePack
+  fn unsigned(&mut self, size: usize) -> Result<u64, WyldcardError> {
+    Ok(self.take(size)?.iter().fold(0, |value, byte| (value << 8) | *byte as u64))
+  }
+
+  fn signed(&mut self, size: usize) -> Result<i64, WyldcardError> {
+    let shift = 64 - 8 * size as u32;
+    Ok(((self.unsigned(size)? << shift) as i64) >> shift)
+  }
+
+  fn value(&mut self, depth: usize) -> Result<Data, WyldcardError> {
+    if depth > MAX_DEPTH {
+      return Err(invalid("it's nested too deeply"));
+    }
+
+    let marker = self.take(1)?[0];
+    Ok(match marker {
+      0x00..=0x7f => Data::Integer(marker as i64),
+      0x80..=0x8f => self.map((marker & 0x0f) as usize, depth)?,
+      0x90..=0x9f => self.array((marker & 0x0f) as usize, depth)?,
+      0xa0..=0xbf => self.string((marker & 0x1f) as usize)?,
+      0xc0 => Data::Nil,
+      0xc2 => Data::Boolean(false),
+      0xc3 => Data::Boolean(true),
+      0xc4..=0xc6 => {
+        let length = self.unsigned(1 << (marker - 0xc4))? as usize;
+        Data::Binary(self.take(length)?.to_vec())
+      },
+      0xca => Data::Float(f32::from_be_bytes(self.take(4)?.try_into().unwrap()) as f64),
+      0xcb => Data::Float(f64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+      0xcc..=0xce => Data::Integer(self.unsigned(1 << (marker - 0xcc))? as i64),
+      // too big for an i64, so it can only be kept approximately
+      0xcf => match self.unsigned(8)? {
+        value if value > i64::MAX as u64 => Data::Float(value as f64),
+        value => Data::Integer(value as i64),
+      },
+      0xd0..=0xd3 => Data::Integer(self.signed(1 << (marker - 0xd0))?),
+      0xd9..=0xdb => {
+        let length = self.unsigned(1 << (marker - 0xd9))? as usize;
+        self.string(length)?
+      },
+      0xdc | 0xdd => {
+        let length = self.unsigned(2 << (marker - 0xdc))? as usize;
+        self.array(length, depth)?
+      },
+      0xde | 0xdf => {
+        let length = self.unsigned(2 << (marker - 0xde))? as usize;
+        self.map(length, depth)?
+      },
+      0xe0..=0xff => Data::Integer(marker as i8 as i64),
+      _ => return Err(invalid(&format!("it uses type {:#04x}, which cards don't store", marker))),
+    })
+  }
+
+  fn string(&mut self, length: usize) -> Result<Data, WyldcardError> {
+    let bytes = self.take(length)?.to_vec();
+    Ok(Data::String(String::from_utf8(bytes).map_err(|_| invalid("a string isn't UTF-8"))?))
+  }
+
+  // a corrupt count could claim billions of items, so don't reserve room up front
+  fn array(&mut self, length: usize, depth: usize) -> Result<Data, WyldcardError> {
+    let mut items = Vec::new();
+    for _ in 0..length {
+      items.push(self.value(depth + 1)?);
+    }
+    Ok(Data::Array(items))
+  }
+
+  fn map(&mut self, length: usize, depth: usize) -> Result<Data, WyldcardError> {
+    let mut entries = Vec::new();
+    for _ in 0..length {
+      let key = match self.value(depth + 1)? {
+        Data::String(key) => key,
+        _ => return Err(invalid("a map has a key which isn't a string")),
+      };
+      entries.push((key, self.value(depth + 1)?));
+    }
+    Ok(Data::Map(entries))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // encodes to exactly `bytes`, and decodes back again
+  fn assert_encodes_as(data: Data, bytes: &[u8]) {
+    assert_eq!(encode(&data), bytes, "encoding {:?}", data);
+    assert_eq!(decode(bytes).unwrap(), data);
+  }
+
+  fn assert_invalid(bytes: &[u8]) {
+    assert!(matches!(decode(bytes), Err(WyldcardError::InvalidArgument(_))), "decoding {:02x?}", bytes);
+  }
+
+  // the marker and length which start a value, without what follows
+  fn header(data: &Data) -> Vec<u8> {
+    let encoded = encode(data);
+    let body = match data {
+      Data::String(string) => string.len(),
+      Data::Binary(bytes) => bytes.len(),
+      Data::Array(items) => items.len(),
+      Data::Map(entries) => entries.iter().map(|(key, _)| key.len() + 2).sum(),
+      _ => 0,
+    };
+    encoded[..encoded.len() - body].to_vec()
+  }
+
+  #[test]
+  fn nil_and_booleans() {
+    assert_encodes_as(Data::Nil, &[0xc0]);
+    assert_encodes_as(Data::Boolean(false), &[0xc2]);
+    assert_encodes_as(Data::Boolean(true), &[0xc3]);
+  }
+
+  #[test]
+  fn positive_integers_use_the_smallest_form() {
+    assert_encodes_as(Data::Integer(0), &[0x00]);
+    assert_encodes_as(Data::Integer(0x7f), &[0x7f]);
+    assert_encodes_as(Data::Integer(0x80), &[0xcc, 0x80]);
+    assert_encodes_as(Data::Integer(0xff), &[0xcc, 0xff]);
+    assert_encodes_as(Data::Integer(0x100), &[0xcd, 0x01, 0x00]);
+    assert_encodes_as(Data::Integer(0xffff), &[0xcd, 0xff, 0xff]);
+    assert_encodes_as(Data::Integer(0x1_0000), &[0xce, 0x00, 0x01, 0x00, 0x00]);
+    assert_encodes_as(Data::Integer(0xffff_ffff), &[0xce, 0xff, 0xff, 0xff, 0xff]);
+    assert_encodes_as(Data::Integer(0x1_0000_0000), &[0xcf, 0, 0, 0, 0x01, 0, 0, 0, 0]);
+    assert_encodes_as(Data::Integer(i64::MAX), &[0xcf, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+  }
+
+  #[test]
+  fn negative_integers_use_the_smallest_form() {
+    assert_encodes_as(Data::Integer(-1), &[0xff]);
+    assert_encodes_as(Data::Integer(-32), &[0xe0]);
+    assert_encodes_as(Data::Integer(-33), &[0xd0, 0xdf]);
+    assert_encodes_as(Data::Integer(-0x80), &[0xd0, 0x80]);
+    assert_encodes_as(Data::Integer(-0x81), &[0xd1, 0xff, 0x7f]);
+    assert_encodes_as(Data::Integer(-0x8000), &[0xd1, 0x80, 0x00]);
+    assert_encodes_as(Data::Integer(-0x8001), &[0xd2, 0xff, 0xff, 0x7f, 0xff]);
+    assert_encodes_as(Data::Integer(i32::MIN as i64), &[0xd2, 0x80, 0x00, 0x00, 0x00]);
+    assert_encodes_as(Data::Integer(i32::MIN as i64 - 1), &[0xd3, 0xff, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff]);
+    assert_encodes_as(Data::Integer(i64::MIN), &[0xd3, 0x80, 0, 0, 0, 0, 0, 0, 0]);
+  }
+
+  // other encoders don't always pick the smallest form, and the value is the same whichever they pick
+  #[test]
+  fn integers_in_a_larger_form_than_needed_still_decode() {
+    assert_eq!(decode(&[0xcc, 0x05]).unwrap(), Data::Integer(5));
+    assert_eq!(decode(&[0xce, 0, 0, 0, 0x05]).unwrap(), Data::Integer(5));
+    assert_eq!(decode(&[0xd0, 0x05]).unwrap(), Data::Integer(5));
+    assert_eq!(decode(&[0xd1, 0xff, 0xff]).unwrap(), Data::Integer(-1));
+    assert_eq!(decode(&[0xd3, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]).unwrap(), Data::Integer(-2));
+  }
+
+  #[test]
+  fn unsigned_integers_too_big_for_an_i64_come_back_as_floats() {
+    assert_eq!(decode(&[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap(), Data::Float(u64::MAX as f64));
+  }
+
+  #[test]
+  fn floats_are_kept_as_f32_only_when_nothing_is_lost() {
+    assert_encodes_as(Data::Float(0.5), &[0xca, 0x3f, 0x00, 0x00, 0x00]);
+    assert_encodes_as(Data::Float(-0.0), &[0xca, 0x80, 0x00, 0x00, 0x00]);
+    assert_encodes_as(Data::Float(f64::INFINITY), &[0xca, 0x7f, 0x80, 0x00, 0x00]);
+    assert_encodes_as(Data::Float(0.1), &[0xcb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]);
+    assert_encodes_as(Data::Float(1e300), &[&[0xcb][..], &1e300f64.to_be_bytes()].concat());
+
+    let Data::Float(zero) = decode(&[0xca, 0x80, 0x00, 0x00, 0x00]).unwrap() else { panic!() };
+    assert!(zero.is_sign_negative());
+    let Data::Float(nan) = decode(&encode(&Data::Float(f64::NAN))).unwrap() else { panic!() };
+    assert!(nan.is_nan());
+  }
+
+  #[test]
+  fn strings_switch_form_at_each_length_boundary() {
+    let string = |length: usize| Data::String("x".repeat(length));
+    assert_encodes_as(string(0), &[0xa0]);
+    assert_eq!(header(&string(31)), [0xbf]);
+    assert_eq!(header(&string(32)), [0xd9, 32]);
+    assert_eq!(header(&string(0xff)), [0xd9, 0xff]);
+    assert_eq!(header(&string(0x100)), [0xda, 0x01, 0x00]);
+    assert_eq!(header(&string(0xffff)), [0xda, 0xff, 0xff]);
+    assert_eq!(header(&string(0x1_0000)), [0xdb, 0x00, 0x01, 0x00, 0x00]);
+    for length in [0, 31, 32, 0xff, 0x100, 0xffff, 0x1_0000] {
+      assert_eq!(decode(&encode(&string(length))).unwrap(), string(length));
+    }
+    assert_encodes_as(Data::String(String::from("é")), &[0xa2, 0xc3, 0xa9]);
+  }
+
+  #[test]
+  fn binary_switches_form_at_each_length_boundary() {
+    let binary = |length: usize| Data::Binary(vec![7; length]);
+    assert_encodes_as(binary(0), &[0xc4, 0x00]);
+    assert_encodes_as(binary(2), &[0xc4, 0x02, 7, 7]);
+    assert_eq!(header(&binary(0xff)), [0xc4, 0xff]);
+    assert_eq!(header(&binary(0x100)), [0xc5, 0x01, 0x00]);
+    assert_eq!(header(&binary(0xffff)), [0xc5, 0xff, 0xff]);
+    assert_eq!(header(&binary(0x1_0000)), [0xc6, 0x00, 0x01, 0x00, 0x00]);
+    for length in [0xff, 0x100, 0xffff, 0x1_0000] {
+      assert_eq!(decode(&encode(&binary(length))).unwrap(), binary(length));
+    }
+  }
+
+  #[test]
+  fn arrays_switch_form_at_each_length_boundary() {
+    let array = |length: usize| Data::Array(vec![Data::Nil; length]);
+    assert_encodes_as(array(0), &[0x90]);
+    assert_encodes_as(array(2), &[0x92, 0xc0, 0xc0]);
+    assert_eq!(header(&array(15)), [0x9f]);
+    assert_eq!(header(&array(16)), [0xdc, 0x00, 0x10]);
+    assert_eq!(header(&array(0xffff)), [0xdc, 0xff, 0xff]);
+    assert_eq!(header(&array(0x1_0000)), [0xdd, 0x00, 0x01, 0x00, 0x00]);
+    for length in [15, 16, 0xffff, 0x1_0000] {
+      assert_eq!(decode(&encode(&array(length))).unwrap(), array(length));
+    }
+  }
+
+  #[test]
+  fn maps_switch_form_at_each_length_boundary() {
+    let map = |length: usize| Data::Map((0..length).map(|index| (format!("{:x}", index % 16), Data::Nil)).collect());
+    assert_encodes_as(map(0), &[0x80]);
+    assert_encodes_as(
+      Data::Map(vec![(String::from("a"), Data::Integer(1)), (String::from("b"), Data::Boolean(true))]),
+      &[0x82, 0xa1, b'a', 0x01, 0xa1, b'b', 0xc3],
+    );
+    assert_eq!(header(&map(15)), [0x8f]);
+    assert_eq!(header(&map(16)), [0xde, 0x00, 0x10]);
+    assert_eq!(header(&map(0xffff)), [0xde, 0xff, 0xff]);
+    assert_eq!(header(&map(0x1_0000)), [0xdf, 0x00, 0x01, 0x00, 0x00]);
+    for length in [15, 16, 0xffff, 0x1_0000] {
+      assert_eq!(decode(&encode(&map(length))).unwrap(), map(length));
+    }
+  }
+
+  #[test]
+  fn nested_values_round_trip() {
+    let data = Data::Map(vec![
+      (String::from("hp"), Data::Integer(12)),
+      (String::from("deck"), Data::Array(vec![Data::Integer(3), Data::Integer(-17), Data::Float(2.5)])),
+      (String::from("sprite"), Data::Binary(vec![0, 1, 2])),
+      (String::from("owner"), Data::Map(vec![(String::from("name"), Data::String(String::from("chukwudi")))])),
+      (String::from("lost"), Data::Nil),
+    ]);
+    assert_eq!(decode(&encode(&data)).unwrap(), data);
+  }
+
+  #[test]
+  fn values_cut_short_are_refused() {
+    let values = [
+      Data::Integer(0xffff_ffff),
+      Data::Integer(i64::MIN),
+      Data::Float(0.1),
+      Data::String("x".repeat(40)),
+      Data::Binary(vec![1; 300]),
+      Data::Array(vec![Data::Integer(1); 20]),
+      Data::Map(vec![(String::from("key"), Data::String(String::from("value")))]),
+    ];
+    for value in values {
+      let encoded = encode(&value);
+      for length in 0..encoded.len() {
+        assert_invalid(&encoded[..length]);
+      }
+    }
+  }
+
+  // a corrupt length shouldn't have the decoder allocate or loop for as long as it claims
+  #[test]
+  fn lengths_longer_than_the_data_are_refused() {
+    assert_invalid(&[0xdb, 0xff, 0xff, 0xff, 0xff, b'x']);
+    assert_invalid(&[0xc6, 0xff, 0xff, 0xff, 0xff, 0x00]);
+    assert_invalid(&[0xdd, 0xff, 0xff, 0xff, 0xff, 0xc0]);
+    assert_invalid(&[0xdf, 0xff, 0xff, 0xff, 0xff]);
+  }
+
+  #[test]
+  fn nesting_is_limited_to_max_depth() {
+    let nested = |depth: usize| [vec![0x91; depth], vec![0xc0]].concat();
+    assert!(decode(&nested(MAX_DEPTH)).is_ok());
+    assert_invalid(&nested(MAX_DEPTH + 1));
+
+    let nested_maps = |depth: usize| [[0x81, 0xa1, b'k'].repeat(depth), vec![0xc0]].concat();
+    assert!(decode(&nested_maps(MAX_DEPTH)).is_ok());
+    assert_invalid(&nested_maps(MAX_DEPTH + 1));
+  }
+
+  #[test]
+  fn trailing_bytes_are_refused() {
+    assert_invalid(&[0xc0, 0xc0]);
+    assert_invalid(&[0x01, 0x00]);
+    assert_invalid(&[0x91, 0x01, 0x02]);
+  }
+
+  #[test]
+  fn types_cards_dont_store_are_refused() {
+    // never used, an extension, and a timestamp
+    assert_invalid(&[0xc1]);
+    assert_invalid(&[0xd4, 0x01, 0x00]);
+    assert_invalid(&[0xd6, 0xff, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn strings_which_arent_utf8_and_keys_which_arent_strings_are_refused() {
+    assert_invalid(&[0xa2, 0xc3, 0x28]);
+    assert_invalid(&[0x81, 0x01, 0xc0]);
+    assert_invalid(&[0x81, 0xc4, 0x01, b'k', 0xc0]);
+  }
+}
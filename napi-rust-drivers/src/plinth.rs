@@ -4,8 +4,12 @@
 #[allow(unused_imports)]
 use std::error::Error;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::time::{ Duration };
+use std::rc::Rc;
+use std::sync::{ Mutex, MutexGuard };
+use std::thread;
+use std::time::{ Duration, Instant };
 
 
 use rppal::gpio::{ Gpio, Trigger, OutputPin, InputPin, Level };
@@ -13,13 +17,28 @@ use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
 use rppal::i2c::I2c;
 use rppal::uart::{ Uart, Parity };
 
+use embedded_hal::blocking::spi::Transfer;
+
 use m95320::prelude::*;
 use m95320::m95320::{Flash, Status};
 
 use port_expander::{ Pca9555, Pcf8574 };
+use port_expander::mode::{ Input, Output, QuasiBidirectional };
+
+use shared_bus::{ BusManagerStd, I2cProxy };
 
-use crate::GDEW029T5D::GDEW029T5DController;
-use crate::GDEY029T94::GDEY029T94Controller;
+use crate::epd::{ check_image_size, monochrome_to_grayscale, new_driver, wait_while_busy, Capabilities, ColorResolution, EpdDriver, Lut, Orientation, PanelType, Window, DEFAULT_BUSY_TIMEOUT };
+use crate::error::WyldcardError;
+use crate::framebuffer::{ WIDTH, HEIGHT };
+
+pub const CARD_MEMORY_SIZE: usize = 4096; // bytes, the size of the M95320
+pub const PANEL_TYPE_ADDRESS: usize = CARD_MEMORY_SIZE - 1; // the last byte of card memory is reserved for the card's panel type
+pub const IMAGE_HASH_ADDRESS: usize = PANEL_TYPE_ADDRESS - 8; // and the 8 bytes before it for a hash of the image on its screen
+// both boards drive the panels over 3-wire SPI without their data line wired to MISO, so nothing they send can be
+// read, and panels don't try to read their temperature
+const PANEL_DATA_LINE_READABLE: bool = false;
+const MEMORY_PAGE_SIZE: usize = 32; // bytes the M95320 writes at once. Writes past the end of a page wrap around to its start
+const MEMORY_WRITE_TIMEOUT: Duration = Duration::from_millis(50); // a page should take at most 5ms
 
 #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 enum GpioExpander {
@@ -39,525 +58,876 @@ struct Well {
   memory_chip_select_pin: VirtualPinAddress,
 }
 
-pub trait Plinth {
-  fn display_image(&self, well: usize, image: Vec<u8>);
-  fn well_occupied(&self, well: usize) -> bool;
-  fn read_memory(&self, well: usize, buffer: &mut [u8]) -> Result<(), String>;
-  fn write_memory(&self, well: usize, buffer: &mut [u8]) -> Result<(), String>;
-  fn set_switch_callback(&mut self, well: usize, switch: char, callback: impl FnMut(Level) + Send + 'static) -> Result<(), String>;
-}
-
-pub struct DevKitV1 {
-  gpio_expander_addresses: HashMap<GpioExpander, (bool, bool, bool)>,
-  wyldcard_wells: [Well; 4],
-  uart: Uart,
-  switch_names: HashMap<u8, String>,
-  switches: HashMap<(usize, char), InputPin>,
-}
-
-impl Plinth for DevKitV1 {
-  fn display_image(&self, well: usize, image: Vec<u8>) {
-    let pin_assignments = self.wyldcard_wells[well];
-
-    let gpio = Gpio::new().unwrap();
-    let data_or_command = gpio.get(pin_assignments.data_or_command_pin).unwrap().into_output();
-
-    let i2c = I2c::new().unwrap();
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).unwrap();
-
-    // we're assuming all pins for a card are using the same expander
-    let expander_address = self.gpio_expander_addresses[&pin_assignments.busy_pin.0];
-    let mut expander = Pca9555::new(i2c, expander_address.0, expander_address.1, expander_address.2);
-    let virtual_gpios = expander.split();
-
-    let mut pins = HashMap::from([
-      (0, virtual_gpios.io0_0),
-      (1, virtual_gpios.io0_1),
-      (2, virtual_gpios.io0_2),
-      (3, virtual_gpios.io0_3),
-      (4, virtual_gpios.io0_4),
-      (5, virtual_gpios.io0_5),
-      (6, virtual_gpios.io0_6),
-      (7, virtual_gpios.io0_7),
-      (8, virtual_gpios.io1_0),
-      (9, virtual_gpios.io1_1),
-      (10, virtual_gpios.io1_2),
-      (11, virtual_gpios.io1_3),
-      (12, virtual_gpios.io1_4),
-      (13, virtual_gpios.io1_5),
-      (14, virtual_gpios.io1_6),
-      (15, virtual_gpios.io1_7),
-    ]);
+// the gpio expanders share one I2C bus. It's kept for the life of the program, so the expanders and their pins can be too
+type SharedI2c = I2cProxy<'static, Mutex<I2c>>;
+type Pca9555Pin<MODE> = port_expander::Pin<'static, MODE, Mutex<port_expander::dev::pca9555::Driver<SharedI2c>>>;
+type Pcf8574Pin = port_expander::Pin<'static, QuasiBidirectional, Mutex<port_expander::dev::pcf8574::Driver<SharedI2c>>>;
 
-    let reset = pins.remove(&pin_assignments.reset_pin.1).expect("missing pin").into_output().expect("reset pin");
-    let busy = pins.remove(&pin_assignments.busy_pin.1).expect("missing pin").into_input().expect("busy pin");
-    let epd_chip_select = pins.remove(&pin_assignments.epd_chip_select_pin.1).expect("missing pin").into_output().expect("cs pin");
-
-    let mut display = GDEY029T94Controller::new(
-                      reset,
-                      busy,
-                      data_or_command,
-                      epd_chip_select,
-                      spi).expect("create epd");
-
-    if let Err(e) = display.start_epd() {
-      println!("{}", e); return;
-    }
-    display.display_image(image);
-    display.sleep();
-  }
-
-  fn well_occupied(&self, well: usize) -> bool {
-    let pin_assignments = self.wyldcard_wells[well];
-
-    let i2c = I2c::new().unwrap();
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).unwrap();
-
-    // we're assuming all pins for a card are using the same expander
-    let expander_address = self.gpio_expander_addresses[&pin_assignments.busy_pin.0];
-    let mut expander = Pca9555::new(i2c, expander_address.0, expander_address.1, expander_address.2);
-    let virtual_gpios = expander.split();
-
-    let mut pins = HashMap::from([
-      (0, virtual_gpios.io0_0),
-      (1, virtual_gpios.io0_1),
-      (2, virtual_gpios.io0_2),
-      (3, virtual_gpios.io0_3),
-      (4, virtual_gpios.io0_4),
-      (5, virtual_gpios.io0_5),
-      (6, virtual_gpios.io0_6),
-      (7, virtual_gpios.io0_7),
-      (8, virtual_gpios.io1_0),
-      (9, virtual_gpios.io1_1),
-      (10, virtual_gpios.io1_2),
-      (11, virtual_gpios.io1_3),
-      (12, virtual_gpios.io1_4),
-      (13, virtual_gpios.io1_5),
-      (14, virtual_gpios.io1_6),
-      (15, virtual_gpios.io1_7),
-    ]);
+fn new_shared_i2c() -> Result<&'static BusManagerStd<I2c>, WyldcardError> {
+  let i2c = I2c::new().map_err(WyldcardError::bus)?;
+  Ok(Box::leak(Box::new(BusManagerStd::new(i2c))))
+}
 
-    let memory_chip_select = pins.remove(&pin_assignments.memory_chip_select_pin.1).expect("missing pin").into_output().expect("mem chip select");
+// the expander pins wired to one well's display, claimed once when the plinth is created. The card's memory chip
+// select is kept apart from these, so the card's memory can be used while its display is refreshing
+struct WellPins<OUTPUT, INPUT> {
+  reset: OUTPUT,
+  busy: INPUT,
+  epd_chip_select: OUTPUT,
+}
 
-    let mut flash = Flash::init(
-                    spi,
-                    memory_chip_select,
-                  ).expect("memory");
+// every well shares the SPI bus and the data/command line, so hold this lock for as long as you're talking to a chip.
+// Always lock the well's pins or memory chip select first, then the bus
+struct SpiBus<SPI = Spi, DC = OutputPin> {
+  spi: SPI,
+  data_or_command: DC,
+}
 
-    flash._write_enable();
+// the drivers want to own their pins and bus, this lends them the plinth's for the length of one call
+struct Borrowed<'a, T>(&'a mut T);
 
-    let status = flash.read_status().expect("get status");
+impl<'a, T: embedded_hal::digital::v2::OutputPin> embedded_hal::digital::v2::OutputPin for Borrowed<'a, T> {
+  type Error = T::Error;
 
-    match status {
-      Status::WRITE_ENABLE_LATCH => {
-        flash._write_disable();
-        true
-      },
-      _ => false
-    }
-  }
-
-  fn read_memory(&self, well: usize, buffer: &mut [u8]) -> Result<(), String> {
-    let pin_assignments = self.wyldcard_wells[well];
-
-    let i2c = I2c::new().unwrap();
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).unwrap();
-
-    // we're assuming all pins for a card are using the same expander
-    let expander_address = self.gpio_expander_addresses[&pin_assignments.busy_pin.0];
-    let mut expander = Pca9555::new(i2c, expander_address.0, expander_address.1, expander_address.2);
-    let virtual_gpios = expander.split();
-
-    let mut pins = HashMap::from([
-      (0, virtual_gpios.io0_0),
-      (1, virtual_gpios.io0_1),
-      (2, virtual_gpios.io0_2),
-      (3, virtual_gpios.io0_3),
-      (4, virtual_gpios.io0_4),
-      (5, virtual_gpios.io0_5),
-      (6, virtual_gpios.io0_6),
-      (7, virtual_gpios.io0_7),
-      (8, virtual_gpios.io1_0),
-      (9, virtual_gpios.io1_1),
-      (10, virtual_gpios.io1_2),
-      (11, virtual_gpios.io1_3),
-      (12, virtual_gpios.io1_4),
-      (13, virtual_gpios.io1_5),
-      (14, virtual_gpios.io1_6),
-      (15, virtual_gpios.io1_7),
-    ]);
+  fn set_low(&mut self) -> Result<(), Self::Error> {
+    self.0.set_low()
+  }
 
-    let memory_chip_select = pins.remove(&pin_assignments.memory_chip_select_pin.1).expect("missing pin").into_output().expect("mem chip select");
+  fn set_high(&mut self) -> Result<(), Self::Error> {
+    self.0.set_high()
+  }
+}
 
-    let mut flash = Flash::init(
-                    spi,
-                    memory_chip_select,
-                  ).expect("memory");
-
-    let result = flash.read(0, buffer);
-
-    match result {
-      Err(e) => return Err(e.to_string()),
-      Ok(_) => return Ok(()),
-    }
-  }
-
-  fn write_memory(&self, well: usize, buffer: &mut [u8]) -> Result<(), String> {
-    let pin_assignments = self.wyldcard_wells[well];
-
-    let i2c = I2c::new().unwrap();
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).unwrap();
-
-    // we're assuming all pins for a card are using the same expander
-    let expander_address = self.gpio_expander_addresses[&pin_assignments.busy_pin.0];
-    let mut expander = Pca9555::new(i2c, expander_address.0, expander_address.1, expander_address.2);
-    let virtual_gpios = expander.split();
-
-    let mut pins = HashMap::from([
-      (0, virtual_gpios.io0_0),
-      (1, virtual_gpios.io0_1),
-      (2, virtual_gpios.io0_2),
-      (3, virtual_gpios.io0_3),
-      (4, virtual_gpios.io0_4),
-      (5, virtual_gpios.io0_5),
-      (6, virtual_gpios.io0_6),
-      (7, virtual_gpios.io0_7),
-      (8, virtual_gpios.io1_0),
-      (9, virtual_gpios.io1_1),
-      (10, virtual_gpios.io1_2),
-      (11, virtual_gpios.io1_3),
-      (12, virtual_gpios.io1_4),
-      (13, virtual_gpios.io1_5),
-      (14, virtual_gpios.io1_6),
-      (15, virtual_gpios.io1_7),
-    ]);
+impl<'a, T: embedded_hal::digital::v2::InputPin> embedded_hal::digital::v2::InputPin for Borrowed<'a, T> {
+  type Error = T::Error;
 
-    let memory_chip_select = pins.remove(&pin_assignments.memory_chip_select_pin.1).expect("missing pin").into_output().expect("mem chip select");
+  fn is_high(&self) -> Result<bool, Self::Error> {
+    self.0.is_high()
+  }
 
-    let mut flash = Flash::init(
-                    spi,
-                    memory_chip_select,
-                  ).expect("memory");
+  fn is_low(&self) -> Result<bool, Self::Error> {
+    self.0.is_low()
+  }
+}
 
-    let result = flash.write_bytes(0, buffer);
+impl<'a, T: Transfer<u8>> Transfer<u8> for Borrowed<'a, T> {
+  type Error = T::Error;
 
-    match result {
-      Err(e) => return Err(e.to_string()),
-      Ok(_) => return Ok(()),
-    }
+  fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+    self.0.transfer(words)
   }
+}
 
-  // switches are identified by well number and then switch 'a', 'b', or 'c'
-  fn set_switch_callback(&mut self, well: usize, switch: char, callback: impl FnMut(Level) + Send + 'static) -> Result<(), String> {
-    if !['a','b','c'].contains(&switch) {
-      return Err(String::from("Invalid switch"));
+// the bus, lent to a display driver a transaction at a time. It's locked when the panel's chip select goes low and
+// unlocked when it goes high, so while the panel is busy the bus is free for other wells and for card memory
+struct LentBus<'a, SPI, DC> {
+  bus: &'a Mutex<SpiBus<SPI, DC>>,
+  locked: Option<MutexGuard<'a, SpiBus<SPI, DC>>>,
+}
+
+type SharedLentBus<'a, SPI, DC> = Rc<RefCell<LentBus<'a, SPI, DC>>>;
+
+// what the driver gets in place of the panel's chip select, the bus and the data/command line
+struct LentChipSelect<'a, CS, SPI, DC>(&'a mut CS, SharedLentBus<'a, SPI, DC>);
+struct LentSpi<'a, SPI, DC>(SharedLentBus<'a, SPI, DC>);
+struct LentDataOrCommand<'a, SPI, DC>(SharedLentBus<'a, SPI, DC>);
+
+#[derive(Debug)]
+enum LentBusError<E> {
+  NotSelected, // the bus was used without selecting the panel first
+  Bus(E),
+}
+
+impl<'a, CS: embedded_hal::digital::v2::OutputPin, SPI, DC> embedded_hal::digital::v2::OutputPin for LentChipSelect<'a, CS, SPI, DC> {
+  type Error = CS::Error;
+
+  fn set_low(&mut self) -> Result<(), Self::Error> {
+    let mut lent = self.1.borrow_mut();
+    if lent.locked.is_none() {
+      lent.locked = Some(lent.bus.lock().unwrap());
+    }
+    let selected = self.0.set_low();
+    if selected.is_err() {
+      lent.locked = None;
     }
+    selected
+  }
 
-    let switch = self.switches.get_mut(&(well, switch)).unwrap();
+  fn set_high(&mut self) -> Result<(), Self::Error> {
+    let deselected = self.0.set_high();
+    self.1.borrow_mut().locked = None;
+    deselected
+  }
+}
 
-    switch.set_async_interrupt(Trigger::FallingEdge, callback).expect("set switch");
+impl<'a, SPI: Transfer<u8>, DC> Transfer<u8> for LentSpi<'a, SPI, DC> {
+  type Error = LentBusError<SPI::Error>;
 
-    Ok(())
+  fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+    let mut lent = self.0.borrow_mut();
+    let bus = lent.locked.as_mut().ok_or(LentBusError::NotSelected)?;
+    bus.spi.transfer(words).map_err(LentBusError::Bus)
   }
 }
 
-impl DevKitV1 {
-  pub fn new() -> DevKitV1 {
+impl<'a, SPI, DC: embedded_hal::digital::v2::OutputPin> embedded_hal::digital::v2::OutputPin for LentDataOrCommand<'a, SPI, DC> {
+  type Error = LentBusError<DC::Error>;
 
-    let gpio_expander_addresses = HashMap::from([
-      (GpioExpander::EXPANDER0, (false, false, false)),
-    ]);
+  fn set_low(&mut self) -> Result<(), Self::Error> {
+    let mut lent = self.0.borrow_mut();
+    let bus = lent.locked.as_mut().ok_or(LentBusError::NotSelected)?;
+    bus.data_or_command.set_low().map_err(LentBusError::Bus)
+  }
 
-    let wyldcard_wells = [
-      Well {
-        reset_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 0),
-        busy_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 1),
-        data_or_command_pin: 25,
-        epd_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 2),
-        memory_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 3),
-      },
-      Well {
-        reset_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 4),
-        busy_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 5),
-        data_or_command_pin: 25,
-        epd_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 6),
-        memory_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 7),
-      },
-      Well {
-        reset_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 8),
-        busy_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 9),
-        data_or_command_pin: 25,
-        epd_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 10),
-        memory_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 11),
-      },
-      Well {
-        reset_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 12),
-        busy_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 13),
-        data_or_command_pin: 25,
-        epd_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 14),
-        memory_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 15),
+  fn set_high(&mut self) -> Result<(), Self::Error> {
+    let mut lent = self.0.borrow_mut();
+    let bus = lent.locked.as_mut().ok_or(LentBusError::NotSelected)?;
+    bus.data_or_command.set_high().map_err(LentBusError::Bus)
+  }
+}
+
+// how a full refresh is done
+#[derive(Clone, Debug, Default)]
+pub struct RefreshOptions {
+  pub force: bool, // refresh even if the card is `already_showing()` the image
+  pub lut: Option<Vec<u8>>, // a waveform for just this refresh, in place of the well's. See `Lut`
+}
+
+// the waveforms a grayscale refresh can use. The panel picks from `by_temperature` when it's started, as
+// (the lowest temperature in degrees C each is for, lut), unless it's given a `lut`
+#[derive(Clone, Debug, Default)]
+pub struct Waveforms {
+  pub lut: Option<Lut>,
+  pub by_temperature: Vec<(f32, Lut)>,
+}
+
+// plinths are shared with the worker threads which run display refreshes, so every method takes &self and
+// anything which changes is kept behind a lock
+pub trait Plinth {
+  // refreshing is slow and wears the panel, so unless it's forced an image the card is `already_showing()` is skipped
+  fn display_image(&self, well: usize, image: Vec<u8>, color_resolution: ColorResolution, options: &RefreshOptions) -> Result<(), WyldcardError>;
+  // shows an image on each of several wells, taking about as long as refreshing one. Plinths which can't
+  // refresh their wells together show the images one after another
+  fn display_images(&self, images: Vec<(usize, Vec<u8>)>, color_resolution: ColorResolution, options: &RefreshOptions) -> Result<(), WyldcardError> {
+    for (well, image) in images {
+      self.display_image(well, image, color_resolution, options)?;
+    }
+    Ok(())
+  }
+  // redraw just one region of the screen, quickly and without a full screen flash. Only black and white are
+  // shown, and panels without partial refresh redraw the whole image
+  fn display_partial(&self, well: usize, image: Vec<u8>, window: Window) -> Result<(), WyldcardError>;
+  fn well_occupied(&self, well: usize) -> Result<bool, WyldcardError>;
+  // card memory is read and written from `address` onwards, so small fields can be updated without rewriting the
+  // whole chip, which wears it out sooner
+  fn read_memory_at(&self, well: usize, address: usize, buffer: &mut [u8]) -> Result<(), WyldcardError>;
+  fn write_memory_at(&self, well: usize, address: usize, buffer: &mut [u8]) -> Result<(), WyldcardError>;
+  fn read_memory(&self, well: usize, buffer: &mut [u8]) -> Result<(), WyldcardError> {
+    self.read_memory_at(well, 0, buffer)
+  }
+  fn write_memory(&self, well: usize, buffer: &mut [u8]) -> Result<(), WyldcardError> {
+    self.write_memory_at(well, 0, buffer)
+  }
+  fn set_switch_callback(&self, well: usize, switch: char, callback: impl FnMut(Level) + Send + 'static) -> Result<(), WyldcardError>;
+  // how long to wait on a busy display before giving up with `WyldcardError::BusyTimeout`
+  fn set_busy_timeout(&self, timeout: Duration);
+
+  // choose which display controller drives this well. Passing None goes back to detecting it from the card
+  fn set_panel_type(&self, well: usize, panel_type: Option<PanelType>) -> Result<(), WyldcardError>;
+  // the display controller which will be used for this well
+  fn panel_type(&self, well: usize) -> Result<PanelType, WyldcardError>;
+
+  // turns every image shown on this well, including partial updates
+  fn set_orientation(&self, well: usize, orientation: Orientation) -> Result<(), WyldcardError>;
+  fn orientation(&self, well: usize) -> Result<Orientation, WyldcardError>;
+
+  // a custom waveform for this well's grayscale refreshes, in place of the panel's built in one. None goes back
+  // to the built in one
+  fn set_lut(&self, well: usize, lut: Option<Lut>) -> Result<(), WyldcardError>;
+  fn lut(&self, well: usize) -> Result<Option<Lut>, WyldcardError>;
+  // waveforms for this well's grayscale refreshes chosen by how warm the panel is, as (the lowest temperature in
+  // degrees C each is for, lut). Each is used up to the next one's temperature, and the coldest below all of them.
+  // A `set_lut()` waveform takes priority
+  fn set_temperature_luts(&self, well: usize, luts: Vec<(f32, Lut)>) -> Result<(), WyldcardError>;
+  fn temperature_luts(&self, well: usize) -> Result<Vec<(f32, Lut)>, WyldcardError>;
+  // the temperature of the panel in the well in degrees C, or None if its panel can't report it. Hardware plinths
+  // can't read their panels back, see `PANEL_DATA_LINE_READABLE`, so they always give None
+  fn panel_temperature(&self, well: usize) -> Result<Option<f32>, WyldcardError>;
+
+  // the waveforms a refresh of this well uses: `lut` if one was given for just this refresh, otherwise the well's
+  // own, along with its temperature waveforms. Checks they suit the panel in the well, which may have changed since
+  fn waveforms(&self, well: usize, lut: Option<&[u8]>) -> Result<Waveforms, WyldcardError> {
+    let lut = match lut {
+      Some(bytes) => Some(Lut::new(self.panel_type(well)?, bytes.to_vec())?),
+      None => self.lut(well)?,
+    };
+    let waveforms = Waveforms { lut, by_temperature: self.temperature_luts(well)? };
+
+    if waveforms.lut.is_some() || !waveforms.by_temperature.is_empty() {
+      let panel_type = self.panel_type(well)?;
+      let luts = waveforms.lut.iter().chain(waveforms.by_temperature.iter().map(|(_, lut)| lut));
+      for lut in luts {
+        if lut.panel_type() != panel_type {
+          return Err(WyldcardError::InvalidArgument(format!("well {} has a {:?} waveform, but its panel is a {:?}", well, lut.panel_type(), panel_type)));
+        }
       }
-    ];
+    }
+    Ok(waveforms)
+  }
 
-    let mut uart = Uart::new(9600, Parity::Even, 8, 1).expect("uart");
-    uart.set_write_mode(true).expect("uart");
-    uart.set_read_mode(0, Duration::from_millis(100)).expect("uart");
-
-
-    let gpio = Gpio::new().unwrap();
-
-    const GPIO_SWITCH_A0: u8 = 23; // gpio #4 throws "in use" errors :(
-    const GPIO_SWITCH_B0: u8 = 5;
-    const GPIO_SWITCH_C0: u8 = 6;
-  
-    const GPIO_SWITCH_A1: u8 = 12;
-    const GPIO_SWITCH_B1: u8 = 13;
-    const GPIO_SWITCH_C1: u8 = 21;
-  
-    const GPIO_SWITCH_A2: u8 = 22;
-    const GPIO_SWITCH_B2: u8 = 16;
-    const GPIO_SWITCH_C2: u8 = 17;
-  
-    const GPIO_SWITCH_A3: u8 = 18;
-    const GPIO_SWITCH_B3: u8 = 19;
-    const GPIO_SWITCH_C3: u8 = 20;
-
-    // setup switches
-    let switch_a0 = gpio.get(GPIO_SWITCH_A0).unwrap().into_input_pullup();
-    let switch_b0 = gpio.get(GPIO_SWITCH_B0).unwrap().into_input_pullup();
-    let switch_c0 = gpio.get(GPIO_SWITCH_C0).unwrap().into_input_pullup();
-    let switch_a1 = gpio.get(GPIO_SWITCH_A1).unwrap().into_input_pullup();
-    let switch_b1 = gpio.get(GPIO_SWITCH_B1).unwrap().into_input_pullup();
-    let switch_c1 = gpio.get(GPIO_SWITCH_C1).unwrap().into_input_pullup();
-    let switch_a2 = gpio.get(GPIO_SWITCH_A2).unwrap().into_input_pullup();
-    let switch_b2 = gpio.get(GPIO_SWITCH_B2).unwrap().into_input_pullup();
-    let switch_c2 = gpio.get(GPIO_SWITCH_C2).unwrap().into_input_pullup();
-    let switch_a3 = gpio.get(GPIO_SWITCH_A3).unwrap().into_input_pullup();
-    let switch_b3 = gpio.get(GPIO_SWITCH_B3).unwrap().into_input_pullup();
-    let switch_c3 = gpio.get(GPIO_SWITCH_C3).unwrap().into_input_pullup();
-    
-    let switch_names = HashMap::from([
-      (GPIO_SWITCH_A0, String::from("Switch A for Card 0")),
-      (GPIO_SWITCH_B0, String::from("Switch B for Card 0")),
-      (GPIO_SWITCH_C0, String::from("Switch C for Card 0")),
-      (GPIO_SWITCH_A1, String::from("Switch A for Card 1")),
-      (GPIO_SWITCH_B1, String::from("Switch B for Card 1")),
-      (GPIO_SWITCH_C1, String::from("Switch C for Card 1")),
-      (GPIO_SWITCH_A2, String::from("Switch A for Card 2")),
-      (GPIO_SWITCH_B2, String::from("Switch B for Card 2")),
-      (GPIO_SWITCH_C2, String::from("Switch C for Card 2")),
-      (GPIO_SWITCH_A3, String::from("Switch A for Card 3")),
-      (GPIO_SWITCH_B3, String::from("Switch B for Card 3")),
-      (GPIO_SWITCH_C3, String::from("Switch C for Card 3")),
-    ]);
+  // the image last shown on this well, as the panel has it: portrait, in 2-bit grayscale, after any orientation.
+  // None if it isn't known, eg. nothing has been shown since the plinth started, or the card's been swapped since.
+  // Plinths can't see the screens, so hardware plinths go by the card's memory, see `ShownImages`
+  fn current_image(&self, well: usize) -> Result<Option<Vec<u8>>, WyldcardError>;
+  // whether to write the `image_hash()` of every image shown to the card, so it's known even after a restart.
+  // Off by default, as it means writing to card memory after every refresh
+  fn set_persist_image_hashes(&self, persist: bool);
+  fn persisting_image_hashes(&self) -> bool;
+
+  // whether showing `image` would leave the card in this well looking just the same: it's the well's
+  // `current_image()`, and with persisted hashes the card's own hash matches as well
+  fn already_showing(&self, well: usize, image: &[u8], color_resolution: ColorResolution) -> Result<bool, WyldcardError> {
+    let current = match self.current_image(well)? {
+      Some(current) => current,
+      None => return Ok(false),
+    };
+    // an empty well isn't skipped, so it's refreshed just as it would be without this check. The simulator fails
+    // that with `CardNotPresent`, but hardware plinths don't check and drive a panel which isn't there
+    if !self.well_occupied(well)? {
+      return Ok(false);
+    }
 
-    DevKitV1 {
-      gpio_expander_addresses,
-      wyldcard_wells,
-      uart,
-      switch_names,
-      switches: HashMap::from([
-        ((0, 'a'), switch_a0),
-        ((0, 'b'), switch_b0),
-        ((0, 'c'), switch_c0),
-        ((1, 'a'), switch_a1),
-        ((1, 'b'), switch_b1),
-        ((1, 'c'), switch_c1),
-        ((2, 'a'), switch_a2),
-        ((2, 'b'), switch_b2),
-        ((2, 'c'), switch_c2),
-        ((3, 'a'), switch_a3),
-        ((3, 'b'), switch_b3),
-        ((3, 'c'), switch_c3),
-      ]),
+    check_image_size(image, (WIDTH * HEIGHT) as usize * color_resolution.bits_per_pixel() / 8)?;
+    let image = as_grayscale(self.orientation(well)?.orient_image(image.to_vec(), color_resolution), color_resolution);
+    if image != current {
+      return Ok(false);
+    }
+
+    // a hash which can't be read is a cache miss like any other
+    if self.persisting_image_hashes() {
+      return Ok(self.stored_image_hash(well).ok().flatten() == Some(image_hash(&image)));
     }
+    Ok(true)
+  }
+
+  // the hash of the image the card was showing when it was last written, if it's been stored
+  fn stored_image_hash(&self, well: usize) -> Result<Option<u64>, WyldcardError> {
+    let mut hash = [0; 8];
+    self.read_memory_at(well, IMAGE_HASH_ADDRESS, &mut hash)?;
+    // erased memory reads as all ones
+    if hash.iter().all(|byte| *byte == 0xFF) {
+      return Ok(None);
+    }
+    Ok(Some(u64::from_le_bytes(hash)))
+  }
+
+  fn store_image_hash(&self, well: usize, hash: u64) -> Result<(), WyldcardError> {
+    self.write_memory_at(well, IMAGE_HASH_ADDRESS, &mut hash.to_le_bytes())
+  }
+
+  // reads the panel type byte the card was programmed with, if any
+  fn detect_panel_type(&self, well: usize) -> Option<PanelType> {
+    let mut id = [0];
+    self.read_memory_at(well, PANEL_TYPE_ADDRESS, &mut id).ok()?;
+    PanelType::from_id(id[0])
+  }
+
+  // programs the card in this well with its panel type, so any plinth can detect it later
+  fn store_panel_type(&self, well: usize, panel_type: PanelType) -> Result<(), WyldcardError> {
+    self.write_memory_at(well, PANEL_TYPE_ADDRESS, &mut [panel_type.id()])
   }
 }
 
-pub struct Prototype {
-  gpio_expander_addresses: HashMap<GpioExpander, (bool, bool, bool)>,
-  wyldcard_wells: [Well; 4],
-  uart: Uart,
-  switch_names: HashMap<u8, String>,
-  switches: HashMap<(usize, char), InputPin>,
+fn check_well(well: usize) -> Result<(), WyldcardError> {
+  match well {
+    0..=3 => Ok(()),
+    _ => Err(WyldcardError::InvalidWell(well)),
+  }
 }
 
-impl Plinth for Prototype {
-  fn display_image(&self, well: usize, image: Vec<u8>) {
-    let pin_assignments = self.wyldcard_wells[well];
+fn check_memory_range(address: usize, length: usize) -> Result<(), WyldcardError> {
+  if address.checked_add(length).is_none_or(|end| end > CARD_MEMORY_SIZE) {
+    return Err(WyldcardError::MemoryOutOfRange { address, length });
+  }
+  Ok(())
+}
 
-    let gpio = Gpio::new().unwrap();
-    let data_or_command = gpio.get(pin_assignments.data_or_command_pin).unwrap().into_output();
+// checks `length` bytes from `address` are all on the chip, and gives the address the way the chip takes it
+fn memory_address(address: usize, length: usize) -> Result<u16, WyldcardError> {
+  check_memory_range(address, length)?;
+  u16::try_from(address).map_err(|_| WyldcardError::MemoryOutOfRange { address, length })
+}
 
-    let i2c = I2c::new().unwrap();
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).unwrap();
+// the M95320's instructions, for writes. The m95320 crate's own page writes panic on short writes that don't start
+// a page, and wait on the chip forever if the card is pulled out
+const MEMORY_WRITE_ENABLE: u8 = 0x06;
+const MEMORY_READ_STATUS: u8 = 0x05;
+const MEMORY_READ: u8 = 0x03;
+const MEMORY_WRITE: u8 = 0x02;
+
+// writes a page at a time, waiting for each one to finish and reading it back to check it landed. A card pulled out
+// partway through leaves the pages after it untouched, and the error names the first byte which didn't make it
+fn write_memory_verified<SPI, CS>(spi: &mut SPI, chip_select: &mut CS, address: usize, data: &[u8]) -> Result<(), WyldcardError>
+  where SPI: Transfer<u8>,
+        SPI::Error: std::fmt::Debug,
+        CS: embedded_hal::digital::v2::OutputPin,
+        CS::Error: std::fmt::Debug {
+  let mut written = 0;
+  while written < data.len() {
+    let page_address = address + written;
+    let length = (MEMORY_PAGE_SIZE - page_address % MEMORY_PAGE_SIZE).min(data.len() - written);
+    let page = &data[written..written + length];
+    let [address_high, address_low] = memory_address(page_address, length)?.to_be_bytes();
+
+    memory_command(spi, chip_select, &mut [MEMORY_WRITE_ENABLE])?;
+    let mut write = vec![MEMORY_WRITE, address_high, address_low];
+    write.extend_from_slice(page);
+    memory_command(spi, chip_select, &mut write)?;
+
+    let start = Instant::now();
+    loop {
+      let mut status = [MEMORY_READ_STATUS, 0];
+      memory_command(spi, chip_select, &mut status)?;
+      if !Status::from_bits_truncate(status[1]).contains(Status::WRITE_IN_PROGRESS) {
+        break;
+      }
+      if start.elapsed() > MEMORY_WRITE_TIMEOUT {
+        return Err(WyldcardError::MemoryWriteFailed { address: page_address, reason: format!("the memory chip was still busy after {:?}", MEMORY_WRITE_TIMEOUT) });
+      }
+      thread::sleep(Duration::from_millis(1));
+    }
 
-    // we're assuming all pins for a card are using the same expander
-    let expander_address = self.gpio_expander_addresses[&pin_assignments.busy_pin.0];
-    let mut expander = Pcf8574::new(i2c, expander_address.0, expander_address.1, expander_address.2);
-    let virtual_gpios = expander.split();
+    let mut read = vec![MEMORY_READ, address_high, address_low];
+    read.resize(3 + length, 0);
+    memory_command(spi, chip_select, &mut read)?;
+    let read_back = &read[3..];
+    if let Some(offset) = page.iter().zip(read_back).position(|(expected, found)| expected != found) {
+      let reason = format!("read back {:#04x} instead of {:#04x}", read_back[offset], page[offset]);
+      return Err(WyldcardError::MemoryWriteFailed { address: page_address + offset, reason });
+    }
 
-    let mut pins = HashMap::from([
-      (0, virtual_gpios.p0),
-      (1, virtual_gpios.p1),
-      (2, virtual_gpios.p2),
-      (3, virtual_gpios.p3),
-      (4, virtual_gpios.p4),
-      (5, virtual_gpios.p5),
-      (6, virtual_gpios.p6),
-      (7, virtual_gpios.p7),
-    ]);
+    written += length;
+  }
+  Ok(())
+}
 
-    let reset = pins.remove(&pin_assignments.reset_pin.1).expect("reset pin");
-    let busy = pins.remove(&pin_assignments.busy_pin.1).expect("busy pin");
-    let epd_chip_select = pins.remove(&pin_assignments.epd_chip_select_pin.1).expect("cs pin");
+// sends one instruction to the memory chip, leaving whatever it sends back in `bytes`
+fn memory_command<SPI, CS>(spi: &mut SPI, chip_select: &mut CS, bytes: &mut [u8]) -> Result<(), WyldcardError>
+  where SPI: Transfer<u8>,
+        SPI::Error: std::fmt::Debug,
+        CS: embedded_hal::digital::v2::OutputPin,
+        CS::Error: std::fmt::Debug {
+  chip_select.set_low().map_err(WyldcardError::bus)?;
+  let transferred = spi.transfer(bytes).map(|_| ()).map_err(WyldcardError::bus);
+  // deselect the chip even if the transfer failed, it only starts writing once it's deselected
+  chip_select.set_high().map_err(WyldcardError::bus)?;
+  transferred
+}
 
-    let mut display = GDEW029T5DController::new(
-                      reset,
-                      busy,
-                      data_or_command,
-                      epd_chip_select,
-                      spi).expect("create epd");
+// a 64 bit FNV-1a hash of an image, which stays the same between versions and platforms so it can be kept on cards
+pub fn image_hash(image: &[u8]) -> u64 {
+  image.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
 
-    if let Err(e) = display.start_epd() {
-      println!("{}", e); return;
-    }
-    display.display_image(image);
-    display.sleep();
+// the hash on the card is only a cache, so a refresh which worked doesn't fail because it couldn't be written. A card
+// left with the hash of its old image isn't skipped by mistake, as `already_showing()` also needs the image to match
+// the well's `current_image()`, which is the new one. The worst that happens is a refresh which wasn't needed
+fn try_store_image_hash<P: Plinth>(plinth: &P, well: usize, hash: u64) {
+  if plinth.persisting_image_hashes() {
+    let _ = plinth.store_image_hash(well, hash);
   }
+}
 
-  fn well_occupied(&self, well: usize) -> bool {
-    let pin_assignments = self.wyldcard_wells[well];
+// every well's current image is kept in 2-bit grayscale, so they can be compared and turned into pngs the same way
+fn as_grayscale(image: Vec<u8>, color_resolution: ColorResolution) -> Vec<u8> {
+  match color_resolution {
+    ColorResolution::BlackAndWhiteMonochrome => monochrome_to_grayscale(&image),
+    ColorResolution::FourColorGrayscale => image,
+  }
+}
 
-    let i2c = I2c::new().unwrap();
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).unwrap();
+// works out what a panel shows after a partial update of `window`: only the window changes, and every pixel in it
+// ends up black or white
+fn apply_partial_update(shown: &mut [u8], image: &[u8], window: Window) {
+  for y in window.y as usize..(window.y + window.height) as usize {
+    for x in window.x as usize..(window.x + window.width) as usize {
+      let pixel_index = y * 128 + x;
+      let shift = 6 - 2 * (pixel_index % 4);
+      let pixel = if (image[pixel_index / 4] >> shift) & 0b10 == 0 { 0b00 } else { 0b11 };
+      shown[pixel_index / 4] = (shown[pixel_index / 4] & !(0b11 << shift)) | (pixel << shift);
+    }
+  }
+}
 
-    // we're assuming all pins for a card are using the same expander
-    let expander_address = self.gpio_expander_addresses[&pin_assignments.busy_pin.0];
-    let mut expander = Pcf8574::new(i2c, expander_address.0, expander_address.1, expander_address.2);
-    let virtual_gpios = expander.split();
+// checks the image is the right size for the panel and turns it to suit the well. Panels without a black and white
+// mode show black and white images in grayscale. Returns the image along with the color resolution it's sent in
+fn prepare_image(display: &mut dyn EpdDriver, image: Vec<u8>, color_resolution: ColorResolution, orientation: Orientation, waveforms: Waveforms) -> Result<(Vec<u8>, ColorResolution), WyldcardError> {
+  let capabilities = display.capabilities();
+  check_image_size(&image, capabilities.image_size(color_resolution))?;
+  let image = orientation.orient_image(image, color_resolution);
+
+  let (image, color_resolution) = match color_resolution {
+    ColorResolution::BlackAndWhiteMonochrome if !capabilities.monochrome => (monochrome_to_grayscale(&image), ColorResolution::FourColorGrayscale),
+    _ => (image, color_resolution),
+  };
+
+  display.set_color_resolution(color_resolution);
+  display.set_lut(waveforms.lut.map(|lut| lut.bytes().to_vec()));
+  display.set_temperature_luts(waveforms.by_temperature.into_iter().map(|(from, lut)| (from, lut.bytes().to_vec())).collect());
+  Ok((image, color_resolution))
+}
 
-    let mut pins = HashMap::from([
-      (0, virtual_gpios.p0),
-      (1, virtual_gpios.p1),
-      (2, virtual_gpios.p2),
-      (3, virtual_gpios.p3),
-      (4, virtual_gpios.p4),
-      (5, virtual_gpios.p5),
-      (6, virtual_gpios.p6),
-      (7, virtual_gpios.p7),
-    ]);
+// lends a well's pins to a driver for its panel, and the bus a transaction at a time, see `LentBus`
+fn open_display<'a, OUTPUT, INPUT, SPI, DC>(panel_type: PanelType, pins: &'a mut WellPins<OUTPUT, INPUT>, bus: &'a Mutex<SpiBus<SPI, DC>>, busy_timeout: Duration) -> Result<Box<dyn EpdDriver + 'a>, WyldcardError>
+  where OUTPUT: embedded_hal::digital::v2::OutputPin,
+        OUTPUT::Error: std::fmt::Debug,
+        INPUT: embedded_hal::digital::v2::InputPin,
+        INPUT::Error: std::fmt::Debug,
+        SPI: Transfer<u8>,
+        SPI::Error: std::fmt::Debug,
+        DC: embedded_hal::digital::v2::OutputPin,
+        DC::Error: std::fmt::Debug {
+  let bus = Rc::new(RefCell::new(LentBus { bus, locked: None }));
+  let mut display = new_driver(
+                    panel_type,
+                    Borrowed(&mut pins.reset),
+                    Borrowed(&mut pins.busy),
+                    LentDataOrCommand(bus.clone()),
+                    LentChipSelect(&mut pins.epd_chip_select, bus.clone()),
+                    LentSpi(bus))?;
+  display.set_busy_timeout(busy_timeout);
+  display.set_data_line_readable(PANEL_DATA_LINE_READABLE);
+
+  Ok(display)
+}
 
-    let memory_chip_select = pins.remove(&pin_assignments.memory_chip_select_pin.1).expect("mem chip select");
+// an image for `display_images_together()`, with what it needs to know about the well it's going to
+struct PendingImage {
+  well: usize,
+  image: Vec<u8>,
+  panel_type: PanelType,
+  orientation: Orientation,
+  waveforms: Waveforms,
+}
 
-    let mut flash = Flash::init(
-      spi,
-      memory_chip_select,
-    ).expect("memory");
+// images have to be sent to the panels one at a time over the shared bus, but after that each panel refreshes on
+// its own and signals on its own busy pin when it's done, so the slow part happens for every well at once.
+// The wells' pins are held throughout, locked in well order, and the bus only while something is being sent.
+// A well which fails doesn't stop the others. Returns the wells which were updated with the grayscale image each now
+// shows, along with the first error
+fn display_images_together<OUTPUT, INPUT, SPI, DC>(well_pins: &[Mutex<WellPins<OUTPUT, INPUT>>], spi_bus: &Mutex<SpiBus<SPI, DC>>, busy_timeout: Duration, mut images: Vec<PendingImage>, color_resolution: ColorResolution) -> (Vec<(usize, Vec<u8>)>, Option<WyldcardError>)
+  where OUTPUT: embedded_hal::digital::v2::OutputPin,
+        OUTPUT::Error: std::fmt::Debug,
+        INPUT: embedded_hal::digital::v2::InputPin,
+        INPUT::Error: std::fmt::Debug,
+        SPI: Transfer<u8>,
+        SPI::Error: std::fmt::Debug,
+        DC: embedded_hal::digital::v2::OutputPin,
+        DC::Error: std::fmt::Debug {
+  images.sort_by_key(|pending| pending.well);
+  if images.windows(2).any(|pair| pair[0].well == pair[1].well) {
+    return (Vec::new(), Some(WyldcardError::InvalidArgument(String::from("Can only show one image on each well at a time"))));
+  }
 
-    flash._write_enable();
+  let mut pins: Vec<_> = images.iter().map(|pending| well_pins[pending.well].lock().unwrap()).collect();
+  let mut refreshing = Vec::new();
+  let mut shown = Vec::new();
+  let mut first_error = None;
+
+  for (index, PendingImage { well, image, panel_type, orientation, waveforms }) in images.into_iter().enumerate() {
+    let started = open_display(panel_type, &mut pins[index], spi_bus, busy_timeout).and_then(|mut display| {
+      let (image, color_resolution) = prepare_image(display.as_mut(), image, color_resolution, orientation, waveforms)?;
+      display.start_epd()?;
+      display.load_image(image.clone())?;
+      display.begin_update()?;
+      Ok(as_grayscale(image, color_resolution))
+    });
+
+    match started {
+      Ok(image) => refreshing.push((index, well, panel_type, image)),
+      Err(e) => { first_error.get_or_insert(e); },
+    }
+  }
 
-    let status = flash.read_status().expect("get status");
+  for (index, well, panel_type, image) in refreshing {
+    let finished = wait_while_busy(&pins[index].busy, panel_type.busy_when_high(), busy_timeout).and_then(|_| {
+      let mut display = open_display(panel_type, &mut pins[index], spi_bus, busy_timeout)?;
+      display.sleep()
+    });
 
-    match status {
-      Status::WRITE_ENABLE_LATCH => {
-        flash._write_disable();
-        true
-      },
-      _ => false
+    match finished {
+      Ok(()) => shown.push((well, image)),
+      Err(e) => { first_error.get_or_insert(e); },
     }
   }
 
-  fn read_memory(&self, well: usize, buffer: &mut [u8]) -> Result<(), String> {
-    let pin_assignments = self.wyldcard_wells[well];
+  (shown, first_error)
+}
+
+// what a hardware plinth last showed on each well, along with a `card_fingerprint()` of the card it was shown on.
+// A card pulled out and put back, or swapped for another, can't be seen, so an image is only trusted while the card
+// in the well has the same fingerprint. Cards without persisted image hashes and with the same panel type, like two
+// blank ones, can't be told apart that way
+#[derive(Default)]
+struct ShownImages {
+  wells: Mutex<[Option<ShownImage>; 4]>,
+}
 
-    let i2c = I2c::new().unwrap();
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).unwrap();
+#[derive(Clone)]
+struct ShownImage {
+  image: Vec<u8>,
+  fingerprint: u64,
+}
 
-    // we're assuming all pins for a card are using the same expander
-    let expander_address = self.gpio_expander_addresses[&pin_assignments.busy_pin.0];
-    let mut expander = Pcf8574::new(i2c, expander_address.0, expander_address.1, expander_address.2);
-    let virtual_gpios = expander.split();
+impl ShownImages {
+  fn record(&self, well: usize, image: Vec<u8>, fingerprint: u64) {
+    self.wells.lock().unwrap()[well] = Some(ShownImage { image, fingerprint });
+  }
 
-    let mut pins = HashMap::from([
-      (0, virtual_gpios.p0),
-      (1, virtual_gpios.p1),
-      (2, virtual_gpios.p2),
-      (3, virtual_gpios.p3),
-      (4, virtual_gpios.p4),
-      (5, virtual_gpios.p5),
-      (6, virtual_gpios.p6),
-      (7, virtual_gpios.p7),
-    ]);
+  fn forget(&self, well: usize) {
+    self.wells.lock().unwrap()[well] = None;
+  }
 
-    let memory_chip_select = pins.remove(&pin_assignments.memory_chip_select_pin.1).expect("mem chip select");
+  // the image shown on this well, unless the card there now isn't the one it was shown on. Then it's forgotten,
+  // so putting the first card back doesn't bring it back either, as its screen may have changed elsewhere
+  fn image_on<P: Plinth>(&self, plinth: &P, well: usize) -> Option<Vec<u8>> {
+    let ShownImage { image, fingerprint } = self.wells.lock().unwrap()[well].clone()?;
+    if card_fingerprint(plinth, well).ok() == Some(fingerprint) {
+      return Some(image);
+    }
 
-    let mut flash = Flash::init(
-                    spi,
-                    memory_chip_select,
-                  ).expect("memory");
+    // unless another image has been recorded in the meantime
+    let mut wells = self.wells.lock().unwrap();
+    if wells[well].as_ref().is_some_and(|shown| shown.fingerprint == fingerprint) {
+      wells[well] = None;
+    }
+    None
+  }
+}
+
+// a hash of the image hash and panel type at the end of the card's memory, which say what the card is and what it
+// shows rather than what a game's saved on it. So saving data doesn't make the plinth forget the card's image, and
+// a card swapped for another showing something else is noticed once image hashes are persisted
+fn card_fingerprint<P: Plinth>(plinth: &P, well: usize) -> Result<u64, WyldcardError> {
+  let mut identity = [0; CARD_MEMORY_SIZE - IMAGE_HASH_ADDRESS];
+  plinth.read_memory_at(well, IMAGE_HASH_ADDRESS, &mut identity)?;
+  Ok(image_hash(&identity))
+}
 
-    let result = flash.read(0, buffer);
+// everything a plinth does besides claiming its wells' pins, which hang off different gpio expanders on each
+// board. OUTPUT and INPUT are the types of those pins
+pub struct HardwarePlinth<OUTPUT, INPUT> {
+  well_pins: Vec<Mutex<WellPins<OUTPUT, INPUT>>>,
+  memory_chip_selects: Vec<Mutex<OUTPUT>>,
+  default_panel_type: PanelType, // for cards which don't say what they have
+  panel_types: Mutex<[Option<PanelType>; 4]>, // None means detect it from the card
+  orientations: Mutex<[Orientation; 4]>,
+  luts: Mutex<[Option<Lut>; 4]>,
+  temperature_luts: Mutex<[Vec<(f32, Lut)>; 4]>,
+  shown_images: ShownImages,
+  persist_image_hashes: Mutex<bool>,
+  busy_timeout: Mutex<Duration>,
+  spi_bus: Mutex<SpiBus>,
+  #[allow(dead_code)]
+  uart: Mutex<Uart>, // held open for the life of the plinth, though nothing is sent over it yet
+  #[allow(dead_code)]
+  switch_names: HashMap<u8, String>, // by pin, eg. "Switch A for Card 0"
+  switches: HashMap<(usize, char), Mutex<InputPin>>,
+}
 
-    match result {
-      Err(e) => return Err(e.to_string()),
-      Ok(_) => return Ok(()),
+pub type DevKitV1 = HardwarePlinth<Pca9555Pin<Output>, Pca9555Pin<Input>>;
+pub type Prototype = HardwarePlinth<Pcf8574Pin, Pcf8574Pin>;
+
+// each well's switches as (well, switch, gpio pin), wired the same on every board
+const SWITCH_PINS: [(usize, char, u8); 12] = [
+  (0, 'a', 23), // gpio #4 throws "in use" errors :(
+  (0, 'b', 5),
+  (0, 'c', 6),
+  (1, 'a', 12),
+  (1, 'b', 13),
+  (1, 'c', 21),
+  (2, 'a', 22),
+  (2, 'b', 16),
+  (2, 'c', 17),
+  (3, 'a', 18),
+  (3, 'b', 19),
+  (3, 'c', 20),
+];
+
+impl<OUTPUT, INPUT> Plinth for HardwarePlinth<OUTPUT, INPUT>
+  where OUTPUT: embedded_hal::digital::v2::OutputPin,
+        OUTPUT::Error: std::fmt::Debug,
+        INPUT: embedded_hal::digital::v2::InputPin,
+        INPUT::Error: std::fmt::Debug {
+  fn display_image(&self, well: usize, image: Vec<u8>, color_resolution: ColorResolution, options: &RefreshOptions) -> Result<(), WyldcardError> {
+    // a waveform for the wrong panel is an error even when the refresh turns out not to be needed
+    let waveforms = self.waveforms(well, options.lut.as_deref())?;
+    if !options.force && self.already_showing(well, &image, color_resolution)? {
+      return Ok(());
     }
+
+    let orientation = self.orientation(well)?;
+    let shown = self.with_display(well, |display| {
+      let (image, color_resolution) = prepare_image(display, image, color_resolution, orientation, waveforms)?;
+      display.start_epd()?;
+      display.display_image(image.clone())?;
+      display.sleep()?;
+      Ok(as_grayscale(image, color_resolution))
+    })?;
+
+    self.image_shown(well, shown);
+    Ok(())
   }
 
-  fn write_memory(&self, well: usize, buffer: &mut [u8]) -> Result<(), String> {
-    let pin_assignments = self.wyldcard_wells[well];
+  fn display_images(&self, images: Vec<(usize, Vec<u8>)>, color_resolution: ColorResolution, options: &RefreshOptions) -> Result<(), WyldcardError> {
+    let mut changed = Vec::new();
+    for (well, image) in images {
+      let waveforms = self.waveforms(well, options.lut.as_deref())?;
+      if options.force || !self.already_showing(well, &image, color_resolution)? {
+        let (panel_type, orientation) = (self.panel_type(well)?, self.orientation(well)?);
+        changed.push(PendingImage { well, image, panel_type, orientation, waveforms });
+      }
+    }
+    let images = changed;
 
-    let i2c = I2c::new().unwrap();
-    let spi = Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).unwrap();
+    let (shown, error) = display_images_together(&self.well_pins, &self.spi_bus, *self.busy_timeout.lock().unwrap(), images, color_resolution);
+    for (well, image) in shown {
+      self.image_shown(well, image);
+    }
 
-    // we're assuming all pins for a card are using the same expander
-    let expander_address = self.gpio_expander_addresses[&pin_assignments.busy_pin.0];
-    let mut expander = Pcf8574::new(i2c, expander_address.0, expander_address.1, expander_address.2);
-    let virtual_gpios = expander.split();
+    match error {
+      Some(e) => Err(e),
+      None => Ok(()),
+    }
+  }
 
-    let mut pins = HashMap::from([
-      (0, virtual_gpios.p0),
-      (1, virtual_gpios.p1),
-      (2, virtual_gpios.p2),
-      (3, virtual_gpios.p3),
-      (4, virtual_gpios.p4),
-      (5, virtual_gpios.p5),
-      (6, virtual_gpios.p6),
-      (7, virtual_gpios.p7),
-    ]);
+  fn display_partial(&self, well: usize, image: Vec<u8>, window: Window) -> Result<(), WyldcardError> {
+    let orientation = self.orientation(well)?;
+    let shown = self.current_image(well)?;
+    let partial = self.with_display(well, |display| {
+      let capabilities = display.capabilities();
+      check_image_size(&image, capabilities.image_size(ColorResolution::FourColorGrayscale))?;
+      let image = orientation.orient_image(image, ColorResolution::FourColorGrayscale);
+      let window = orientation.orient_window(window)?;
+      if !window.fits(&capabilities) {
+        return Err(WyldcardError::InvalidArgument(format!("window {:?} doesn't fit on a {}x{} display", window, capabilities.width, capabilities.height)));
+      }
 
-    let memory_chip_select = pins.remove(&pin_assignments.memory_chip_select_pin.1).expect("mem chip select");
+      display.display_partial(image.clone(), window, shown.as_deref())?;
+      display.sleep()?;
+      Ok((image, window, capabilities.partial_refresh))
+    })?;
+
+    // panels without partial refresh showed the whole image in grayscale, the others changed whole bytes of the
+    // window to black and white. The rest of the screen is only known if something was shown before
+    let (image, window, partial_refresh) = partial;
+    if !partial_refresh {
+      self.image_shown(well, image);
+    } else if let Some(mut shown) = self.current_image(well)? {
+      apply_partial_update(&mut shown, &image, window.byte_aligned());
+      self.image_shown(well, shown);
+    }
+    Ok(())
+  }
 
-    let mut flash = Flash::init(
-                    spi,
-                    memory_chip_select,
-                  ).expect("memory");
+  // a well seen empty has lost whatever card was shown on, see `ShownImages`
+  fn well_occupied(&self, well: usize) -> Result<bool, WyldcardError> {
+    let occupied = self.with_memory(well, |flash| {
+      flash._write_enable().map_err(WyldcardError::bus)?;
 
-    let result = flash.write_bytes(0, buffer);
+      let status = flash.read_status().map_err(WyldcardError::bus)?;
+
+      match status {
+        Status::WRITE_ENABLE_LATCH => {
+          flash._write_disable().map_err(WyldcardError::bus)?;
+          Ok(true)
+        },
+        _ => Ok(false)
+      }
+    })?;
 
-    match result {
-      Err(e) => return Err(e.to_string()),
-      Ok(_) => return Ok(()),
+    if !occupied {
+      self.shown_images.forget(well);
     }
+    Ok(occupied)
+  }
+
+  fn read_memory_at(&self, well: usize, address: usize, buffer: &mut [u8]) -> Result<(), WyldcardError> {
+    let chip_address = memory_address(address, buffer.len())?;
+    self.with_memory(well, |flash| {
+      flash.read(chip_address, buffer).map_err(WyldcardError::bus)
+    })
+  }
+
+  fn write_memory_at(&self, well: usize, address: usize, buffer: &mut [u8]) -> Result<(), WyldcardError> {
+    check_memory_range(address, buffer.len())?;
+    check_well(well)?;
+    let mut chip_select = self.memory_chip_selects[well].lock().unwrap();
+    let mut bus = self.spi_bus.lock().unwrap();
+    write_memory_verified(&mut bus.spi, &mut *chip_select, address, buffer)
   }
 
   // switches are identified by well number and then switch 'a', 'b', or 'c'
-  fn set_switch_callback(&mut self, well: usize, switch: char, callback: impl FnMut(Level) + Send + 'static) -> Result<(), String> {
-    if !['a','b','c'].contains(&switch) {
-      return Err(String::from("Invalid switch"));
-    }
+  fn set_switch_callback(&self, well: usize, switch: char, callback: impl FnMut(Level) + Send + 'static) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    let mut switch = self.switches.get(&(well, switch)).ok_or(WyldcardError::InvalidArgument(format!("Invalid switch '{}'", switch)))?.lock().unwrap();
+
+    switch.set_async_interrupt(Trigger::FallingEdge, callback).map_err(WyldcardError::bus)
+  }
+
+  fn set_busy_timeout(&self, timeout: Duration) {
+    *self.busy_timeout.lock().unwrap() = timeout;
+  }
 
-    let switch = self.switches.get_mut(&(well, switch)).unwrap();
+  fn set_panel_type(&self, well: usize, panel_type: Option<PanelType>) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.panel_types.lock().unwrap()[well] = panel_type;
+    Ok(())
+  }
+
+  fn panel_type(&self, well: usize) -> Result<PanelType, WyldcardError> {
+    check_well(well)?;
+    let panel_type = self.panel_types.lock().unwrap()[well];
+    Ok(panel_type
+      .or_else(|| self.detect_panel_type(well))
+      .unwrap_or(self.default_panel_type))
+  }
+
+  fn set_orientation(&self, well: usize, orientation: Orientation) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.orientations.lock().unwrap()[well] = orientation;
+    Ok(())
+  }
 
-    switch.set_async_interrupt(Trigger::FallingEdge, callback).expect("set switch");
+  fn orientation(&self, well: usize) -> Result<Orientation, WyldcardError> {
+    check_well(well)?;
+    Ok(self.orientations.lock().unwrap()[well])
+  }
+
+  fn set_lut(&self, well: usize, lut: Option<Lut>) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.luts.lock().unwrap()[well] = lut;
+    Ok(())
+  }
+
+  fn lut(&self, well: usize) -> Result<Option<Lut>, WyldcardError> {
+    check_well(well)?;
+    Ok(self.luts.lock().unwrap()[well].clone())
+  }
 
+  fn set_temperature_luts(&self, well: usize, luts: Vec<(f32, Lut)>) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.temperature_luts.lock().unwrap()[well] = luts;
     Ok(())
   }
+
+  fn temperature_luts(&self, well: usize) -> Result<Vec<(f32, Lut)>, WyldcardError> {
+    check_well(well)?;
+    Ok(self.temperature_luts.lock().unwrap()[well].clone())
+  }
+
+  fn panel_temperature(&self, well: usize) -> Result<Option<f32>, WyldcardError> {
+    self.with_display(well, |display| display.read_temperature())
+  }
+
+  fn current_image(&self, well: usize) -> Result<Option<Vec<u8>>, WyldcardError> {
+    check_well(well)?;
+    Ok(self.shown_images.image_on(self, well))
+  }
+
+  fn set_persist_image_hashes(&self, persist: bool) {
+    *self.persist_image_hashes.lock().unwrap() = persist;
+  }
+
+  fn persisting_image_hashes(&self) -> bool {
+    *self.persist_image_hashes.lock().unwrap()
+  }
+}
+
+impl DevKitV1 {
+  pub fn new() -> Result<DevKitV1, WyldcardError> {
+
+    let gpio_expander_addresses = HashMap::from([
+      (GpioExpander::EXPANDER0, (false, false, false)),
+    ]);
+
+    let wyldcard_wells = [
+      Well {
+        reset_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 0),
+        busy_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 1),
+        data_or_command_pin: 25,
+        epd_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 2),
+        memory_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 3),
+      },
+      Well {
+        reset_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 4),
+        busy_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 5),
+        data_or_command_pin: 25,
+        epd_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 6),
+        memory_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 7),
+      },
+      Well {
+        reset_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 8),
+        busy_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 9),
+        data_or_command_pin: 25,
+        epd_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 10),
+        memory_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 11),
+      },
+      Well {
+        reset_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 12),
+        busy_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 13),
+        data_or_command_pin: 25,
+        epd_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 14),
+        memory_chip_select_pin: VirtualPinAddress(GpioExpander::EXPANDER0, 15),
+      }
+    ];
+
+    // claim every well's pins up front. The expanders are kept for the life of the program, so their pins can be too
+    let i2c_bus = new_shared_i2c()?;
+    let mut expander_pins: HashMap<GpioExpander, HashMap<usize, Pca9555Pin<Input>>> = HashMap::new();
+    for (expander, address) in gpio_expander_addresses {
+      let expander_chip = Box::leak(Box::new(Pca9555::with_mutex(i2c_bus.acquire_i2c(), address.0, address.1, address.2)));
+      let virtual_gpios = expander_chip.split();
+
+      expander_pins.insert(expander, HashMap::from([
+        (0, virtual_gpios.io0_0),
+        (1, virtual_gpios.io0_1),
+        (2, virtual_gpios.io0_2),
+        (3, virtual_gpios.io0_3),
+        (4, virtual_gpios.io0_4),
+        (5, virtual_gpios.io0_5),
+        (6, virtual_gpios.io0_6),
+        (7, virtual_gpios.io0_7),
+        (8, virtual_gpios.io1_0),
+        (9, virtual_gpios.io1_1),
+        (10, virtual_gpios.io1_2),
+        (11, virtual_gpios.io1_3),
+        (12, virtual_gpios.io1_4),
+        (13, virtual_gpios.io1_5),
+        (14, virtual_gpios.io1_6),
+        (15, virtual_gpios.io1_7),
+      ]));
+    }
+
+    let mut well_pins = Vec::new();
+    let mut memory_chip_selects = Vec::new();
+    for well in &wyldcard_wells {
+      let mut take_pin = |address: VirtualPinAddress| expander_pins.get_mut(&address.0).unwrap().remove(&address.1).expect("missing pin");
+
+      well_pins.push(Mutex::new(WellPins {
+        reset: take_pin(well.reset_pin).into_output().map_err(WyldcardError::bus)?,
+        busy: take_pin(well.busy_pin).into_input().map_err(WyldcardError::bus)?,
+        epd_chip_select: take_pin(well.epd_chip_select_pin).into_output().map_err(WyldcardError::bus)?,
+      }));
+      memory_chip_selects.push(Mutex::new(take_pin(well.memory_chip_select_pin).into_output().map_err(WyldcardError::bus)?));
+    }
+
+    HardwarePlinth::with_pins(wyldcard_wells, well_pins, memory_chip_selects, PanelType::GDEY029T94)
+  }
 }
 
 impl Prototype {
-  pub fn new() -> Prototype {
+  pub fn new() -> Result<Prototype, WyldcardError> {
 
     let gpio_expander_addresses = HashMap::from([
       (GpioExpander::EXPANDER0, (false, false, false)),
@@ -595,77 +965,637 @@ impl Prototype {
       }
     ];
 
-    let mut uart = Uart::new(9600, Parity::Even, 8, 1).expect("uart");
-    uart.set_write_mode(true).expect("uart");
-    uart.set_read_mode(0, Duration::from_millis(100)).expect("uart");
-
-
-    let gpio = Gpio::new().unwrap();
-
-    const GPIO_SWITCH_A0: u8 = 23; // gpio #4 throws "in use" errors :(
-    const GPIO_SWITCH_B0: u8 = 5;
-    const GPIO_SWITCH_C0: u8 = 6;
-  
-    const GPIO_SWITCH_A1: u8 = 12;
-    const GPIO_SWITCH_B1: u8 = 13;
-    const GPIO_SWITCH_C1: u8 = 21;
-  
-    const GPIO_SWITCH_A2: u8 = 22;
-    const GPIO_SWITCH_B2: u8 = 16;
-    const GPIO_SWITCH_C2: u8 = 17;
-  
-    const GPIO_SWITCH_A3: u8 = 18;
-    const GPIO_SWITCH_B3: u8 = 19;
-    const GPIO_SWITCH_C3: u8 = 20;
-
-    // setup switches
-    let switch_a0 = gpio.get(GPIO_SWITCH_A0).unwrap().into_input_pullup();
-    let switch_b0 = gpio.get(GPIO_SWITCH_B0).unwrap().into_input_pullup();
-    let switch_c0 = gpio.get(GPIO_SWITCH_C0).unwrap().into_input_pullup();
-    let switch_a1 = gpio.get(GPIO_SWITCH_A1).unwrap().into_input_pullup();
-    let switch_b1 = gpio.get(GPIO_SWITCH_B1).unwrap().into_input_pullup();
-    let switch_c1 = gpio.get(GPIO_SWITCH_C1).unwrap().into_input_pullup();
-    let switch_a2 = gpio.get(GPIO_SWITCH_A2).unwrap().into_input_pullup();
-    let switch_b2 = gpio.get(GPIO_SWITCH_B2).unwrap().into_input_pullup();
-    let switch_c2 = gpio.get(GPIO_SWITCH_C2).unwrap().into_input_pullup();
-    let switch_a3 = gpio.get(GPIO_SWITCH_A3).unwrap().into_input_pullup();
-    let switch_b3 = gpio.get(GPIO_SWITCH_B3).unwrap().into_input_pullup();
-    let switch_c3 = gpio.get(GPIO_SWITCH_C3).unwrap().into_input_pullup();
-    
-    let switch_names = HashMap::from([
-      (GPIO_SWITCH_A0, String::from("Switch A for Card 0")),
-      (GPIO_SWITCH_B0, String::from("Switch B for Card 0")),
-      (GPIO_SWITCH_C0, String::from("Switch C for Card 0")),
-      (GPIO_SWITCH_A1, String::from("Switch A for Card 1")),
-      (GPIO_SWITCH_B1, String::from("Switch B for Card 1")),
-      (GPIO_SWITCH_C1, String::from("Switch C for Card 1")),
-      (GPIO_SWITCH_A2, String::from("Switch A for Card 2")),
-      (GPIO_SWITCH_B2, String::from("Switch B for Card 2")),
-      (GPIO_SWITCH_C2, String::from("Switch C for Card 2")),
-      (GPIO_SWITCH_A3, String::from("Switch A for Card 3")),
-      (GPIO_SWITCH_B3, String::from("Switch B for Card 3")),
-      (GPIO_SWITCH_C3, String::from("Switch C for Card 3")),
-    ]);
+    // claim every well's pins up front. The expanders are kept for the life of the program, so their pins can be too
+    let i2c_bus = new_shared_i2c()?;
+    let mut expander_pins: HashMap<GpioExpander, HashMap<usize, Pcf8574Pin>> = HashMap::new();
+    for (expander, address) in gpio_expander_addresses {
+      let expander_chip = Box::leak(Box::new(Pcf8574::with_mutex(i2c_bus.acquire_i2c(), address.0, address.1, address.2)));
+      let virtual_gpios = expander_chip.split();
+
+      expander_pins.insert(expander, HashMap::from([
+        (0, virtual_gpios.p0),
+        (1, virtual_gpios.p1),
+        (2, virtual_gpios.p2),
+        (3, virtual_gpios.p3),
+        (4, virtual_gpios.p4),
+        (5, virtual_gpios.p5),
+        (6, virtual_gpios.p6),
+        (7, virtual_gpios.p7),
+      ]));
+    }
+
+    let mut well_pins = Vec::new();
+    let mut memory_chip_selects = Vec::new();
+    for well in &wyldcard_wells {
+      let mut take_pin = |address: VirtualPinAddress| expander_pins.get_mut(&address.0).unwrap().remove(&address.1).expect("missing pin");
+
+      well_pins.push(Mutex::new(WellPins {
+        reset: take_pin(well.reset_pin),
+        busy: take_pin(well.busy_pin),
+        epd_chip_select: take_pin(well.epd_chip_select_pin),
+      }));
+      memory_chip_selects.push(Mutex::new(take_pin(well.memory_chip_select_pin)));
+    }
 
-    Prototype {
-      gpio_expander_addresses,
-      wyldcard_wells,
-      uart,
+    HardwarePlinth::with_pins(wyldcard_wells, well_pins, memory_chip_selects, PanelType::GDEW029T5D)
+  }
+}
+
+impl<OUTPUT, INPUT> HardwarePlinth<OUTPUT, INPUT>
+  where OUTPUT: embedded_hal::digital::v2::OutputPin,
+        OUTPUT::Error: std::fmt::Debug,
+        INPUT: embedded_hal::digital::v2::InputPin,
+        INPUT::Error: std::fmt::Debug {
+  // sets up the rest of the plinth around the pins a board has claimed for its wells
+  fn with_pins(wyldcard_wells: [Well; 4], well_pins: Vec<Mutex<WellPins<OUTPUT, INPUT>>>, memory_chip_selects: Vec<Mutex<OUTPUT>>, default_panel_type: PanelType) -> Result<HardwarePlinth<OUTPUT, INPUT>, WyldcardError> {
+    let mut uart = Uart::new(9600, Parity::Even, 8, 1).map_err(WyldcardError::bus)?;
+    uart.set_write_mode(true).map_err(WyldcardError::bus)?;
+    uart.set_read_mode(0, Duration::from_millis(100)).map_err(WyldcardError::bus)?;
+
+    let gpio = Gpio::new().map_err(WyldcardError::bus)?;
+
+    // every well uses the same data/command line
+    let spi_bus = Mutex::new(SpiBus {
+      spi: Spi::new(Bus::Spi0, SlaveSelect::Ss0, 10_000_000, Mode::Mode0).map_err(WyldcardError::bus)?,
+      data_or_command: gpio.get(wyldcard_wells[0].data_or_command_pin).map_err(WyldcardError::bus)?.into_output(),
+    });
+
+    let mut switches = HashMap::new();
+    let mut switch_names = HashMap::new();
+    for (well, switch, pin) in SWITCH_PINS {
+      switches.insert((well, switch), Mutex::new(gpio.get(pin).map_err(WyldcardError::bus)?.into_input_pullup()));
+      switch_names.insert(pin, format!("Switch {} for Card {}", switch.to_ascii_uppercase(), well));
+    }
+
+    Ok(HardwarePlinth {
+      well_pins,
+      memory_chip_selects,
+      default_panel_type,
+      panel_types: Mutex::new([None; 4]),
+      orientations: Mutex::new([Orientation::default(); 4]),
+      luts: Mutex::new([None, None, None, None]),
+      temperature_luts: Mutex::new(Default::default()),
+      shown_images: ShownImages::default(),
+      persist_image_hashes: Mutex::new(false),
+      busy_timeout: Mutex::new(DEFAULT_BUSY_TIMEOUT),
+      spi_bus,
+      uart: Mutex::new(uart),
       switch_names,
-      switches: HashMap::from([
-        ((0, 'a'), switch_a0),
-        ((0, 'b'), switch_b0),
-        ((0, 'c'), switch_c0),
-        ((1, 'a'), switch_a1),
-        ((1, 'b'), switch_b1),
-        ((1, 'c'), switch_c1),
-        ((2, 'a'), switch_a2),
-        ((2, 'b'), switch_b2),
-        ((2, 'c'), switch_c2),
-        ((3, 'a'), switch_a3),
-        ((3, 'b'), switch_b3),
-        ((3, 'c'), switch_c3),
+      switches,
+    })
+  }
+
+  // records the image a well now shows, and its hash on the card if that's been asked for. The card's fingerprint
+  // is taken after the hash is written, and if it can't be read the image isn't trusted later
+  fn image_shown(&self, well: usize, image: Vec<u8>) {
+    try_store_image_hash(self, well, image_hash(&image));
+    match card_fingerprint(self, well) {
+      Ok(fingerprint) => self.shown_images.record(well, image, fingerprint),
+      Err(_) => self.shown_images.forget(well),
+    }
+  }
+
+  // sets up the display controller for a well, and hands it to `f` to draw with. The well's pins are held until `f`
+  // returns, but the bus only while the driver is sending something, so other wells carry on while the panel's busy
+  fn with_display<R>(&self, well: usize, f: impl FnOnce(&mut dyn EpdDriver) -> Result<R, WyldcardError>) -> Result<R, WyldcardError> {
+    let panel_type = self.panel_type(well)?; // may need to read card memory, so do it before taking the locks
+
+    let mut pins = self.well_pins[well].lock().unwrap();
+    let mut display = open_display(panel_type, &mut pins, &self.spi_bus, *self.busy_timeout.lock().unwrap())?;
+
+    f(display.as_mut())
+  }
+
+  // sets up the memory chip for a well, and hands it to `f` to read or write
+  fn with_memory<R>(&self, well: usize, f: impl FnOnce(&mut Flash<Borrowed<Spi>, Borrowed<OUTPUT>>) -> Result<R, WyldcardError>) -> Result<R, WyldcardError> {
+    check_well(well)?;
+    let mut chip_select = self.memory_chip_selects[well].lock().unwrap();
+    let mut bus = self.spi_bus.lock().unwrap();
+
+    let mut flash = Flash::init(
+                    Borrowed(&mut bus.spi),
+                    Borrowed(&mut *chip_select),
+                  ).map_err(WyldcardError::bus)?;
+
+    f(&mut flash)
+  }
+}
+
+// the simulator behaves like a devkit card's panel
+const SIMULATED_PANEL: Capabilities = Capabilities {
+  width: 128,
+  height: 296,
+  gray_levels: 4,
+  monochrome: true,
+  partial_refresh: true,
+};
+
+// a virtual Wyldcard. Like a real card, it keeps its memory and whatever image was last drawn to its
+// e-paper display when it's pulled out of a well
+#[derive(Clone, Debug)]
+pub struct SimulatedCard {
+  pub memory: Vec<u8>,
+  pub image: Vec<u8>,
+  pub temperature: f32, // what the panel's sensor reads, in degrees C
+}
+
+impl SimulatedCard {
+  pub fn new() -> SimulatedCard {
+    SimulatedCard {
+      memory: vec![0xFF; CARD_MEMORY_SIZE], // erased EEPROM reads back as all ones
+      image: vec![0xFF; SIMULATED_PANEL.image_size(ColorResolution::FourColorGrayscale)], // all white
+      temperature: 20.0, // room temperature
+    }
+  }
+}
+
+struct SimulatedSwitch {
+  level: Level,
+  callback: Option<Box<dyn FnMut(Level) + Send>>,
+}
+
+// a plinth which needs no hardware at all. Wells, cards and switches are all kept in memory, so game logic
+// can be run on a laptop or in CI
+pub struct SimulatedPlinth {
+  wells: Mutex<[Option<SimulatedCard>; 4]>,
+  switches: HashMap<(usize, char), Mutex<SimulatedSwitch>>,
+  panel_types: Mutex<[Option<PanelType>; 4]>,
+  orientations: Mutex<[Orientation; 4]>,
+  luts: Mutex<[Option<Lut>; 4]>,
+  temperature_luts: Mutex<[Vec<(f32, Lut)>; 4]>,
+  persist_image_hashes: Mutex<bool>,
+}
+
+impl Plinth for SimulatedPlinth {
+  fn display_image(&self, well: usize, image: Vec<u8>, color_resolution: ColorResolution, options: &RefreshOptions) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    check_image_size(&image, SIMULATED_PANEL.image_size(color_resolution))?;
+    // simulated panels don't have waveforms, but a bad one is still caught, like it would be on a real plinth
+    self.waveforms(well, options.lut.as_deref())?;
+    if !options.force && self.already_showing(well, &image, color_resolution)? {
+      return Ok(());
+    }
+    let image = self.orientation(well)?.orient_image(image, color_resolution);
+    let image = as_grayscale(image, color_resolution);
+
+    let hash = image_hash(&image);
+    self.wells.lock().unwrap()[well].as_mut().ok_or(WyldcardError::CardNotPresent)?.image = image;
+    self.image_shown(well, hash);
+    Ok(())
+  }
+
+  fn display_partial(&self, well: usize, image: Vec<u8>, window: Window) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    check_image_size(&image, SIMULATED_PANEL.image_size(ColorResolution::FourColorGrayscale))?;
+    let orientation = self.orientation(well)?;
+    let image = orientation.orient_image(image, ColorResolution::FourColorGrayscale);
+    let window = orientation.orient_window(window)?;
+    if !window.fits(&SIMULATED_PANEL) {
+      return Err(WyldcardError::InvalidArgument(format!("window {:?} doesn't fit on a {}x{} display", window, SIMULATED_PANEL.width, SIMULATED_PANEL.height)));
+    }
+
+    // like the real panel, whole bytes of the window are redrawn
+    let hash = {
+      let mut wells = self.wells.lock().unwrap();
+      let card = wells[well].as_mut().ok_or(WyldcardError::CardNotPresent)?;
+      apply_partial_update(&mut card.image, &image, window.byte_aligned());
+      image_hash(&card.image)
+    };
+    self.image_shown(well, hash);
+    Ok(())
+  }
+
+  fn well_occupied(&self, well: usize) -> Result<bool, WyldcardError> {
+    check_well(well)?;
+    Ok(self.wells.lock().unwrap()[well].is_some())
+  }
+
+  fn read_memory_at(&self, well: usize, address: usize, buffer: &mut [u8]) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    check_memory_range(address, buffer.len())?;
+
+    let wells = self.wells.lock().unwrap();
+    let card = wells[well].as_ref().ok_or(WyldcardError::CardNotPresent)?;
+
+    buffer.copy_from_slice(&card.memory[address..address + buffer.len()]);
+    Ok(())
+  }
+
+  fn write_memory_at(&self, well: usize, address: usize, buffer: &mut [u8]) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    check_memory_range(address, buffer.len())?;
+
+    let mut wells = self.wells.lock().unwrap();
+    let card = wells[well].as_mut().ok_or(WyldcardError::CardNotPresent)?;
+
+    card.memory[address..address + buffer.len()].copy_from_slice(buffer);
+    Ok(())
+  }
+
+  // switches are identified by well number and then switch 'a', 'b', or 'c'
+  fn set_switch_callback(&self, well: usize, switch: char, callback: impl FnMut(Level) + Send + 'static) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    let mut switch = self.switches.get(&(well, switch)).ok_or(WyldcardError::InvalidArgument(format!("Invalid switch '{}'", switch)))?.lock().unwrap();
+    switch.callback = Some(Box::new(callback));
+
+    Ok(())
+  }
+
+  // simulated displays are never busy
+  fn set_busy_timeout(&self, _timeout: Duration) {}
+
+  fn set_panel_type(&self, well: usize, panel_type: Option<PanelType>) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.panel_types.lock().unwrap()[well] = panel_type;
+    Ok(())
+  }
+
+  fn panel_type(&self, well: usize) -> Result<PanelType, WyldcardError> {
+    check_well(well)?;
+    let panel_type = self.panel_types.lock().unwrap()[well];
+    Ok(panel_type
+      .or_else(|| self.detect_panel_type(well))
+      .unwrap_or(PanelType::GDEY029T94))
+  }
+
+  fn set_orientation(&self, well: usize, orientation: Orientation) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.orientations.lock().unwrap()[well] = orientation;
+    Ok(())
+  }
+
+  fn orientation(&self, well: usize) -> Result<Orientation, WyldcardError> {
+    check_well(well)?;
+    Ok(self.orientations.lock().unwrap()[well])
+  }
+
+  fn set_lut(&self, well: usize, lut: Option<Lut>) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.luts.lock().unwrap()[well] = lut;
+    Ok(())
+  }
+
+  fn lut(&self, well: usize) -> Result<Option<Lut>, WyldcardError> {
+    check_well(well)?;
+    Ok(self.luts.lock().unwrap()[well].clone())
+  }
+
+  fn set_temperature_luts(&self, well: usize, luts: Vec<(f32, Lut)>) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.temperature_luts.lock().unwrap()[well] = luts;
+    Ok(())
+  }
+
+  fn temperature_luts(&self, well: usize) -> Result<Vec<(f32, Lut)>, WyldcardError> {
+    check_well(well)?;
+    Ok(self.temperature_luts.lock().unwrap()[well].clone())
+  }
+
+  // only the GDEY029T94 can report its temperature, so simulated cards do too when they're that panel
+  fn panel_temperature(&self, well: usize) -> Result<Option<f32>, WyldcardError> {
+    if self.panel_type(well)? != PanelType::GDEY029T94 {
+      return Ok(None);
+    }
+    Ok(Some(self.wells.lock().unwrap()[well].as_ref().ok_or(WyldcardError::CardNotPresent)?.temperature))
+  }
+
+  // simulated cards remember their image, so unlike a real plinth this is whatever the card in the well shows
+  fn current_image(&self, well: usize) -> Result<Option<Vec<u8>>, WyldcardError> {
+    check_well(well)?;
+    Ok(self.wells.lock().unwrap()[well].as_ref().map(|card| card.image.clone()))
+  }
+
+  fn set_persist_image_hashes(&self, persist: bool) {
+    *self.persist_image_hashes.lock().unwrap() = persist;
+  }
+
+  fn persisting_image_hashes(&self) -> bool {
+    *self.persist_image_hashes.lock().unwrap()
+  }
+}
+
+impl SimulatedPlinth {
+  // starts with a blank card sitting in each of the four wells
+  pub fn new() -> SimulatedPlinth {
+    let mut switches = HashMap::new();
+    for well in 0..4 {
+      for switch in ['a', 'b', 'c'] {
+        switches.insert((well, switch), Mutex::new(SimulatedSwitch { level: Level::High, callback: None }));
+      }
+    }
+
+    SimulatedPlinth {
+      wells: Mutex::new([
+        Some(SimulatedCard::new()),
+        Some(SimulatedCard::new()),
+        Some(SimulatedCard::new()),
+        Some(SimulatedCard::new()),
       ]),
+      switches,
+      panel_types: Mutex::new([None; 4]),
+      orientations: Mutex::new([Orientation::default(); 4]),
+      luts: Mutex::new([None, None, None, None]),
+      temperature_luts: Mutex::new(Default::default()),
+      persist_image_hashes: Mutex::new(false),
+    }
+  }
+
+  // places a card in the well, returning whichever card was already sitting there
+  pub fn insert_card(&self, well: usize, card: SimulatedCard) -> Result<Option<SimulatedCard>, WyldcardError> {
+    check_well(well)?;
+    Ok(self.wells.lock().unwrap()[well].replace(card))
+  }
+
+  pub fn remove_card(&self, well: usize) -> Result<Option<SimulatedCard>, WyldcardError> {
+    check_well(well)?;
+    Ok(self.wells.lock().unwrap()[well].take())
+  }
+
+  // warms or cools the card in the well, as seen by `panel_temperature()`
+  pub fn set_card_temperature(&self, well: usize, temperature: f32) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    self.wells.lock().unwrap()[well].as_mut().ok_or(WyldcardError::CardNotPresent)?.temperature = temperature;
+    Ok(())
+  }
+
+  // like a real plinth, stores the hash of a card's new image on it if that's been asked for
+  fn image_shown(&self, well: usize, hash: u64) {
+    try_store_image_hash(self, well, hash);
+  }
+
+  // switches are pulled up, so pressing one drives it low. Like the real interrupts, which trigger on a
+  // falling edge, the callback only fires when a released switch is pressed
+  pub fn press_switch(&self, well: usize, switch: char) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    let mut switch = self.switches.get(&(well, switch)).ok_or(WyldcardError::InvalidArgument(format!("Invalid switch '{}'", switch)))?.lock().unwrap();
+
+    if switch.level == Level::Low {
+      return Ok(());
+    }
+    switch.level = Level::Low;
+
+    if let Some(callback) = switch.callback.as_mut() {
+      callback(Level::Low);
+    }
+
+    Ok(())
+  }
+
+  pub fn release_switch(&self, well: usize, switch: char) -> Result<(), WyldcardError> {
+    check_well(well)?;
+    let mut switch = self.switches.get(&(well, switch)).ok_or(WyldcardError::InvalidArgument(format!("Invalid switch '{}'", switch)))?.lock().unwrap();
+    switch.level = Level::High;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::RefCell;
+  use std::convert::Infallible;
+  use std::rc::Rc;
+
+  // an M95320 on the other end of the bus. Like the real chip, writes wrap around within their page
+  struct FakeMemory {
+    memory: Vec<u8>,
+    selected: bool,
+    write_enabled: bool,
+    busy_polls: usize, // how many more status reads the current write stays busy for
+    busy_per_write: usize,
+    pulled_out: bool, // writes are lost and reads come back as all ones
+    page_writes: Vec<(usize, usize)>, // (address, length)
+  }
+
+  struct FakeSpi(Rc<RefCell<FakeMemory>>);
+  struct FakeChipSelect(Rc<RefCell<FakeMemory>>);
+
+  impl Transfer<u8> for FakeSpi {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+      let mut chip = self.0.borrow_mut();
+      assert!(chip.selected, "the memory chip wasn't selected");
+      let address = || u16::from_be_bytes([words[1], words[2]]) as usize;
+
+      match words[0] {
+        MEMORY_WRITE_ENABLE => chip.write_enabled = true,
+        MEMORY_READ_STATUS => {
+          let busy = chip.busy_polls > 0;
+          chip.busy_polls = chip.busy_polls.saturating_sub(1);
+          words[1] = if chip.pulled_out { 0xFF } else if busy { 0b11 } else { 0 };
+        },
+        MEMORY_WRITE => {
+          assert!(chip.write_enabled, "wrote without enabling writes");
+          let (address, data) = (address(), words[3..].to_vec());
+          chip.page_writes.push((address, data.len()));
+          chip.write_enabled = false;
+          chip.busy_polls = chip.busy_per_write;
+          if !chip.pulled_out {
+            let page = address - address % MEMORY_PAGE_SIZE;
+            for (offset, byte) in data.into_iter().enumerate() {
+              chip.memory[page + (address + offset) % MEMORY_PAGE_SIZE] = byte;
+            }
+          }
+        },
+        MEMORY_READ => {
+          let address = address();
+          for (offset, word) in words[3..].iter_mut().enumerate() {
+            *word = if chip.pulled_out { 0xFF } else { chip.memory[address + offset] };
+          }
+        },
+        instruction => panic!("unexpected instruction {:#04x}", instruction),
+      }
+      Ok(words)
     }
   }
-}
\ No newline at end of file
+
+  impl embedded_hal::digital::v2::OutputPin for FakeChipSelect {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+      self.0.borrow_mut().selected = true;
+      Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+      self.0.borrow_mut().selected = false;
+      Ok(())
+    }
+  }
+
+  fn fake_memory() -> (Rc<RefCell<FakeMemory>>, FakeSpi, FakeChipSelect) {
+    let chip = Rc::new(RefCell::new(FakeMemory {
+      memory: vec![0xFF; CARD_MEMORY_SIZE],
+      selected: false,
+      write_enabled: false,
+      busy_polls: 0,
+      busy_per_write: 2,
+      pulled_out: false,
+      page_writes: Vec::new(),
+    }));
+    (chip.clone(), FakeSpi(chip.clone()), FakeChipSelect(chip))
+  }
+
+  #[test]
+  fn short_writes_in_the_middle_of_a_page() {
+    let (chip, mut spi, mut chip_select) = fake_memory();
+    write_memory_verified(&mut spi, &mut chip_select, 100, &[1, 2, 3]).unwrap();
+
+    let chip = chip.borrow();
+    assert_eq!(&chip.memory[99..104], &[0xFF, 1, 2, 3, 0xFF]);
+    assert_eq!(chip.page_writes, vec![(100, 3)]);
+  }
+
+  #[test]
+  fn writes_up_to_the_end_of_memory() {
+    let (chip, mut spi, mut chip_select) = fake_memory();
+    let hash = 0x0123_4567_89ab_cdef_u64.to_le_bytes();
+    write_memory_verified(&mut spi, &mut chip_select, IMAGE_HASH_ADDRESS, &hash).unwrap();
+    write_memory_verified(&mut spi, &mut chip_select, PANEL_TYPE_ADDRESS, &[0x94]).unwrap();
+
+    let chip = chip.borrow();
+    assert_eq!(&chip.memory[IMAGE_HASH_ADDRESS..PANEL_TYPE_ADDRESS], &hash);
+    assert_eq!(chip.memory[PANEL_TYPE_ADDRESS], 0x94);
+  }
+
+  #[test]
+  fn unaligned_writes_are_split_on_page_boundaries() {
+    let (chip, mut spi, mut chip_select) = fake_memory();
+    let data: Vec<u8> = (0..80).collect();
+    write_memory_verified(&mut spi, &mut chip_select, 20, &data).unwrap();
+
+    let chip = chip.borrow();
+    assert_eq!(&chip.memory[20..100], data.as_slice());
+    assert_eq!(chip.page_writes, vec![(20, 12), (32, 32), (64, 32), (96, 4)]);
+  }
+
+  #[test]
+  fn a_chip_which_stays_busy_times_out() {
+    let (chip, mut spi, mut chip_select) = fake_memory();
+    chip.borrow_mut().busy_per_write = usize::MAX;
+
+    match write_memory_verified(&mut spi, &mut chip_select, 40, &[1; 40]) {
+      Err(WyldcardError::MemoryWriteFailed { address, .. }) => assert_eq!(address, 40),
+      other => panic!("expected MemoryWriteFailed, got {:?}", other),
+    }
+    assert_eq!(chip.borrow().page_writes.len(), 1);
+  }
+
+  #[test]
+  fn a_pulled_out_card_fails_at_the_first_page() {
+    let (chip, mut spi, mut chip_select) = fake_memory();
+    chip.borrow_mut().pulled_out = true;
+
+    match write_memory_verified(&mut spi, &mut chip_select, 64, &[0; 8]) {
+      Err(WyldcardError::MemoryWriteFailed { address, .. }) => assert_eq!(address, 64),
+      other => panic!("expected MemoryWriteFailed, got {:?}", other),
+    }
+  }
+
+  fn black_image() -> Vec<u8> {
+    vec![0; SIMULATED_PANEL.image_size(ColorResolution::FourColorGrayscale)]
+  }
+
+  fn pixel(image: &[u8], x: usize, y: usize) -> u8 {
+    let index = y * 128 + x;
+    (image[index / 4] >> (6 - 2 * (index % 4))) & 0b11
+  }
+
+  #[test]
+  fn partial_windows_which_dont_fit_are_rejected() {
+    let plinth = SimulatedPlinth::new();
+    let windows = [
+      Window { x: 120, y: 0, width: 16, height: 8 },
+      Window { x: 0, y: 290, width: 8, height: 10 },
+      Window { x: 0, y: 0, width: 0, height: 8 },
+      Window { x: u16::MAX, y: 0, width: 2, height: 8 }, // would wrap around in u16
+    ];
+    for window in windows {
+      assert!(matches!(plinth.display_partial(0, black_image(), window), Err(WyldcardError::InvalidArgument(_))), "{:?}", window);
+    }
+    assert_eq!(plinth.current_image(0).unwrap(), Some(SimulatedCard::new().image));
+  }
+
+  #[test]
+  fn partial_windows_are_widened_to_whole_bytes() {
+    let plinth = SimulatedPlinth::new();
+    plinth.display_partial(0, black_image(), Window { x: 3, y: 10, width: 2, height: 1 }).unwrap();
+
+    let shown = plinth.current_image(0).unwrap().unwrap();
+    assert!((0..8).all(|x| pixel(&shown, x, 10) == 0b00));
+    assert_eq!(pixel(&shown, 8, 10), 0b11);
+    assert_eq!(pixel(&shown, 3, 9), 0b11);
+    assert_eq!(pixel(&shown, 3, 11), 0b11);
+  }
+
+  fn card_with_image_hash(byte: u8) -> SimulatedCard {
+    let mut card = SimulatedCard::new();
+    card.memory[IMAGE_HASH_ADDRESS..PANEL_TYPE_ADDRESS].fill(byte);
+    card
+  }
+
+  #[test]
+  fn a_swapped_card_forgets_the_image_shown_on_the_last_one() {
+    let plinth = SimulatedPlinth::new();
+    plinth.insert_card(0, card_with_image_hash(1)).unwrap();
+    let shown = ShownImages::default();
+    shown.record(0, black_image(), card_fingerprint(&plinth, 0).unwrap());
+    assert_eq!(shown.image_on(&plinth, 0), Some(black_image()));
+
+    let first_card = plinth.insert_card(0, card_with_image_hash(2)).unwrap().unwrap();
+    assert_eq!(shown.image_on(&plinth, 0), None);
+
+    // it may have been drawn on somewhere else while it was out
+    plinth.insert_card(0, first_card).unwrap();
+    assert_eq!(shown.image_on(&plinth, 0), None);
+  }
+
+  #[test]
+  fn a_pulled_out_card_forgets_its_image() {
+    let plinth = SimulatedPlinth::new();
+    let shown = ShownImages::default();
+    shown.record(0, black_image(), card_fingerprint(&plinth, 0).unwrap());
+
+    plinth.remove_card(0).unwrap();
+    assert_eq!(shown.image_on(&plinth, 0), None);
+    plinth.insert_card(0, SimulatedCard::new()).unwrap();
+    assert_eq!(shown.image_on(&plinth, 0), None);
+  }
+
+  #[test]
+  fn saving_data_on_a_card_keeps_its_image() {
+    let plinth = SimulatedPlinth::new();
+    let shown = ShownImages::default();
+    shown.record(0, black_image(), card_fingerprint(&plinth, 0).unwrap());
+
+    crate::storage::write_record(&plinth, 0, b"score: 10").unwrap();
+    crate::storage::write_record(&plinth, 0, b"score: 20").unwrap();
+    assert_eq!(shown.image_on(&plinth, 0), Some(black_image()));
+  }
+
+  #[test]
+  fn a_card_given_another_image_hash_forgets_its_image() {
+    let plinth = SimulatedPlinth::new();
+    let shown = ShownImages::default();
+    shown.record(0, black_image(), card_fingerprint(&plinth, 0).unwrap());
+
+    plinth.store_image_hash(0, 42).unwrap();
+    assert_eq!(shown.image_on(&plinth, 0), None);
+  }
+
+  #[test]
+  fn displays_only_hold_the_bus_while_their_panel_is_selected() {
+    use crate::epd::fakes::{ FakePin, FakeSpi as FakeDisplaySpi };
+
+    let bus = Mutex::new(SpiBus { spi: FakeDisplaySpi::default(), data_or_command: FakePin::default() });
+    let mut pins = WellPins { reset: FakePin::default(), busy: FakePin::reading(false), epd_chip_select: FakePin::default() };
+    let mut display = open_display(PanelType::GDEY029T94, &mut pins, &bus, DEFAULT_BUSY_TIMEOUT).unwrap();
+    display.start_epd().unwrap();
+    display.begin_update().unwrap();
+
+    // the panel is refreshing now, and another well or card can use the bus
+    let free = bus.try_lock().expect("the bus was still locked");
+    assert!(!free.spi.sent.borrow().is_empty());
+  }
+}
@@ -0,0 +1,255 @@
+use crate::error::WyldcardError;
+use crate::plinth::{ Plinth, IMAGE_HASH_ADDRESS };
+
+// cards keep their data as records in two slots, A at the start of memory and B straight after it. A write always
+// goes to the slot without the newest record, so if the card is pulled out partway through, the other slot still
+// holds the last record that was written in full
+const SLOT_SIZE: usize = IMAGE_HASH_ADDRESS / 2;
+const SLOT_ADDRESSES: [usize; 2] = [0, SLOT_SIZE];
+
+// each slot starts with a header: a sequence number which goes up with every write, the length of the data, and a
+// CRC-32 of the sequence number, length and data, all little endian
+const HEADER_SIZE: usize = 4 + 2 + 4;
+pub const MAX_RECORD_SIZE: usize = SLOT_SIZE - HEADER_SIZE;
+
+struct Record {
+  sequence: u32,
+  data: Vec<u8>,
+}
+
+// the last record written in full to the card in the well, or None if there isn't one. A card saved on before records
+// existed gives its JSON instead, so whatever reads and rewrites the record carries the old data over
+pub fn read_record<P: Plinth>(plinth: &P, well: usize) -> Result<Option<Vec<u8>>, WyldcardError> {
+  match newest_record(plinth, well)? {
+    Some((_, record)) => Ok(Some(record.data)),
+    None => read_legacy_data(plinth, well),
+  }
+}
+
+// writes over the older of the two slots. Once this returns, `read_record()` gives back `data`
+pub fn write_record<P: Plinth>(plinth: &P, well: usize, data: &[u8]) -> Result<(), WyldcardError> {
+  // checked before the length, as old JSON which is too long to keep gets carried over into whatever's saved next
+  let (slot, sequence) = match newest_record(plinth, well)? {
+    Some((slot, record)) => (1 - slot, record.sequence.wrapping_add(1)),
+    None => match read_legacy_data(plinth, well)? {
+      // JSON which runs on into slot B would lose its end to the record, and a card pulled out partway through would
+      // be left with neither, so it's only ever thrown away on purpose with `discard_legacy_data()`
+      Some(json) if json.len() > SLOT_SIZE => return Err(WyldcardError::LegacyDataTooLong(json.len())),
+      // the old JSON starts at the beginning of memory, so write slot B first and leave it readable until this is done
+      Some(_) => (1, 0),
+      None => (0, 0),
+    },
+  };
+
+  if data.len() > MAX_RECORD_SIZE {
+    return Err(WyldcardError::InvalidArgument(format!("there's only room for {} bytes on the card, this needs {}", MAX_RECORD_SIZE, data.len())));
+  }
+
+  let mut slot_contents = Vec::with_capacity(HEADER_SIZE + data.len());
+  slot_contents.extend_from_slice(&sequence.to_le_bytes());
+  slot_contents.extend_from_slice(&(data.len() as u16).to_le_bytes());
+  slot_contents.extend_from_slice(&record_crc(sequence, data).to_le_bytes());
+  slot_contents.extend_from_slice(data);
+
+  plinth.write_memory_at(well, SLOT_ADDRESSES[slot], &mut slot_contents)
+}
+
+// stops JSON saved by an older version being read, so records can be written over it. Cards which already have a
+// record are left alone, as their JSON is either gone or behind the record anyway
+pub fn discard_legacy_data<P: Plinth>(plinth: &P, well: usize) -> Result<(), WyldcardError> {
+  if newest_record(plinth, well)?.is_some() {
+    return Ok(());
+  }
+  // without its opening brace it's no longer taken for JSON
+  plinth.write_memory_at(well, 0, &mut [b' '])
+}
+
+// which slot holds the newest intact record, and the record. Sequence numbers wrap around, so newer means ahead
+// by less than half the range
+fn newest_record<P: Plinth>(plinth: &P, well: usize) -> Result<Option<(usize, Record)>, WyldcardError> {
+  let mut newest: Option<(usize, Record)> = None;
+  for (slot, address) in SLOT_ADDRESSES.iter().enumerate() {
+    let Some(record) = read_slot(plinth, well, *address)? else {
+      continue;
+    };
+    let is_newer = match &newest {
+      Some((_, current)) => (record.sequence.wrapping_sub(current.sequence) as i32) > 0,
+      None => true,
+    };
+    if is_newer {
+      newest = Some((slot, record));
+    }
+  }
+  Ok(newest)
+}
+
+// None if the slot has never been written, or its write didn't finish
+fn read_slot<P: Plinth>(plinth: &P, well: usize, address: usize) -> Result<Option<Record>, WyldcardError> {
+  let mut slot = vec![0; SLOT_SIZE];
+  plinth.read_memory_at(well, address, &mut slot)?;
+
+  let sequence = u32::from_le_bytes(slot[0..4].try_into().unwrap());
+  let length = u16::from_le_bytes(slot[4..6].try_into().unwrap()) as usize;
+  let crc = u32::from_le_bytes(slot[6..10].try_into().unwrap());
+  // erased memory reads as all ones, which is far too long
+  if length > MAX_RECORD_SIZE {
+    return Ok(None);
+  }
+
+  let data = &slot[HEADER_SIZE..HEADER_SIZE + length];
+  if record_crc(sequence, data) != crc {
+    return Ok(None);
+  }
+  Ok(Some(Record { sequence, data: data.to_vec() }))
+}
+
+// older versions saved an object as JSON across the whole of memory, padded with spaces. The panel type and image
+// hash have been written over the end of it since, so only what comes before them is looked at
+fn read_legacy_data<P: Plinth>(plinth: &P, well: usize) -> Result<Option<Vec<u8>>, WyldcardError> {
+  let mut memory = vec![0; IMAGE_HASH_ADDRESS];
+  plinth.read_memory_at(well, 0, &mut memory)?;
+
+  // the first record goes in slot B, so if the card was pulled out partway through it, JSON short enough to be kept
+  // is still whole in slot A, with the start of the record after it rather than spaces
+  Ok(json_object(&memory).or_else(|| json_object(&memory[..SLOT_SIZE])))
+}
+
+// the bytes up to the trailing spaces, if they look like a JSON object
+fn json_object(memory: &[u8]) -> Option<Vec<u8>> {
+  let length = memory.iter().rposition(|byte| *byte != b' ').map_or(0, |last| last + 1);
+  let json = &memory[..length];
+  let is_json_object = json.first() == Some(&b'{') && json.last() == Some(&b'}') && std::str::from_utf8(json).is_ok();
+  is_json_object.then(|| json.to_vec())
+}
+
+fn record_crc(sequence: u32, data: &[u8]) -> u32 {
+  let length = (data.len() as u16).to_le_bytes();
+  crc32(sequence.to_le_bytes().iter().chain(length.iter()).chain(data.iter()))
+}
+
+// the usual CRC-32, as used by zip and png, worked out a bit at a time. Records are small, so a table isn't worth it
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+  !bytes.fold(0xFFFF_FFFF, |crc, byte| {
+    (0..8).fold(crc ^ *byte as u32, |crc, _| (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg()))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::plinth::{ SimulatedCard, SimulatedPlinth };
+
+  const LEGACY_JSON: &[u8] = br#"{"id":"abc","name":"chuckwudi"}"#;
+
+  // a card as the old `storeData()` left it, with the panel type and image hash written since
+  fn plinth_with_legacy_card() -> SimulatedPlinth {
+    let mut card = SimulatedCard::new();
+    card.memory.fill(b' ');
+    card.memory[..LEGACY_JSON.len()].copy_from_slice(LEGACY_JSON);
+    card.memory[IMAGE_HASH_ADDRESS..].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 0]);
+
+    let plinth = SimulatedPlinth::new();
+    plinth.insert_card(0, card).unwrap();
+    plinth
+  }
+
+  #[test]
+  fn records_round_trip() {
+    let plinth = SimulatedPlinth::new();
+    plinth.insert_card(0, SimulatedCard::new()).unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), None);
+
+    write_record(&plinth, 0, b"first").unwrap();
+    write_record(&plinth, 0, b"second").unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(b"second".to_vec()));
+  }
+
+  #[test]
+  fn a_legacy_card_reads_as_its_json() {
+    let plinth = plinth_with_legacy_card();
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(LEGACY_JSON.to_vec()));
+  }
+
+  #[test]
+  fn the_first_record_leaves_the_legacy_json_alone() {
+    let plinth = plinth_with_legacy_card();
+    write_record(&plinth, 0, b"new").unwrap();
+
+    let mut start = vec![0; LEGACY_JSON.len()];
+    plinth.read_memory_at(0, 0, &mut start).unwrap();
+    assert_eq!(start, LEGACY_JSON);
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(b"new".to_vec()));
+
+    write_record(&plinth, 0, b"newer").unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(b"newer".to_vec()));
+  }
+
+  // JSON which runs past the end of slot A and on into slot B
+  fn plinth_with_long_legacy_card() -> (SimulatedPlinth, Vec<u8>) {
+    let mut json = br#"{"log":""#.to_vec();
+    json.resize(SLOT_SIZE + 500, b'x');
+    json.extend_from_slice(br#""}"#);
+
+    let mut card = SimulatedCard::new();
+    card.memory.fill(b' ');
+    card.memory[..json.len()].copy_from_slice(&json);
+
+    let plinth = SimulatedPlinth::new();
+    plinth.insert_card(0, card).unwrap();
+    (plinth, json)
+  }
+
+  #[test]
+  fn legacy_json_running_into_slot_b_isnt_written_over() {
+    let (plinth, json) = plinth_with_long_legacy_card();
+
+    let error = write_record(&plinth, 0, b"new").unwrap_err();
+    assert!(matches!(error, WyldcardError::LegacyDataTooLong(length) if length == json.len()));
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(json));
+  }
+
+  #[test]
+  fn discarded_legacy_json_makes_room_for_records() {
+    let (plinth, _) = plinth_with_long_legacy_card();
+    discard_legacy_data(&plinth, 0).unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), None);
+
+    // pulled out partway through the first record: half of it landed, over the rest of the old JSON
+    let mut torn = [0; HEADER_SIZE + 100];
+    torn[..6].copy_from_slice(&[0, 0, 0, 0, 100, 0]);
+    plinth.write_memory_at(0, 0, &mut torn[..HEADER_SIZE + 50]).unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), None);
+
+    write_record(&plinth, 0, b"new").unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(b"new".to_vec()));
+    write_record(&plinth, 0, b"newer").unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(b"newer".to_vec()));
+  }
+
+  #[test]
+  fn a_torn_first_record_leaves_the_legacy_json_to_read() {
+    let plinth = plinth_with_legacy_card();
+
+    let mut torn = [7; HEADER_SIZE + 50];
+    plinth.write_memory_at(0, SLOT_SIZE, &mut torn).unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(LEGACY_JSON.to_vec()));
+  }
+
+  #[test]
+  fn discarding_leaves_records_alone() {
+    let plinth = plinth_with_legacy_card();
+    write_record(&plinth, 0, b"new").unwrap();
+
+    discard_legacy_data(&plinth, 0).unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), Some(b"new".to_vec()));
+  }
+
+  #[test]
+  fn blank_cards_have_no_legacy_json() {
+    let plinth = SimulatedPlinth::new();
+    let mut card = SimulatedCard::new();
+    card.memory.fill(0);
+    plinth.insert_card(0, card).unwrap();
+    assert_eq!(read_record(&plinth, 0).unwrap(), None);
+  }
+}
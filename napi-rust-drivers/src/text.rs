@@ -0,0 +1,234 @@
+use embedded_graphics::mono_font::{ ascii, MonoFont, MonoTextStyle };
+use embedded_graphics::pixelcolor::Gray2;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{ Baseline, Text };
+
+// the bundled fonts are the X11 "fixed" bitmap fonts which ship with embedded-graphics
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FontSize {
+  Tiny, // 4x6 pixels a letter
+  Small, // 6x13
+  Medium, // 7x14
+  Large, // 9x18
+  ExtraLarge, // 10x20
+}
+
+impl FontSize {
+  // tiny and extra large have no bold face, so they're always regular
+  pub fn font(&self, bold: bool) -> &'static MonoFont<'static> {
+    match (self, bold) {
+      (FontSize::Tiny, _) => &ascii::FONT_4X6,
+      (FontSize::Small, false) => &ascii::FONT_6X13,
+      (FontSize::Small, true) => &ascii::FONT_6X13_BOLD,
+      (FontSize::Medium, false) => &ascii::FONT_7X14,
+      (FontSize::Medium, true) => &ascii::FONT_7X14_BOLD,
+      (FontSize::Large, false) => &ascii::FONT_9X18,
+      (FontSize::Large, true) => &ascii::FONT_9X18_BOLD,
+      (FontSize::ExtraLarge, _) => &ascii::FONT_10X20,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HorizontalAlignment {
+  Left,
+  Center,
+  Right,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VerticalAlignment {
+  Top,
+  Middle,
+  Bottom,
+}
+
+#[derive(Copy, Clone)]
+pub struct TextBoxStyle {
+  pub font: &'static MonoFont<'static>,
+  pub color: Gray2,
+  pub horizontal_alignment: HorizontalAlignment,
+  pub vertical_alignment: VerticalAlignment,
+  pub line_spacing: u32, // extra pixels between lines
+}
+
+// draws text inside `area`, word wrapping it to fit the width and starting a new line at each '\n'.
+// Lines which don't fit in the height are left off. Returns whether all of the text fit
+pub fn draw_text_box<D>(target: &mut D, text: &str, area: Rectangle, style: &TextBoxStyle) -> Result<bool, D::Error>
+  where D: DrawTarget<Color = Gray2> {
+  let font = style.font;
+  let advance = font.character_size.width + font.character_spacing;
+  let line_height = font.character_size.height + style.line_spacing;
+
+  // the last letter on a line doesn't need spacing after it
+  let columns = ((area.size.width + font.character_spacing) / advance).max(1) as usize;
+  let rows = ((area.size.height + style.line_spacing) / line_height) as usize;
+
+  let mut lines = wrap(text, columns);
+  let fits = lines.len() <= rows;
+  lines.truncate(rows);
+
+  let text_height = (lines.len() as u32 * line_height).saturating_sub(style.line_spacing);
+  let top = area.top_left.y + match style.vertical_alignment {
+    VerticalAlignment::Top => 0,
+    VerticalAlignment::Middle => area.size.height.saturating_sub(text_height) / 2,
+    VerticalAlignment::Bottom => area.size.height.saturating_sub(text_height),
+  } as i32;
+
+  let character_style = MonoTextStyle::new(font, style.color);
+  for (row, line) in lines.iter().enumerate() {
+    let line_width = (line.chars().count() as u32 * advance).saturating_sub(font.character_spacing);
+    let left = area.top_left.x + match style.horizontal_alignment {
+      HorizontalAlignment::Left => 0,
+      HorizontalAlignment::Center => area.size.width.saturating_sub(line_width) / 2,
+      HorizontalAlignment::Right => area.size.width.saturating_sub(line_width),
+    } as i32;
+
+    Text::with_baseline(line, Point::new(left, top + (row as u32 * line_height) as i32), character_style, Baseline::Top)
+      .draw(target)?;
+  }
+
+  Ok(fits)
+}
+
+// breaks text into lines of at most `columns` letters, between words where it can. Words longer than a whole
+// line are split wherever they reach the edge
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+  let mut lines = Vec::new();
+
+  for paragraph in text.split('\n') {
+    let mut line = String::new();
+    let mut line_length = 0;
+
+    for word in paragraph.split_whitespace() {
+      let mut word: Vec<char> = word.chars().collect();
+      while word.len() > columns {
+        if line_length > 0 {
+          lines.push(std::mem::take(&mut line));
+          line_length = 0;
+        }
+        lines.push(word.drain(..columns).collect());
+      }
+      if word.is_empty() {
+        continue;
+      }
+
+      if line_length > 0 && line_length + 1 + word.len() > columns {
+        lines.push(std::mem::take(&mut line));
+        line_length = 0;
+      }
+      if line_length > 0 {
+        line.push(' ');
+        line_length += 1;
+      }
+      line_length += word.len();
+      line.extend(word);
+    }
+
+    lines.push(line);
+  }
+
+  lines
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::framebuffer::Framebuffer;
+
+  // 6x13 letters with no spacing, so a 60x40 box has 10 columns and 3 rows
+  fn style(horizontal_alignment: HorizontalAlignment, vertical_alignment: VerticalAlignment) -> TextBoxStyle {
+    TextBoxStyle { font: FontSize::Small.font(false), color: Gray2::BLACK, horizontal_alignment, vertical_alignment, line_spacing: 0 }
+  }
+
+  fn area() -> Rectangle {
+    Rectangle::new(Point::new(10, 20), Size::new(60, 40))
+  }
+
+  // the top left and bottom right of everything drawn that isn't white
+  fn inked(framebuffer: &Framebuffer) -> Option<(Point, Point)> {
+    let mut inked: Option<(Point, Point)> = None;
+    for y in 0..296 {
+      for x in 0..128 {
+        if framebuffer.pixel(x, y) != Gray2::WHITE {
+          let (top_left, bottom_right) = inked.get_or_insert((Point::new(x, y), Point::new(x, y)));
+          *top_left = top_left.component_min(Point::new(x, y));
+          *bottom_right = bottom_right.component_max(Point::new(x, y));
+        }
+      }
+    }
+    inked
+  }
+
+  fn draw(text: &str, style: &TextBoxStyle) -> (bool, Option<(Point, Point)>) {
+    let mut framebuffer = Framebuffer::new();
+    let fits = draw_text_box(&mut framebuffer, text, area(), style).unwrap();
+    (fits, inked(&framebuffer))
+  }
+
+  #[test]
+  fn lines_break_between_words() {
+    assert_eq!(wrap("the quick brown fox jumps", 10), ["the quick", "brown fox", "jumps"]);
+    assert_eq!(wrap("exactly ten", 11), ["exactly ten"]);
+    assert_eq!(wrap("spaces   are    squashed", 30), ["spaces are squashed"]);
+  }
+
+  #[test]
+  fn words_longer_than_a_line_are_split_at_the_edge() {
+    assert_eq!(wrap("abcdefghijklmnop qr", 5), ["abcde", "fghij", "klmno", "p qr"]);
+    assert_eq!(wrap("hi abcdefgh", 4), ["hi", "abcd", "efgh"]);
+  }
+
+  #[test]
+  fn newlines_start_new_lines_and_blank_lines_are_kept() {
+    assert_eq!(wrap("one\n\ntwo three", 5), ["one", "", "two", "three"]);
+    assert_eq!(wrap("", 5), [""]);
+  }
+
+  #[test]
+  fn text_which_fits_is_all_drawn_inside_the_box() {
+    let (fits, inked) = draw("hello wyldcard world", &style(HorizontalAlignment::Left, VerticalAlignment::Top));
+    assert!(fits);
+    let (top_left, bottom_right) = inked.unwrap();
+    assert!(area().contains(top_left) && area().contains(bottom_right));
+    // three lines
+    assert!(bottom_right.y >= area().top_left.y + 26);
+  }
+
+  #[test]
+  fn text_which_doesnt_fit_is_cut_off_at_the_bottom_of_the_box() {
+    let (fits, inked) = draw("one\ntwo\nthree\nfour\nfive", &style(HorizontalAlignment::Left, VerticalAlignment::Top));
+    assert!(!fits);
+    let (_, bottom_right) = inked.unwrap();
+    assert!(area().contains(bottom_right));
+
+    let (fits, _) = draw("one\ntwo\nthree", &style(HorizontalAlignment::Left, VerticalAlignment::Top));
+    assert!(fits);
+  }
+
+  #[test]
+  fn lines_are_aligned_across_the_box() {
+    // "MM" is 12 pixels wide in a 60 pixel box
+    let (_, left) = draw("MM", &style(HorizontalAlignment::Left, VerticalAlignment::Top));
+    let (_, center) = draw("MM", &style(HorizontalAlignment::Center, VerticalAlignment::Top));
+    let (_, right) = draw("MM", &style(HorizontalAlignment::Right, VerticalAlignment::Top));
+    let (left, center, right) = (left.unwrap().0, center.unwrap().0, right.unwrap().0);
+    assert!(left.x >= area().top_left.x && left.x < area().top_left.x + 6);
+    assert_eq!(center.x - left.x, 24);
+    assert_eq!(right.x - left.x, 48);
+    assert_eq!((left.y, center.y), (right.y, right.y));
+  }
+
+  #[test]
+  fn text_is_aligned_down_the_box() {
+    // one 13 pixel line in a 40 pixel box
+    let (_, top) = draw("MM", &style(HorizontalAlignment::Left, VerticalAlignment::Top));
+    let (_, middle) = draw("MM", &style(HorizontalAlignment::Left, VerticalAlignment::Middle));
+    let (_, bottom) = draw("MM", &style(HorizontalAlignment::Left, VerticalAlignment::Bottom));
+    let (top, middle, bottom) = (top.unwrap().0, middle.unwrap().0, bottom.unwrap().0);
+    assert_eq!(middle.y - top.y, 13);
+    assert_eq!(bottom.y - top.y, 27);
+    assert_eq!((top.x, middle.x), (bottom.x, bottom.x));
+  }
+}